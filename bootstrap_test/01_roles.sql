@@ -0,0 +1,2 @@
+CREATE ROLE app LOGIN PASSWORD 'app';
+CREATE SCHEMA app AUTHORIZATION app;
@@ -0,0 +1 @@
+CREATE DATABASE app OWNER app;
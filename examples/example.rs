@@ -1,3 +1,4 @@
+use std::collections::HashMap;
 use std::error::Error;
 use std::path::PathBuf;
 use std::time::Duration;
@@ -7,6 +8,7 @@ use sqlx_tokio::postgres::PgPoolOptions;
 use pg_embed::pg_access::PgAccess;
 use pg_embed::pg_enums::PgAuthMethod;
 use pg_embed::pg_fetch::{PgFetchSettings, PG_V16};
+use pg_embed::pg_types::Timeouts;
 use pg_embed::postgres::{PgEmbed, PgSettings};
 
 #[tokio::main]
@@ -19,19 +21,41 @@ async fn main() -> Result<(), Box<dyn Error>> {
         cache_dir: Some(cache_dir.clone()),
         port: 5432,
         user: "postgres".to_string(),
-        password: "password".to_string(),
+        password: "password".into(),
         // authentication method
         auth_method: PgAuthMethod::Plain,
         // If persistent is false clean up files and directories on drop, otherwise keep them
         persistent: false,
-        // duration to wait before terminating process execution
-        // pg_ctl start/stop and initdb timeout
-        // if set to None the process will not be terminated
-        timeout: Some(Duration::from_secs(15)),
+        // durations to wait before terminating process execution
+        // (pg_ctl start/stop and initdb) and server readiness
+        // if set to None the related operation will not be timed out
+        timeouts: Timeouts {
+            process: Some(Duration::from_secs(15)),
+            ..Default::default()
+        },
         // If migration sql scripts need to be run, the directory containing those scripts can be
         // specified here with `Some(PathBuf(path_to_dir)), otherwise `None` to run no migrations.
         // To enable migrations view the **Usage** section for details
         migration_dir: None,
+        // Database used to create, drop and look up databases
+        // if set to None `postgres` is used, falling back to `template1`
+        maintenance_db: None,
+        // Operating system user to run initdb and postgresql as (unix only)
+        // if set to None the processes run as the current user
+        os_user: None,
+        server_config: HashMap::new(),
+        server_tuning: Default::default(),
+        listen_addresses: Default::default(),
+        initdb_options: Default::default(),
+        tls: None,
+        stale_pid_policy: Default::default(),
+        reinit_on_mismatch: false,
+        initdb_template: false,
+        bootstrap_sql: None,
+        roles: Vec::new(),
+        databases: Vec::new(),
+        wal_archiving: None,
+        ram_backed: false,
     };
 
     // Postgresql binaries download settings
@@ -65,8 +89,8 @@ async fn main() -> Result<(), Box<dyn Error>> {
 
     println!("{}", row.0);
 
-    // stop postgresql database
-    pg.stop_db().await?;
+    // stop postgresql database and clean up
+    pg.shutdown().await?;
     PgAccess::purge(&cache_dir)?;
 
     Ok(())
@@ -0,0 +1,109 @@
+//!
+//! # pg-embed-macros
+//!
+//! The `#[pg_embed::test]` attribute, re-exported by pg-embed with the `macros` feature.
+//!
+use proc_macro::TokenStream;
+use proc_macro2::TokenStream as TokenStream2;
+use quote::quote;
+use syn::{parse_macro_input, Error, ItemFn, LitStr};
+
+///
+/// Run an async test with a database of its own
+///
+/// The test function takes a `pg_embed::test_support::PgTestDatabase`, created on the server
+/// shared by the tests of the test process. It is removed after the test passed, the database
+/// of a failed test is kept for inspection until the process exits.
+///
+/// The migrations of a directory relative to the crate root are applied to the database with
+/// `#[pg_embed::test(migrations = "migrations")]`.
+///
+/// ```rust, ignore
+/// #[pg_embed::test(migrations = "migrations")]
+/// async fn insert_user(db: PgTestDatabase) -> Result<(), sqlx::Error> {
+///     let pool = PgPoolOptions::new().connect(db.uri()).await?;
+///     ...
+/// }
+/// ```
+///
+#[proc_macro_attribute]
+pub fn test(args: TokenStream, input: TokenStream) -> TokenStream {
+    let mut migrations: Option<LitStr> = None;
+    let parser = syn::meta::parser(|meta| {
+        if meta.path.is_ident("migrations") {
+            migrations = Some(meta.value()?.parse()?);
+            Ok(())
+        } else {
+            Err(meta.error("unsupported argument, expected `migrations = \"<dir>\"`"))
+        }
+    });
+    parse_macro_input!(args with parser);
+    let input = parse_macro_input!(input as ItemFn);
+    expand(input, migrations)
+        .unwrap_or_else(Error::into_compile_error)
+        .into()
+}
+
+///
+/// Wrap the async test function into a synchronous test running it
+///
+fn expand(mut input: ItemFn, migrations: Option<LitStr>) -> syn::Result<TokenStream2> {
+    if input.sig.asyncness.is_none() {
+        return Err(Error::new_spanned(
+            input.sig.fn_token,
+            "the test function must be async",
+        ));
+    }
+    if input.sig.inputs.len() != 1 {
+        return Err(Error::new_spanned(
+            &input.sig.ident,
+            "the test function takes the test database as its only argument",
+        ));
+    }
+    // attributes like #[ignore] or #[should_panic] belong to the generated test
+    let attrs = std::mem::take(&mut input.attrs);
+    let vis = &input.vis;
+    let name = &input.sig.ident;
+    let output = &input.sig.output;
+    let migrations = match migrations {
+        Some(dir) => quote!(::core::option::Option::Some(::core::concat!(
+            ::core::env!("CARGO_MANIFEST_DIR"),
+            "/",
+            #dir
+        ))),
+        None => quote!(::core::option::Option::None),
+    };
+    Ok(quote! {
+        #[::core::prelude::v1::test]
+        #(#attrs)*
+        #vis fn #name() #output {
+            #input
+            ::pg_embed::test_support::run_test(#migrations, #name)
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{expand, ItemFn};
+
+    #[::core::prelude::v1::test]
+    fn expand_test_function() {
+        let input: ItemFn = syn::parse_quote! {
+            #[ignore]
+            async fn my_test(db: PgTestDatabase) {}
+        };
+        let expanded = expand(input, Some(syn::parse_quote!("migrations")))
+            .unwrap()
+            .to_string();
+        assert!(expanded.contains("fn my_test ()"));
+        assert!(expanded.contains("run_test"));
+        assert!(expanded.contains("\"migrations\""));
+        assert!(expanded.find("ignore") < expanded.find("async fn"));
+
+        let not_async: ItemFn = syn::parse_quote! { fn my_test(db: PgTestDatabase) {} };
+        assert!(expand(not_async, None).is_err());
+        let no_database: ItemFn = syn::parse_quote! { async fn my_test() {} };
+        assert!(expand(no_database, None).is_err());
+    }
+}
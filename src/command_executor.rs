@@ -1,24 +1,36 @@
 //!
 //! Process command creation and execution
 //!
+use std::collections::VecDeque;
 use std::error::Error;
 use std::ffi::OsStr;
 use std::marker;
 use std::process::Stdio;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
 
 use async_trait::async_trait;
 use log;
 use tokio::io::{AsyncBufReadExt, AsyncRead, BufReader};
 use tokio::process::Child;
-use tokio::sync::mpsc::{Receiver, Sender};
+use tokio::task::JoinHandle;
 use tokio::time::Duration;
 
+use crate::pg_retry::{self, TransientError};
+
+/// Duration to wait for the output of an exited process to be read
+///
+/// Processes like `pg_ctl start` leave their output pipes open (*inherited by the server*),
+/// so the output readers can not be awaited until the end of the output.
+const OUTPUT_DRAIN_TIMEOUT: Duration = Duration::from_millis(200);
+
 ///
 /// Output logging type
 ///
 #[derive(Debug)]
 pub enum LogType {
     Info,
+    Warn,
     Error,
 }
 
@@ -38,15 +50,126 @@ where
     fn error_type(&self) -> E;
     /// wrap error
     fn wrap_error<F: Error + Sync + Send + 'static>(&self, error: F, message: String) -> E;
+    /// process failure error, carrying the outcome of the failed process
+    fn failure_error(&self, outcome: CommandOutcome) -> E {
+        log::error!("process failed with {}", outcome);
+        self.error_type()
+    }
+}
+
+///
+/// Command execution options
+///
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct CommandOptions {
+    /// maximum number of bytes kept of the stdout and stderr output each
+    pub max_output_bytes: usize,
+    /// kill the process if the execution timed out
+    pub kill_on_timeout: bool,
+}
+
+impl Default for CommandOptions {
+    fn default() -> Self {
+        CommandOptions {
+            max_output_bytes: 64 * 1024,
+            kill_on_timeout: true,
+        }
+    }
+}
+
+///
+/// Command execution outcome
+///
+/// Contains the exit code, the execution duration and the tail of the process output.
+///
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct CommandOutcome {
+    /// process exit code, `None` if the process was terminated by a signal or timed out
+    pub exit_code: Option<i32>,
+    /// duration since the process was spawned
+    pub duration: Duration,
+    /// the last lines of the stdout output
+    pub stdout_tail: String,
+    /// the last lines of the stderr output
+    pub stderr_tail: String,
+}
+
+impl std::fmt::Display for CommandOutcome {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.exit_code {
+            Some(code) => write!(f, "exit code {} after {:?}", code, self.duration)?,
+            None => write!(f, "no exit code after {:?}", self.duration)?,
+        }
+        let output = if self.stderr_tail.trim().is_empty() {
+            self.stdout_tail.trim()
+        } else {
+            self.stderr_tail.trim()
+        };
+        if !output.is_empty() {
+            write!(f, ": {}", output)?;
+        }
+        Ok(())
+    }
+}
+
+///
+/// Command execution timed out
+///
+/// Carries the outcome of the timed out command
+///
+#[derive(Debug, Clone)]
+pub struct CommandTimeout {
+    /// outcome of the timed out command
+    pub outcome: CommandOutcome,
+}
+
+impl std::fmt::Display for CommandTimeout {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("deadline has elapsed")
+    }
 }
 
+impl Error for CommandTimeout {}
+
 ///
-/// Logging data
+/// Capped output buffer, keeping the last lines of a process output
 ///
 #[derive(Debug)]
-pub struct LogOutputData {
-    line: String,
-    log_type: LogType,
+struct OutputTail {
+    lines: VecDeque<String>,
+    bytes: usize,
+    max_bytes: usize,
+}
+
+impl OutputTail {
+    fn new(max_bytes: usize) -> Self {
+        OutputTail {
+            lines: VecDeque::new(),
+            bytes: 0,
+            max_bytes,
+        }
+    }
+
+    fn push(&mut self, line: String) {
+        self.bytes += line.len() + 1;
+        self.lines.push_back(line);
+        while self.bytes > self.max_bytes {
+            match self.lines.pop_front() {
+                Some(line) => self.bytes -= line.len() + 1,
+                None => break,
+            }
+        }
+    }
+
+    fn text(&self) -> String {
+        self.lines
+            .iter()
+            .fold(String::with_capacity(self.bytes), |mut text, line| {
+                text.push_str(line);
+                text.push('\n');
+                text
+            })
+    }
 }
 
 ///
@@ -72,7 +195,21 @@ where
     /// When timeout is Some(duration) the process execution will be timed out after duration,
     /// if set to None the process execution will not be timed out.
     ///
-    async fn execute(&mut self, timeout: Option<Duration>) -> Result<S, E>;
+    async fn execute(&mut self, timeout: Option<Duration>) -> Result<S, E> {
+        self.execute_with_outcome(timeout)
+            .await
+            .map(|(status, _)| status)
+    }
+    ///
+    /// Execute command and return the command outcome
+    ///
+    /// When timeout is Some(duration) the process execution will be timed out after duration,
+    /// if set to None the process execution will not be timed out.
+    ///
+    async fn execute_with_outcome(
+        &mut self,
+        timeout: Option<Duration>,
+    ) -> Result<(S, CommandOutcome), E>;
 }
 
 ///
@@ -86,11 +223,16 @@ where
     Self: Send,
 {
     /// Process command
-    _command: tokio::process::Command,
+    command: tokio::process::Command,
     /// Process child
-    process: Child,
+    /// if set to None spawning failed transiently and is retried on execution
+    process: Option<Child>,
     /// Process type
     process_type: P,
+    /// Execution options
+    options: CommandOptions,
+    /// Process spawn time
+    started: Instant,
     _marker_s: marker::PhantomData<S>,
     _marker_e: marker::PhantomData<E>,
 }
@@ -102,18 +244,73 @@ where
     P: ProcessStatus<S, E> + Send,
 {
     /// Initialize command
-    fn init(command: &mut tokio::process::Command, process_type: &P) -> Result<Child, E> {
-        command
-            .stdout(Stdio::piped())
-            .stderr(Stdio::piped())
-            .spawn()
-            .map_err(|_| process_type.error_type())
+    fn init(command: &mut tokio::process::Command, process_type: &P) -> Result<Option<Child>, E> {
+        command.stdout(Stdio::piped()).stderr(Stdio::piped());
+        match command.spawn() {
+            Ok(process) => Ok(Some(process)),
+            // the first launch of a freshly unpacked executable may hit a virus scanner lock,
+            // retried without blocking on execution
+            Err(e) if e.is_transient() => Ok(None),
+            Err(e) => Err(Self::spawn_failure(command, process_type, e)),
+        }
+    }
+
+    /// Spawn the process if spawning it failed transiently on initialization
+    async fn ensure_spawned(&mut self) -> Result<(), E> {
+        if self.process.is_none() {
+            let command = &mut self.command;
+            let process = pg_retry::retry("spawning process", || command.spawn())
+                .await
+                .map_err(|e| Self::spawn_failure(&self.command, &self.process_type, e))?;
+            self.started = Instant::now();
+            self.process = Some(process);
+        }
+        Ok(())
+    }
+
+    /// Log a failed spawn and create the error
+    fn spawn_failure(command: &tokio::process::Command, process_type: &P, e: std::io::Error) -> E {
+        log::error!(
+            "Failed to spawn {:?}: {}",
+            command.as_std().get_program(),
+            e
+        );
+        process_type.error_type()
+    }
+
+    ///
+    /// Create an executor from a prepared command
+    ///
+    /// Allows configuring the command (*e.g. the user it runs as*) before the process is spawned.
+    ///
+    pub fn from_command(mut command: tokio::process::Command, process_type: P) -> Result<Self, E> {
+        let process = Self::init(&mut command, &process_type)?;
+        Ok(AsyncCommandExecutor {
+            command,
+            process,
+            process_type,
+            options: CommandOptions::default(),
+            started: Instant::now(),
+            _marker_s: Default::default(),
+            _marker_e: Default::default(),
+        })
+    }
+
+    ///
+    /// Set the execution options
+    ///
+    pub fn with_options(mut self, options: CommandOptions) -> Self {
+        self.options = options;
+        self
     }
 
     /// Generate a command
-    fn generate_command<A, B>(executable_path: &OsStr, args: A) -> tokio::process::Command
+    pub(crate) fn generate_command<A, B>(
+        executable_path: &OsStr,
+        args: A,
+    ) -> tokio::process::Command
     where
-        A: IntoIterator<Item=B>,
+        A: IntoIterator<Item = B>,
         B: AsRef<OsStr>,
     {
         let mut command = tokio::process::Command::new(executable_path);
@@ -128,72 +325,116 @@ where
     }
 
     /// Handle process output
-    async fn handle_output<R: AsyncRead + Unpin>(data: R, sender: Sender<LogOutputData>) {
+    async fn handle_output<R: AsyncRead + Unpin>(
+        data: R,
+        log_type: LogType,
+        tail: Arc<Mutex<OutputTail>>,
+    ) {
         let mut lines = BufReader::new(data).lines();
-        while let Some(line) = lines.next_line().await.expect("error handling output") {
-            let io_data = LogOutputData {
-                line,
-                log_type: LogType::Info,
-            };
-            sender
-                .send(io_data)
-                .await
-                .expect("error sending log output data");
-        }
-    }
-
-    /// Log process output
-    async fn log_output(mut receiver: Receiver<LogOutputData>) {
-        while let Some(data) = receiver.recv().await {
-            match data.log_type {
+        while let Ok(Some(line)) = lines.next_line().await {
+            match log_type {
                 LogType::Info => {
-                    log::info!("{}", data.line);
+                    log::info!("{}", line);
+                }
+                LogType::Warn => {
+                    log::warn!("{}", line);
                 }
                 LogType::Error => {
-                    log::error!("{}", data.line);
+                    log::error!("{}", line);
                 }
             }
+            tail.lock().expect("output tail lock poisoned").push(line);
+        }
+    }
+
+    /// Start reading the process output, stderr is logged as warnings
+    fn read_output(
+        &mut self,
+        stdout_tail: &Arc<Mutex<OutputTail>>,
+        stderr_tail: &Arc<Mutex<OutputTail>>,
+    ) -> Vec<JoinHandle<()>> {
+        let mut readers = Vec::with_capacity(2);
+        let process = match self.process.as_mut() {
+            Some(process) => process,
+            None => return readers,
+        };
+        if let Some(stdout) = process.stdout.take() {
+            let tail = stdout_tail.clone();
+            readers.push(tokio::task::spawn(async move {
+                Self::handle_output(stdout, LogType::Info, tail).await
+            }));
+        }
+        if let Some(stderr) = process.stderr.take() {
+            let tail = stderr_tail.clone();
+            readers.push(tokio::task::spawn(async move {
+                Self::handle_output(stderr, LogType::Warn, tail).await
+            }));
+        }
+        readers
+    }
+
+    /// Create the command outcome
+    fn outcome(
+        &self,
+        exit_code: Option<i32>,
+        stdout_tail: &Arc<Mutex<OutputTail>>,
+        stderr_tail: &Arc<Mutex<OutputTail>>,
+    ) -> CommandOutcome {
+        CommandOutcome {
+            exit_code,
+            duration: self.started.elapsed(),
+            stdout_tail: stdout_tail
+                .lock()
+                .expect("output tail lock poisoned")
+                .text(),
+            stderr_tail: stderr_tail
+                .lock()
+                .expect("output tail lock poisoned")
+                .text(),
         }
     }
 
     /// Run process
-    async fn run_process(&mut self) -> Result<S, E> {
-        let exit_status = self.process.wait().await.map_err(|e| {
+    async fn command_execution(
+        &mut self,
+        timeout: Option<Duration>,
+    ) -> Result<(S, CommandOutcome), E> {
+        let stdout_tail = Arc::new(Mutex::new(OutputTail::new(self.options.max_output_bytes)));
+        let stderr_tail = Arc::new(Mutex::new(OutputTail::new(self.options.max_output_bytes)));
+        self.ensure_spawned().await?;
+        let readers = self.read_output(&stdout_tail, &stderr_tail);
+
+        let process = self.process.as_mut().expect("process spawned");
+        let exit_status = match timeout {
+            None => process.wait().await,
+            Some(duration) => match tokio::time::timeout(duration, process.wait()).await {
+                Ok(exit_status) => exit_status,
+                Err(_) => {
+                    if self.options.kill_on_timeout {
+                        if let Err(e) = process.kill().await {
+                            log::warn!("failed to kill timed out process: {}", e);
+                        }
+                    }
+                    let outcome = self.outcome(None, &stdout_tail, &stderr_tail);
+                    return Err(self
+                        .process_type
+                        .wrap_error(CommandTimeout { outcome }, String::from("timed out")));
+                }
+            },
+        }
+        .map_err(|e| {
             self.process_type
                 .wrap_error(e, "failed to run process".to_string())
         })?;
+
+        let drained = futures::future::join_all(readers);
+        let _ = tokio::time::timeout(OUTPUT_DRAIN_TIMEOUT, drained).await;
+        let outcome = self.outcome(exit_status.code(), &stdout_tail, &stderr_tail);
         if exit_status.success() {
-            Ok(self.process_type.status_exit())
+            Ok((self.process_type.status_exit(), outcome))
         } else {
-            Err(self.process_type.error_type())
-        }
-    }
-
-    #[cfg(not(target_os = "windows"))]
-    async fn command_execution(&mut self) -> Result<S, E> {
-        let (sender, receiver) = tokio::sync::mpsc::channel::<LogOutputData>(1000);
-        let res = self.run_process().await;
-        let stdout = self.process.stdout.take().unwrap();
-        let stderr = self.process.stderr.take().unwrap();
-        let tx = sender.clone();
-        let _ = tokio::task::spawn(async { Self::handle_output(stdout, tx).await });
-        let _ = tokio::task::spawn(async { Self::handle_output(stderr, sender).await });
-        let _ = tokio::task::spawn(async { Self::log_output(receiver).await });
-        res
-    }
-
-    #[cfg(target_os = "windows")]
-    async fn command_execution(&mut self) -> Result<S, E> {
-        //TODO: find another way to use stderr on windows
-        // let (sender, receiver) = tokio::sync::mpsc::channel::<LogOutputData>(1000);
-        let res = self.run_process().await;
-        // let stdout = self.process.stdout.take().unwrap();
-        // let stderr = self.process.stderr.take().unwrap();
-        // let tx = sender.clone();
-        // let _ = tokio::task::spawn(async { Self::handle_output(stdout, tx).await });
-        // let _ = tokio::task::spawn(async { Self::handle_output(stderr, sender).await });
-        // let _ = tokio::task::spawn(async { Self::log_output(receiver).await });
-        res
+            Err(self.process_type.failure_error(outcome))
+        }
     }
 }
 
@@ -206,26 +447,112 @@ where
 {
     fn new<A, B>(executable_path: &OsStr, args: A, process_type: P) -> Result<Self, E>
     where
-        A: IntoIterator<Item=B>,
+        A: IntoIterator<Item = B>,
         B: AsRef<OsStr>,
     {
-        let mut _command = Self::generate_command(executable_path, args);
-        let process = Self::init(&mut _command, &process_type)?;
-        Ok(AsyncCommandExecutor {
-            _command,
-            process,
-            process_type,
-            _marker_s: Default::default(),
-            _marker_e: Default::default(),
-        })
+        let command = Self::generate_command(executable_path, args);
+        Self::from_command(command, process_type)
     }
 
-    async fn execute(&mut self, timeout: Option<Duration>) -> Result<S, E> {
-        match timeout {
-            None => self.command_execution().await,
-            Some(duration) => tokio::time::timeout(duration, self.command_execution())
-                .await
-                .map_err(|e| self.process_type.wrap_error(e, String::from("timed out")))?,
+    async fn execute_with_outcome(
+        &mut self,
+        timeout: Option<Duration>,
+    ) -> Result<(S, CommandOutcome), E> {
+        self.command_execution(timeout).await
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+
+    #[derive(Debug, thiserror::Error)]
+    enum TestError {
+        #[error("failed: {0}")]
+        Failed(CommandOutcome),
+        #[error("spawn failed")]
+        Spawn,
+        #[error("{0}")]
+        Wrapped(String),
+    }
+
+    struct TestProcess;
+
+    impl ProcessStatus<bool, TestError> for TestProcess {
+        fn status_entry(&self) -> bool {
+            false
+        }
+
+        fn status_exit(&self) -> bool {
+            true
         }
+
+        fn error_type(&self) -> TestError {
+            TestError::Spawn
+        }
+
+        fn wrap_error<F: Error + Sync + Send + 'static>(
+            &self,
+            error: F,
+            message: String,
+        ) -> TestError {
+            TestError::Wrapped(format!("{} due to error: {}", message, error))
+        }
+
+        fn failure_error(&self, outcome: CommandOutcome) -> TestError {
+            TestError::Failed(outcome)
+        }
+    }
+
+    fn executor(script: &str) -> AsyncCommandExecutor<bool, TestError, TestProcess> {
+        AsyncCommandExecutor::new(OsStr::new("sh"), ["-c", script], TestProcess).unwrap()
+    }
+
+    #[tokio::test]
+    async fn failure_outcome() {
+        let mut executor =
+            executor("echo out; echo err >&2; exit 3").with_options(CommandOptions {
+                max_output_bytes: 1024,
+                kill_on_timeout: true,
+            });
+        match executor.execute(None).await {
+            Err(TestError::Failed(outcome)) => {
+                assert_eq!(Some(3), outcome.exit_code);
+                assert_eq!("out\n", outcome.stdout_tail);
+                assert_eq!("err\n", outcome.stderr_tail);
+            }
+            other => panic!("unexpected result {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn output_tail_is_capped() {
+        let mut executor =
+            executor("for i in 1 2 3 4 5; do echo line$i; done").with_options(CommandOptions {
+                max_output_bytes: 12,
+                kill_on_timeout: true,
+            });
+        let (status, outcome) = executor.execute_with_outcome(None).await.unwrap();
+        assert!(status);
+        assert_eq!("line4\nline5\n", outcome.stdout_tail);
+    }
+
+    #[tokio::test]
+    async fn timeout_kills_process() {
+        let mut executor = executor("sleep 5");
+        let started = Instant::now();
+        let result = executor.execute(Some(Duration::from_millis(50))).await;
+        assert_eq!(
+            Some("timed out due to error: deadline has elapsed".to_string()),
+            result.err().map(|e| e.to_string())
+        );
+        assert!(executor
+            .process
+            .as_mut()
+            .unwrap()
+            .try_wait()
+            .unwrap()
+            .is_some());
+        assert!(started.elapsed() < Duration::from_secs(5));
     }
 }
@@ -3,32 +3,105 @@
 //!
 
 use std::cell::Cell;
-use std::collections::HashMap;
-use std::io::Write;
+use std::io::{Read, Write};
 use std::path::{Path, PathBuf};
 use std::sync::Arc;
 
+use dashmap::DashMap;
+use fs4::FileExt;
 use tokio::sync::Mutex;
 
-use crate::pg_enums::{OperationSystem, PgAcquisitionStatus};
+use crate::pg_archive::{self, PgCompression};
+use crate::pg_cache::{self, PgCacheEntry, PgCacheGcPolicy};
+use crate::pg_commands;
+use crate::pg_enums::{Architecture, OperationSystem, PgAcquisitionStatus, PgShutdownMode};
 use crate::pg_errors::PgEmbedError;
-use crate::pg_fetch::PgFetchSettings;
+#[cfg(feature = "pgvector")]
+use crate::pg_extension;
+use crate::pg_extension::PgExtensionInstaller;
+use crate::pg_fetch::{env_override, PgFetchSettings, PostgresVersion, ProgressCallback};
+use crate::pg_manifest::CacheManifest;
+use crate::pg_process::PgServerProcess;
+use crate::pg_retry;
+use crate::pg_status::{self, PgPhase, PgStatusReport, PgStatusSink};
 use crate::pg_types::{PgCommandSync, PgResult};
 use crate::pg_unpack;
+#[cfg(feature = "pgvector")]
+use crate::pg_vector::{self, PgVectorSettings};
 
 lazy_static! {
     ///
-    /// Stores the paths to the cache directories while acquiring the related postgres binaries
+    /// Stores the acquisition status of the postgres binaries per cache directory
+    ///
+    static ref ACQUIRED_PG_BINS: DashMap<PathBuf, PgAcquisitionStatus> = DashMap::new();
+    ///
+    /// Stores one lock per cache directory
     ///
     /// Used to prevent simultaneous downloads and unpacking of the same binaries
     /// while executing multiple PgEmbed instances concurrently.
+    /// Binaries of different cache directories are acquired in parallel.
     ///
-    static ref ACQUIRED_PG_BINS: Arc<Mutex<HashMap<PathBuf, PgAcquisitionStatus>>> =
-    Arc::new(Mutex::new(HashMap::with_capacity(5)));
+    static ref ACQUISITION_LOCKS: DashMap<PathBuf, Arc<Mutex<()>>> = DashMap::new();
+}
+
+///
+/// Marks an acquisition as in progress until finished
+///
+/// Dropping the guard unfinished, e.g. when the acquiring future is cancelled,
+/// marks the acquisition as failed so it is not reported as in progress forever.
+///
+struct AcquisitionStatusGuard {
+    cache_dir: PathBuf,
+    finished: bool,
+}
+
+impl AcquisitionStatusGuard {
+    fn new(cache_dir: &Path) -> Self {
+        ACQUIRED_PG_BINS.insert(cache_dir.to_path_buf(), PgAcquisitionStatus::InProgress);
+        AcquisitionStatusGuard {
+            cache_dir: cache_dir.to_path_buf(),
+            finished: false,
+        }
+    }
+
+    fn finish(mut self, result: &PgResult<()>) {
+        let status = match result {
+            Ok(()) => PgAcquisitionStatus::Finished,
+            Err(e) => PgAcquisitionStatus::Failed(e.to_string()),
+        };
+        ACQUIRED_PG_BINS.insert(self.cache_dir.clone(), status);
+        self.finished = true;
+    }
+}
+
+impl Drop for AcquisitionStatusGuard {
+    fn drop(&mut self) {
+        if !self.finished {
+            ACQUIRED_PG_BINS.insert(
+                self.cache_dir.clone(),
+                PgAcquisitionStatus::Failed("acquisition was cancelled".to_string()),
+            );
+        }
+    }
 }
 
 const PG_EMBED_CACHE_DIR_NAME: &str = "pg-embed";
+/// Environment variable overriding the cache directory
+pub const PG_EMBED_CACHE_DIR_ENV: &str = "PG_EMBED_CACHE_DIR";
 const PG_VERSION_FILE_NAME: &str = "PG_VERSION";
+/// Advisory lock file guarding the cache directory across processes
+pub(crate) const CACHE_LOCK_FILE_NAME: &str = ".lock";
+/// Top level directories of the postgresql binaries package
+const PG_PACKAGE_DIRS: [&str; 5] = ["bin", "doc", "include", "lib", "share"];
+/// Marker written after the binaries have been completely unpacked
+pub(crate) const CACHE_COMPLETE_FILE_NAME: &str = ".complete";
+/// Manifest of the cached binaries, see [CacheManifest]
+pub(crate) const CACHE_MANIFEST_FILE_NAME: &str = ".manifest";
+/// Marker in the database directory of a cluster whose bootstrap sql has not run yet
+const BOOTSTRAP_PENDING_FILE_NAME: &str = "pg_embed.bootstrap";
+/// Directory inside the cache directory pgvector is downloaded and built in
+#[cfg(feature = "pgvector")]
+const PGVECTOR_STAGING_DIR_NAME: &str = "pg_embed.pgvector";
 
 ///
 /// Access to pg_ctl, initdb, database directory and cache directory
@@ -42,6 +115,20 @@ pub struct PgAccess {
     pub pg_ctl_exe: PathBuf,
     /// Postgresql initdb executable path
     pub init_db_exe: PathBuf,
+    /// Postgresql server executable path
+    pub postgres_exe: PathBuf,
+    /// pg_dump executable
+    pub pg_dump_exe: PathBuf,
+    /// pg_restore executable
+    pub pg_restore_exe: PathBuf,
+    /// pg_isready executable
+    pub pg_isready_exe: PathBuf,
+    /// pg_upgrade executable
+    pub pg_upgrade_exe: PathBuf,
+    /// psql executable
+    pub psql_exe: PathBuf,
+    /// pg_basebackup executable
+    pub pg_basebackup_exe: PathBuf,
     /// Password file path
     pub pw_file_path: PathBuf,
     /// Postgresql binaries zip file path
@@ -51,6 +138,8 @@ pub struct PgAccess {
     pg_version_file: PathBuf,
     /// Fetch settings
     fetch_settings: PgFetchSettings,
+    /// Status reports receiver
+    status_sink: Option<PgStatusSink>,
 }
 
 impl PgAccess {
@@ -64,6 +153,8 @@ impl PgAccess {
         database_dir: &PathBuf,
         cache_dir: Option<&PathBuf>,
     ) -> Result<Self, PgEmbedError> {
+        // the environment override takes precedence over a configured cache directory
+        let cache_dir = cache_dir.filter(|_| env_override(PG_EMBED_CACHE_DIR_ENV).is_none());
         let cache_dir = match cache_dir {
             Some(d) => {
                 std::fs::create_dir_all(d)
@@ -84,10 +175,45 @@ impl PgAccess {
         let init_db = cache_dir.clone().join("bin").join("initdb");
         #[cfg(target_os = "windows")]
         let init_db = cache_dir.clone().join("bin").join("initdb.exe");
+        // postgres executable
+        #[cfg(not(target_os = "windows"))]
+        let postgres = cache_dir.clone().join("bin").join("postgres");
+        #[cfg(target_os = "windows")]
+        let postgres = cache_dir.clone().join("bin").join("postgres.exe");
+        // pg_dump executable
+        #[cfg(not(target_os = "windows"))]
+        let pg_dump = cache_dir.clone().join("bin").join("pg_dump");
+        #[cfg(target_os = "windows")]
+        let pg_dump = cache_dir.clone().join("bin").join("pg_dump.exe");
+        // pg_restore executable
+        #[cfg(not(target_os = "windows"))]
+        let pg_restore = cache_dir.clone().join("bin").join("pg_restore");
+        #[cfg(target_os = "windows")]
+        let pg_restore = cache_dir.clone().join("bin").join("pg_restore.exe");
+        // pg_isready executable
+        #[cfg(not(target_os = "windows"))]
+        let pg_isready = cache_dir.clone().join("bin").join("pg_isready");
+        #[cfg(target_os = "windows")]
+        let pg_isready = cache_dir.clone().join("bin").join("pg_isready.exe");
+        // pg_upgrade executable
+        #[cfg(not(target_os = "windows"))]
+        let pg_upgrade = cache_dir.clone().join("bin").join("pg_upgrade");
+        #[cfg(target_os = "windows")]
+        let pg_upgrade = cache_dir.clone().join("bin").join("pg_upgrade.exe");
+        // psql executable
+        #[cfg(not(target_os = "windows"))]
+        let psql = cache_dir.clone().join("bin").join("psql");
+        #[cfg(target_os = "windows")]
+        let psql = cache_dir.clone().join("bin").join("psql.exe");
+        // pg_basebackup executable
+        #[cfg(not(target_os = "windows"))]
+        let pg_basebackup = cache_dir.clone().join("bin").join("pg_basebackup");
+        #[cfg(target_os = "windows")]
+        let pg_basebackup = cache_dir.clone().join("bin").join("pg_basebackup.exe");
         // postgres zip file
         let mut zip_file_path = cache_dir.clone();
         let platform = fetch_settings.platform();
-        let file_name = format!("{}-{}.zip", platform, &fetch_settings.version.0);
+        let file_name = format!("{}-{}.zip", platform, &fetch_settings.version);
         zip_file_path.push(file_name);
         // password file
         let mut pw_file = database_dir.clone();
@@ -101,10 +227,18 @@ impl PgAccess {
             database_dir: database_dir.clone(),
             pg_ctl_exe: pg_ctl,
             init_db_exe: init_db,
+            postgres_exe: postgres,
+            pg_dump_exe: pg_dump,
+            pg_restore_exe: pg_restore,
+            pg_isready_exe: pg_isready,
+            pg_upgrade_exe: pg_upgrade,
+            psql_exe: psql,
+            pg_basebackup_exe: pg_basebackup,
             pw_file_path: pw_file,
             zip_file_path,
             pg_version_file,
             fetch_settings: fetch_settings.clone(),
+            status_sink: None,
         })
     }
 
@@ -114,21 +248,13 @@ impl PgAccess {
     /// Returns PathBuf(cache_directory) on success, an error otherwise
     ///
     fn create_cache_dir_structure(fetch_settings: &PgFetchSettings) -> PgResult<PathBuf> {
-        let cache_dir = dirs::cache_dir().ok_or_else(|| PgEmbedError::NoSystemCacheDirectory)?;
-        let os_string = match fetch_settings.operating_system {
-            OperationSystem::Darwin | OperationSystem::Windows | OperationSystem::Linux => {
-                fetch_settings.operating_system.to_string()
-            }
-            OperationSystem::AlpineLinux => {
-                format!("arch_{}", fetch_settings.operating_system)
-            }
-        };
-        let cache_pg_embed = cache_dir
-            .clone()
-            .join(PG_EMBED_CACHE_DIR_NAME)
-            .join(os_string)
+        let cache_pg_embed = Self::cache_root()?
+            .join(pg_cache::os_dir_name(&fetch_settings.operating_system))
             .join(fetch_settings.architecture.to_string())
-            .join(fetch_settings.version.0);
+            .join(pg_cache::version_dir_name(
+                &fetch_settings.version,
+                fetch_settings.postgis,
+            ));
         std::fs::create_dir_all(&cache_pg_embed).map_err(|e| PgEmbedError::DirCreationError {
             dir: cache_pg_embed.clone(),
             e,
@@ -136,6 +262,19 @@ impl PgAccess {
         Ok(cache_pg_embed)
     }
 
+    ///
+    /// The default cache directory containing the binaries of all versions
+    ///
+    /// [PG_EMBED_CACHE_DIR_ENV] takes precedence over the system cache directory.
+    ///
+    fn cache_root() -> PgResult<PathBuf> {
+        if let Some(cache_dir) = env_override(PG_EMBED_CACHE_DIR_ENV) {
+            return Ok(PathBuf::from(cache_dir));
+        }
+        let cache_dir = dirs::cache_dir().ok_or_else(|| PgEmbedError::NoSystemCacheDirectory)?;
+        Ok(cache_dir.join(PG_EMBED_CACHE_DIR_NAME))
+    }
+
     fn create_db_dir_structure(db_dir: &PathBuf) -> PgResult<()> {
         std::fs::create_dir_all(db_dir).map_err(|e| PgEmbedError::DirCreationError {
             dir: db_dir.clone(),
@@ -148,35 +287,584 @@ impl PgAccess {
     /// Download and unpack postgres binaries
     ///
     pub async fn maybe_acquire_postgres(&self) -> PgResult<()> {
-        let mut lock = ACQUIRED_PG_BINS.lock().await;
+        let acquisition_lock = self.acquisition_lock();
+        let _lock = acquisition_lock.lock().await;
+        let _file_lock = self.lock_cache_dir().await?;
 
+        self.adopt_unmarked_cache().await?;
         if self.pg_executables_cached()? {
-            return Ok(());
+            let missing = self
+                .read_manifest()?
+                .map(|m| m.missing_files(&self.cache_dir));
+            if missing.as_ref().is_none_or(|missing| missing.is_empty()) {
+                ACQUIRED_PG_BINS.insert(self.cache_dir.clone(), PgAcquisitionStatus::Finished);
+                return pg_cache::touch_last_used(&self.cache_dir);
+            }
+            log::warn!(
+                "Cached postgresql binaries are incomplete, missing {:?}, acquiring again",
+                missing.unwrap_or_default()
+            );
+        }
+        self.acquire_locked().await?;
+        pg_cache::touch_last_used(&self.cache_dir)
+    }
+
+    ///
+    /// Verify the integrity of the cached binaries and re-acquire them if damaged
+    ///
+    /// Hashes all cached files and compares them with the manifest written after unpacking,
+    /// detecting missing or corrupted files (*e.g. quarantined by an antivirus*).
+    ///
+    /// Returns `Ok(true)` if the cache was intact, `Ok(false)` if it has been repaired.
+    ///
+    pub async fn verify_cache(&self) -> PgResult<bool> {
+        let acquisition_lock = self.acquisition_lock();
+        let _lock = acquisition_lock.lock().await;
+        let _file_lock = self.lock_cache_dir().await?;
+
+        self.adopt_unmarked_cache().await?;
+        let corrupted = match (self.pg_executables_cached()?, self.read_manifest()?) {
+            (true, Some(manifest)) => {
+                let cache_dir = self.cache_dir.clone();
+                tokio::task::spawn_blocking(move || manifest.corrupted_files(&cache_dir))
+                    .await
+                    .map_err(|e| PgEmbedError::PgError {
+                        source: Box::new(e),
+                        message: "cache verification task failed".to_string(),
+                    })?
+            }
+            // nothing to compare with
+            (true, None) => return Ok(true),
+            (false, _) => vec![self.cache_dir.join(CACHE_COMPLETE_FILE_NAME)],
+        };
+        if corrupted.is_empty() {
+            return Ok(true);
         }
+        log::warn!(
+            "Cached postgresql binaries are damaged, corrupted {:?}, acquiring again",
+            corrupted
+        );
+        self.acquire_locked().await?;
+        Ok(false)
+    }
 
-        lock.insert(self.cache_dir.clone(), PgAcquisitionStatus::InProgress);
-        let pg_bin_data = self.fetch_settings.fetch_postgres().await?;
+    ///
+    /// Acquire the binaries, the caller holds the acquisition locks
+    ///
+    async fn acquire_locked(&self) -> PgResult<()> {
+        let status = AcquisitionStatusGuard::new(&self.cache_dir);
+        let result = match self.remove_partial_cache().await {
+            Ok(()) => self.acquire_postgres().await,
+            Err(e) => Err(e),
+        };
+        status.finish(&result);
+        result
+    }
+
+    ///
+    /// Download and unpack postgres binaries into the cache directory
+    ///
+    async fn acquire_postgres(&self) -> PgResult<()> {
+        let (pg_bin_data, version) = self
+            .fetch_settings_reporting()
+            .fetch_postgres_version()
+            .await?;
         self.write_pg_zip(&pg_bin_data)?;
+        pg_status::report(
+            self.status_sink.as_ref(),
+            PgPhase::Unpacking,
+            Some(0.0),
+            "Unpacking postgresql binaries",
+        );
         log::debug!(
             "Unpacking postgres binaries {} {}",
             self.zip_file_path.display(),
             self.cache_dir.display()
         );
-        pg_unpack::unpack_postgres(&self.zip_file_path, &self.cache_dir).await?;
-        std::fs::remove_file(&self.zip_file_path).map_err(|e| PgEmbedError::PgCleanUpFailure {
+        pg_unpack::unpack_postgres_reporting(
+            &self.zip_file_path,
+            &self.cache_dir,
+            self.fetch_settings.minimal_install,
+            self.status_sink.clone(),
+        )
+        .await?;
+        pg_retry::retry("removing the package", || {
+            std::fs::remove_file(&self.zip_file_path)
+        })
+        .await
+        .map_err(|e| PgEmbedError::PgCleanUpFailure {
             path: self.zip_file_path.clone(),
             e,
         })?;
+        self.verify_architecture()?;
+        self.verify_postgis()?;
+        self.mark_cache_complete(&version)
+    }
+
+    ///
+    /// Remove the binaries of an incompletely populated cache directory
+    ///
+    /// Left behind if an acquisition was interrupted or the cache was damaged. Only the directories of the postgresql
+    /// package and the downloaded archive are removed, the cache directory may be user provided.
+    ///
+    async fn remove_partial_cache(&self) -> PgResult<()> {
+        for file in [CACHE_COMPLETE_FILE_NAME, CACHE_MANIFEST_FILE_NAME] {
+            let path = self.cache_dir.join(file);
+            if path.exists() {
+                pg_retry::retry("removing a file", || std::fs::remove_file(&path))
+                    .await
+                    .map_err(|e| PgEmbedError::PgCleanUpFailure { path, e })?;
+            }
+        }
+        let package_dirs = PG_PACKAGE_DIRS.iter().map(|dir| self.cache_dir.join(dir));
+        for path in package_dirs.filter(|path| path.exists()) {
+            log::debug!("Removing partially acquired {}", path.display());
+            pg_retry::retry("removing a directory", || std::fs::remove_dir_all(&path))
+                .await
+                .map_err(|e| PgEmbedError::PgCleanUpFailure { path, e })?;
+        }
+        if self.zip_file_path.exists() {
+            pg_retry::retry("removing the package", || {
+                std::fs::remove_file(&self.zip_file_path)
+            })
+            .await
+            .map_err(|e| PgEmbedError::PgCleanUpFailure {
+                path: self.zip_file_path.clone(),
+                e,
+            })?;
+        }
+        Ok(())
+    }
+
+    ///
+    /// Mark binaries cached without the completion marker (*e.g. by an earlier version of this
+    /// crate*) as complete
+    ///
+    /// Only if the downloaded archive is gone, it is removed after unpacking, and the cached
+    /// postgres executable runs and reports the configured major version. Otherwise the cache
+    /// is wiped as partial before acquiring the binaries again.
+    ///
+    async fn adopt_unmarked_cache(&self) -> PgResult<()> {
+        if Self::path_exists(&self.cache_dir.join(CACHE_COMPLETE_FILE_NAME))?
+            || !self.pg_executables_exist()?
+            || Self::path_exists(&self.zip_file_path)?
+        {
+            return Ok(());
+        }
+        match self.executable_version().await {
+            Some(version) if version.major() == self.fetch_settings.version.major() => {
+                log::info!(
+                    "Marking cached postgresql binaries {} in {} as complete",
+                    version,
+                    self.cache_dir.display()
+                );
+                self.mark_cache_complete(&version)
+            }
+            _ => Ok(()),
+        }
+    }
+
+    ///
+    /// Version reported by the cached postgres executable, if it runs
+    ///
+    async fn executable_version(&self) -> Option<PostgresVersion> {
+        let output = tokio::process::Command::new(&self.postgres_exe)
+            .arg("--version")
+            .kill_on_drop(true)
+            .output()
+            .await
+            .ok()?;
+        if !output.status.success() {
+            return None;
+        }
+        // e.g. `postgres (PostgreSQL) 16.2`
+        String::from_utf8_lossy(&output.stdout)
+            .split_whitespace()
+            .nth(2)?
+            .parse()
+            .ok()
+    }
+
+    ///
+    /// Read the manifest of the cached binaries, if written
+    ///
+    fn read_manifest(&self) -> PgResult<Option<CacheManifest>> {
+        CacheManifest::read(&self.cache_dir.join(CACHE_MANIFEST_FILE_NAME))
+    }
+
+    ///
+    /// Write the manifest and the marker completing the cached binaries
+    ///
+    /// The marker records the version of the cached binaries, which may be a fallback of the
+    /// same major version, see [PgFetchSettings::allow_version_fallback].
+    ///
+    fn mark_cache_complete(&self, version: &PostgresVersion) -> PgResult<()> {
+        CacheManifest::create(&self.cache_dir, &PG_PACKAGE_DIRS)?
+            .write(&self.cache_dir.join(CACHE_MANIFEST_FILE_NAME))?;
+        let marker = self.cache_dir.join(CACHE_COMPLETE_FILE_NAME);
+        std::fs::write(&marker, version.to_string())
+            .map_err(|e| PgEmbedError::WriteFileError { e, path: marker })
+    }
+
+    ///
+    /// Lock guarding the acquisition of the binaries in this cache directory
+    ///
+    fn acquisition_lock(&self) -> Arc<Mutex<()>> {
+        ACQUISITION_LOCKS
+            .entry(self.cache_dir.clone())
+            .or_default()
+            .clone()
+    }
+
+    ///
+    /// Acquire the exclusive advisory lock of the cache directory
+    ///
+    /// Serializes acquisitions of separate processes sharing the cache directory,
+    /// e.g. test binaries run by cargo. The lock is released when the returned file is dropped.
+    ///
+    async fn lock_cache_dir(&self) -> PgResult<std::fs::File> {
+        let lock_path = self.cache_dir.join(CACHE_LOCK_FILE_NAME);
+        let file = std::fs::OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(&lock_path)
+            .map_err(|e| PgEmbedError::CacheLockError {
+                e,
+                path: lock_path.clone(),
+            })?;
+        tokio::task::spawn_blocking(move || FileExt::lock(&file).map(|_| file))
+            .await
+            .map_err(|e| PgEmbedError::PgError {
+                source: Box::new(e),
+                message: "cache lock task failed".to_string(),
+            })?
+            .map_err(|e| PgEmbedError::CacheLockError { e, path: lock_path })
+    }
+
+    ///
+    /// Advisory lock file of the database directory
+    ///
+    /// Placed next to the directory, initdb requires it to be empty.
+    ///
+    pub fn database_dir_lock_path(&self) -> PathBuf {
+        let mut file_name = self
+            .database_dir
+            .file_name()
+            .map(|name| name.to_os_string())
+            .unwrap_or_default();
+        file_name.push(".lock");
+        self.database_dir.with_file_name(file_name)
+    }
+
+    ///
+    /// Acquire the exclusive advisory lock of the database directory
+    ///
+    /// Keeps two instances, also of separate processes, from sharing one database directory.
+    /// The lock is released when the returned file is dropped.
+    ///
+    /// Returns [PgEmbedError::DataDirInUse] right away if the lock is held by another instance.
+    ///
+    pub fn lock_database_dir(&self) -> PgResult<std::fs::File> {
+        let lock_path = self.database_dir_lock_path();
+        let file = std::fs::OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(&lock_path)
+            .map_err(|e| PgEmbedError::DataDirLockError {
+                e,
+                path: lock_path.clone(),
+            })?;
+        match FileExt::try_lock(&file) {
+            Ok(()) => Ok(file),
+            Err(fs4::TryLockError::WouldBlock) => Err(PgEmbedError::DataDirInUse {
+                path: self.database_dir.clone(),
+            }),
+            Err(fs4::TryLockError::Error(e)) => {
+                Err(PgEmbedError::DataDirLockError { e, path: lock_path })
+            }
+        }
+    }
+
+    ///
+    /// Set the receiver of download and unpack status reports
+    ///
+    pub fn set_status_sink(&mut self, status_sink: Option<PgStatusSink>) {
+        self.status_sink = status_sink;
+    }
+
+    ///
+    /// Fetch settings reporting the download progress to the status sink
+    ///
+    fn fetch_settings_reporting(&self) -> PgFetchSettings {
+        let mut fetch_settings = self.fetch_settings.clone();
+        if let Some(sink) = self.status_sink.clone() {
+            let on_progress = fetch_settings.on_progress.take();
+            fetch_settings.on_progress = Some(ProgressCallback::new(move |progress| {
+                let percent = progress
+                    .total
+                    .filter(|total| *total > 0)
+                    .map(|total| progress.downloaded as f32 * 100.0 / total as f32);
+                sink.report(PgStatusReport {
+                    phase: PgPhase::Downloading,
+                    percent,
+                    message: "Downloading postgresql binaries".to_string(),
+                });
+                if let Some(on_progress) = &on_progress {
+                    on_progress.report(progress);
+                }
+            }));
+        }
+        fetch_settings
+    }
 
-        lock.insert(self.cache_dir.clone(), PgAcquisitionStatus::Finished);
+    ///
+    /// Verify that the cached postgres executable can run on this machine
+    ///
+    /// Returns [PgEmbedError::ArchitectureMismatch] if the executable is built for another cpu
+    /// architecture, unknown executable formats are accepted.
+    ///
+    pub fn verify_architecture(&self) -> PgResult<()> {
+        let mut header = Vec::with_capacity(4096);
+        std::fs::File::open(&self.postgres_exe)
+            .and_then(|file| file.take(4096).read_to_end(&mut header))
+            .map_err(|e| PgEmbedError::ReadFileError {
+                path: self.postgres_exe.clone(),
+                e,
+            })?;
+        let host = Architecture::default();
+        match Architecture::from_binary_header(&header) {
+            Some(found) if !host.can_execute(found) => Err(PgEmbedError::ArchitectureMismatch {
+                expected: host,
+                found,
+                path: self.postgres_exe.clone(),
+            }),
+            _ => Ok(()),
+        }
+    }
+
+    ///
+    /// Verify that binaries fetched with [PgFetchSettings::with_postgis] provide the `postgis`
+    /// extension
+    ///
+    fn verify_postgis(&self) -> PgResult<()> {
+        if !self.fetch_settings.postgis {
+            return Ok(());
+        }
+        let extension_dir = PgExtensionInstaller::new(&self.cache_dir).extension_dir()?;
+        if !extension_dir.join("postgis.control").is_file() {
+            return Err(PgEmbedError::ExtensionUnavailable {
+                name: "postgis".to_string(),
+                version: self.fetch_settings.version,
+            });
+        }
         Ok(())
     }
 
+    ///
+    /// Export the cached postgresql binaries as a portable bundle
+    ///
+    /// Packages the binaries of the configured operating system, architecture and version into
+    /// the single archive `bundle_path`, which can be restored with [PgAccess::import_bundle]
+    /// on another machine (*e.g. to pre-seed the cache of air-gapped test environments*).
+    /// Only the package directories and the cache manifest are included, not the templates or
+    /// snapshots kept in the cache directory.
+    ///
+    pub async fn export_bundle(&self, bundle_path: &Path) -> PgResult<()> {
+        self.maybe_acquire_postgres().await?;
+        let acquisition_lock = self.acquisition_lock();
+        let _lock = acquisition_lock.lock().await;
+        let _file_lock = self.lock_cache_dir().await?;
+        let cache_dir = self.cache_dir.clone();
+        let bundle_path = bundle_path.to_path_buf();
+        pg_archive::run_blocking(move || {
+            let mut entries = PG_PACKAGE_DIRS.to_vec();
+            entries.push(CACHE_MANIFEST_FILE_NAME);
+            pg_archive::archive_entries(
+                &cache_dir,
+                &entries,
+                &bundle_path,
+                PgCompression::default(),
+            )
+        })
+        .await
+    }
+
+    ///
+    /// Import postgresql binaries from a bundle created by [PgAccess::export_bundle]
+    ///
+    /// The bundle is unpacked into the cache directory, afterwards the binaries are checked
+    /// to be complete, executable on this machine and of the configured major version.
+    ///
+    pub async fn import_bundle(&self, bundle_path: &Path) -> PgResult<()> {
+        let acquisition_lock = self.acquisition_lock();
+        let _lock = acquisition_lock.lock().await;
+        let _file_lock = self.lock_cache_dir().await?;
+        self.remove_partial_cache().await?;
+        let cache_dir = self.cache_dir.clone();
+        let bundle_path = bundle_path.to_path_buf();
+        pg_archive::run_blocking(move || pg_archive::extract_archive(&bundle_path, &cache_dir))
+            .await?;
+        if !self.pg_executables_exist()? {
+            return Err(PgEmbedError::InvalidPgPackage);
+        }
+        self.verify_architecture()?;
+        let version = match self.executable_version().await {
+            Some(version) if version.major() == self.fetch_settings.version.major() => version,
+            Some(version) => {
+                self.remove_partial_cache().await?;
+                return Err(PgEmbedError::InvalidPgVersion(format!(
+                    "the bundle contains postgresql {}, expected postgresql {}",
+                    version,
+                    self.fetch_settings.version.major()
+                )));
+            }
+            None => {
+                self.remove_partial_cache().await?;
+                return Err(PgEmbedError::InvalidPgPackage);
+            }
+        };
+        self.verify_postgis()?;
+        self.mark_cache_complete(&version)
+    }
+
+    ///
+    /// Install the extensions of the archive `archive_path` into the cached binaries
+    ///
+    /// The binaries are acquired first if needed. The extensions must be built for the
+    /// configured postgresql version, see [PgExtensionInstaller].
+    ///
+    /// Returns the names of the installed extensions.
+    ///
+    pub async fn install_extension(&self, archive_path: &Path) -> PgResult<Vec<String>> {
+        self.maybe_acquire_postgres().await?;
+        let acquisition_lock = self.acquisition_lock();
+        let _lock = acquisition_lock.lock().await;
+        let _file_lock = self.lock_cache_dir().await?;
+        let installer = PgExtensionInstaller::new(&self.cache_dir);
+        let archive_path = archive_path.to_path_buf();
+        pg_archive::run_blocking(move || installer.install(&archive_path)).await
+    }
+
+    ///
+    /// Install the pgvector extension into the cached binaries
+    ///
+    /// Downloads the prebuilt archive of [PgVectorSettings::archive_url] or compiles the
+    /// source release, see [crate::pg_vector]. The download is checked against
+    /// [PgVectorSettings::expected_sha256], a source release without a known hash is refused.
+    ///
+    #[cfg(feature = "pgvector")]
+    pub async fn install_pgvector(&self, settings: &PgVectorSettings) -> PgResult<()> {
+        self.maybe_acquire_postgres().await?;
+        let acquisition_lock = self.acquisition_lock();
+        let _lock = acquisition_lock.lock().await;
+        let _file_lock = self.lock_cache_dir().await?;
+        let pg_version = self.fetch_settings.version;
+        let prebuilt_url = settings.prebuilt_url(&pg_version, &self.fetch_settings.platform());
+        let url = prebuilt_url
+            .clone()
+            .unwrap_or_else(|| settings.source_url());
+        log::info!("Installing pgvector {} from {}", settings.version, url);
+        let content = self
+            .fetch_settings
+            .client()?
+            .get(&url)
+            .send()
+            .await
+            .and_then(|response| response.error_for_status())
+            .map_err(crate::pg_fetch::download_failure)?
+            .bytes()
+            .await
+            .map_err(crate::pg_fetch::download_failure)?;
+        match settings.expected_sha256() {
+            Some(sha256) => pg_vector::verify_sha256(&url, &content, &sha256)?,
+            None if prebuilt_url.is_none() => {
+                return Err(PgEmbedError::ExtensionBuildFailure {
+                    name: "vector".to_string(),
+                    reason: format!(
+                        "no sha256 hash is known for pgvector {}, set PgVectorSettings::sha256",
+                        settings.version
+                    ),
+                })
+            }
+            None => {}
+        }
+
+        let staging_dir = self.cache_dir.join(PGVECTOR_STAGING_DIR_NAME);
+        pg_extension::remove_staging_dir(&staging_dir)?;
+        std::fs::create_dir_all(&staging_dir).map_err(|e| PgEmbedError::DirCreationError {
+            dir: staging_dir.clone(),
+            e,
+        })?;
+        let archive = staging_dir.join("pgvector.tar.gz");
+        std::fs::write(&archive, &content).map_err(|e| PgEmbedError::WriteFileError {
+            path: archive.clone(),
+            e,
+        })?;
+        let installer = PgExtensionInstaller::new(&self.cache_dir);
+        let cache_dir = self.cache_dir.clone();
+        let settings = settings.clone();
+        let build_dir = staging_dir.clone();
+        let result = pg_archive::run_blocking(move || {
+            if prebuilt_url.is_some() {
+                return installer.install(&archive);
+            }
+            let source_dir = build_dir.join("source");
+            let package_dir = build_dir.join("package");
+            pg_archive::extract_archive(&archive, &source_dir)?;
+            pg_vector::build_package(
+                &settings,
+                &pg_version,
+                &cache_dir,
+                &source_dir,
+                &package_dir,
+            )?;
+            installer.install_dir(&package_dir)
+        })
+        .await;
+        pg_extension::remove_staging_dir(&staging_dir)?;
+        result.map(|_| ())
+    }
+
     ///
     /// Check if postgresql executables are already cached
     ///
+    /// The binaries count as cached only if their acquisition has been completed.
+    ///
     pub fn pg_executables_cached(&self) -> PgResult<bool> {
-        Self::path_exists(self.init_db_exe.as_path())
+        Ok(
+            Self::path_exists(&self.cache_dir.join(CACHE_COMPLETE_FILE_NAME))?
+                && self.pg_executables_exist()?,
+        )
+    }
+
+    ///
+    /// Path of the bundled tool `name` (*e.g. `pgbench` or `pg_controldata`*) in the cache
+    /// directory, the `.exe` extension is added on windows
+    ///
+    /// Returns `None` if `name` is not a plain file name or the package contains no such tool.
+    ///
+    pub fn tool_path(&self, name: &str) -> Option<PathBuf> {
+        let mut components = Path::new(name).components();
+        match (components.next(), components.next()) {
+            (Some(std::path::Component::Normal(_)), None) => {}
+            _ => return None,
+        }
+        #[cfg(not(target_os = "windows"))]
+        let tool = self.cache_dir.join("bin").join(name);
+        #[cfg(target_os = "windows")]
+        let tool = self.cache_dir.join("bin").join(format!("{}.exe", name));
+        Some(tool).filter(|tool| tool.is_file())
+    }
+
+    ///
+    /// Check if the postgresql executables exist in the cache directory
+    ///
+    fn pg_executables_exist(&self) -> PgResult<bool> {
+        Ok(Self::path_exists(&self.init_db_exe)?
+            && Self::path_exists(&self.pg_ctl_exe)?
+            && Self::path_exists(&self.postgres_exe)?)
     }
 
     ///
@@ -196,6 +884,44 @@ impl PgAccess {
         Ok(file_exists)
     }
 
+    ///
+    /// Read the postgresql version of the database directory
+    ///
+    /// Returns `Ok(Some(version))` with the content of the `PG_VERSION` file (*e.g. `16`*),
+    /// `Ok(None)` if the database directory has not been initialized yet.
+    ///
+    pub fn data_dir_version(&self) -> PgResult<Option<String>> {
+        Self::read_data_dir_version(&self.database_dir)
+    }
+
+    ///
+    /// Read the postgresql version of the database directory `db_dir`, see
+    /// [PgAccess::data_dir_version]
+    ///
+    pub fn read_data_dir_version(db_dir: &Path) -> PgResult<Option<String>> {
+        let pg_version_file = db_dir.join(PG_VERSION_FILE_NAME);
+        if !pg_version_file.exists() {
+            return Ok(None);
+        }
+        let version =
+            std::fs::read_to_string(&pg_version_file).map_err(|e| PgEmbedError::ReadFileError {
+                path: pg_version_file,
+                e,
+            })?;
+        Ok(Some(version.trim().to_string()))
+    }
+
+    ///
+    /// Check if the database directory can be used with the configured postgresql version
+    ///
+    /// Returns `Ok(true)` if the database directory is compatible or has not been initialized yet.
+    ///
+    pub fn data_dir_compatible(&self) -> PgResult<bool> {
+        Ok(self
+            .data_dir_version()?
+            .is_none_or(|v| self.fetch_settings.version.is_data_dir_compatible(&v)))
+    }
+
     ///
     /// Check if file path exists
     ///
@@ -210,12 +936,13 @@ impl PgAccess {
     ///
     /// Check postgresql acquisition status
     ///
+    /// [PgAcquisitionStatus::Undefined] if no acquisition was attempted in this process,
+    /// [PgAcquisitionStatus::Failed] with the cause if the last attempt failed or was cancelled.
+    ///
     pub async fn acquisition_status(&self) -> PgAcquisitionStatus {
-        let lock = ACQUIRED_PG_BINS.lock().await;
-        let acquisition_status = lock.get(&self.cache_dir);
-        match acquisition_status {
+        match ACQUIRED_PG_BINS.get(&self.cache_dir) {
             None => PgAcquisitionStatus::Undefined,
-            Some(status) => *status,
+            Some(status) => status.clone(),
         }
     }
 
@@ -250,12 +977,15 @@ impl PgAccess {
                 e,
             }
         })?;
-        std::fs::remove_file(self.pw_file_path.as_path()).map_err(|e| {
-            PgEmbedError::PgCleanUpFailure {
-                path: self.pw_file_path.clone(),
-                e,
-            }
-        })?;
+        // there is no password file with trust authentication
+        if self.pw_file_path.exists() {
+            std::fs::remove_file(self.pw_file_path.as_path()).map_err(|e| {
+                PgEmbedError::PgCleanUpFailure {
+                    path: self.pw_file_path.clone(),
+                    e,
+                }
+            })?;
+        }
         Ok(())
     }
 
@@ -266,7 +996,8 @@ impl PgAccess {
     ///
     pub fn purge(cache_dir: &Path) -> PgResult<()> {
         if cache_dir.exists() {
-            std::fs::remove_dir_all(&cache_dir).map_err(|e| PgEmbedError::PgCleanUpFailure {
+            pg_retry::retry_blocking("removing the cache", || std::fs::remove_dir_all(cache_dir))
+                .map_err(|e| PgEmbedError::PgCleanUpFailure {
                 path: cache_dir.to_path_buf(),
                 e,
             })?;
@@ -274,6 +1005,46 @@ impl PgAccess {
         Ok(())
     }
 
+    ///
+    /// Reclaim disk space of the default binaries cache
+    ///
+    /// Removes the cached versions of all platforms violating `policy`, the last use of a
+    /// version is recorded each time its binaries are acquired. Versions being acquired
+    /// concurrently are skipped.
+    ///
+    /// Returns the removed directories.
+    ///
+    pub fn gc(policy: &PgCacheGcPolicy) -> PgResult<Vec<PathBuf>> {
+        pg_cache::gc(&Self::cache_root()?, policy, std::time::SystemTime::now())
+    }
+
+    ///
+    /// List the postgresql binaries in the default cache
+    ///
+    pub fn list_cached() -> PgResult<Vec<PgCacheEntry>> {
+        pg_cache::list_entries(&Self::cache_root()?)
+    }
+
+    ///
+    /// Remove the cached binaries of one version from the default cache
+    ///
+    /// Returns `Ok(false)` if the version is not cached or its acquisition is in progress.
+    ///
+    pub fn purge_version(
+        operating_system: OperationSystem,
+        architecture: Architecture,
+        version: PostgresVersion,
+    ) -> PgResult<bool> {
+        let path = Self::cache_root()?
+            .join(pg_cache::os_dir_name(&operating_system))
+            .join(architecture.to_string())
+            .join(version.to_string());
+        if !path.exists() {
+            return Ok(false);
+        }
+        pg_cache::remove_entry(&path)
+    }
+
     ///
     /// Clean up database directory and password file
     ///
@@ -292,30 +1063,392 @@ impl PgAccess {
     ///
     /// Returns `Ok(())` on success, otherwise returns an error.
     ///
+    /// The file is only readable by the owner.
+    ///
     pub fn create_password_file(&self, password: &[u8]) -> PgResult<()> {
-        let mut file = std::fs::File::create(self.pw_file_path.as_path()).map_err(|e| {
-            PgEmbedError::WriteFileError {
-                path: self.pw_file_path.clone(),
-                e,
+        let write_error = |e| PgEmbedError::WriteFileError {
+            path: self.pw_file_path.clone(),
+            e,
+        };
+        let mut options = std::fs::OpenOptions::new();
+        options.write(true).create(true).truncate(true);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+            options.mode(0o600);
+            // the mode only applies to new files
+            if self.pw_file_path.exists() {
+                std::fs::set_permissions(
+                    &self.pw_file_path,
+                    std::fs::Permissions::from_mode(0o600),
+                )
+                .map_err(write_error)?;
             }
-        })?;
-        file.write(password)
-            .map_err(|e| PgEmbedError::WriteFileError {
-                path: self.pw_file_path.clone(),
-                e,
-            })?;
+        }
+        let mut file = options.open(&self.pw_file_path).map_err(write_error)?;
+        file.write_all(password).map_err(write_error)?;
         Ok(())
     }
 
+    ///
+    /// Remove the database password file, if it exists
+    ///
+    /// Returns `Ok(())` on success, otherwise returns an error.
+    ///
+    pub fn remove_password_file(&self) -> PgResult<()> {
+        match std::fs::remove_file(&self.pw_file_path) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => {
+                Err(PgEmbedError::PgCleanUpFailure {
+                    path: self.pw_file_path.clone(),
+                    e,
+                })
+            }
+            _ => Ok(()),
+        }
+    }
+
+    ///
+    /// Mark the bootstrap sql of a new database cluster as pending
+    ///
+    pub fn mark_bootstrap_pending(&self) -> PgResult<()> {
+        let path = self.database_dir.join(BOOTSTRAP_PENDING_FILE_NAME);
+        std::fs::write(&path, b"").map_err(|e| PgEmbedError::WriteFileError { path, e })
+    }
+
+    ///
+    /// Check if the bootstrap sql of the database cluster has not run yet
+    ///
+    pub fn bootstrap_pending(&self) -> bool {
+        self.database_dir.join(BOOTSTRAP_PENDING_FILE_NAME).exists()
+    }
+
+    ///
+    /// Names of the bootstrap sql scripts which already ran, recorded in the bootstrap marker
+    ///
+    pub fn bootstrap_completed(&self) -> PgResult<Vec<String>> {
+        let path = self.database_dir.join(BOOTSTRAP_PENDING_FILE_NAME);
+        match std::fs::read_to_string(&path) {
+            Ok(content) => Ok(content
+                .lines()
+                .filter(|line| !line.is_empty())
+                .map(str::to_string)
+                .collect()),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Vec::new()),
+            Err(e) => Err(PgEmbedError::ReadFileError { path, e }),
+        }
+    }
+
+    ///
+    /// Record in the bootstrap marker that the bootstrap sql script `name` ran, so it is not
+    /// run again if a later script fails
+    ///
+    pub fn mark_bootstrap_completed(&self, name: &str) -> PgResult<()> {
+        use std::io::Write;
+
+        let path = self.database_dir.join(BOOTSTRAP_PENDING_FILE_NAME);
+        std::fs::OpenOptions::new()
+            .append(true)
+            .open(&path)
+            .and_then(|mut marker| writeln!(marker, "{}", name))
+            .map_err(|e| PgEmbedError::WriteFileError { path, e })
+    }
+
+    ///
+    /// Remove the bootstrap marker after the bootstrap sql ran
+    ///
+    pub fn clear_bootstrap_pending(&self) -> PgResult<()> {
+        let path = self.database_dir.join(BOOTSTRAP_PENDING_FILE_NAME);
+        match std::fs::remove_file(&path) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => {
+                Err(PgEmbedError::PgCleanUpFailure { path, e })
+            }
+            _ => Ok(()),
+        }
+    }
+
+    ///
+    /// Remove the `postmaster.pid` file of a server which is not running anymore
+    ///
+    pub fn remove_postmaster_pid_file(&self) -> PgResult<()> {
+        PgServerProcess::remove_pid_file(&self.database_dir)
+    }
+
     ///
     /// Create synchronous pg_ctl stop command
     ///
     pub fn stop_db_command_sync(&self, database_dir: &Path) -> PgCommandSync {
-        let pg_ctl_executable = self.pg_ctl_exe.to_str().unwrap();
-        let mut command = Box::new(Cell::new(std::process::Command::new(pg_ctl_executable)));
-        command
-            .get_mut()
-            .args(["stop", "-w", "-D", database_dir.to_str().unwrap()]);
+        let mut command = Box::new(Cell::new(std::process::Command::new(&self.pg_ctl_exe)));
+        command.get_mut().args(pg_commands::stop_db_args(
+            database_dir,
+            PgShutdownMode::Fast,
+        ));
         command
     }
+
+    ///
+    /// Kill the postmaster and its child processes
+    ///
+    /// Last resort if the server does not stop, the process id is read from the
+    /// `postmaster.pid` file of the database directory.
+    ///
+    /// Returns `Ok(false)` if no server process is known, `Ok(true)` if it was killed.
+    ///
+    pub fn kill_postmaster(&self) -> PgResult<bool> {
+        let process = match self.server_process()? {
+            Some(process) => process,
+            None => return Ok(false),
+        };
+        log::warn!("Killing postgresql server process {}", process.pid());
+        process.kill().map_err(|e| PgEmbedError::PgError {
+            message: format!("failed to kill postgresql server process {}", process.pid()),
+            source: Box::new(e),
+        })?;
+        Ok(true)
+    }
+
+    ///
+    /// Postmaster process of the database directory
+    ///
+    /// Returns `Ok(None)` if no server process is known.
+    ///
+    pub fn server_process(&self) -> PgResult<Option<PgServerProcess>> {
+        PgServerProcess::from_database_dir(&self.database_dir)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn database_dir_is_locked() -> Result<(), PgEmbedError> {
+        let base = std::env::temp_dir().join(format!("pg_embed_lock_{}", std::process::id()));
+        let pg_access = PgAccess::new(
+            &PgFetchSettings::default(),
+            &base.join("db"),
+            Some(&base.join("cache")),
+        )
+        .await?;
+        assert_eq!(base.join("db.lock"), pg_access.database_dir_lock_path());
+        let lock = pg_access.lock_database_dir()?;
+        assert!(matches!(
+            pg_access.lock_database_dir(),
+            Err(PgEmbedError::DataDirInUse { .. })
+        ));
+        drop(lock);
+        pg_access.lock_database_dir()?;
+        std::fs::remove_dir_all(&base).unwrap();
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn failed_acquisition_is_reported() -> Result<(), PgEmbedError> {
+        let base = std::env::temp_dir().join(format!("pg_embed_access_{}", std::process::id()));
+        let fetch_settings = PgFetchSettings {
+            host: "ftp://localhost".to_string(),
+            ..Default::default()
+        };
+        let pg_access =
+            PgAccess::new(&fetch_settings, &base.join("db"), Some(&base.join("cache"))).await?;
+        assert_eq!(
+            PgAcquisitionStatus::Undefined,
+            pg_access.acquisition_status().await
+        );
+        // partially unpacked binaries without completion marker
+        std::fs::create_dir_all(base.join("cache").join("bin")).unwrap();
+        for exe in [
+            &pg_access.init_db_exe,
+            &pg_access.pg_ctl_exe,
+            &pg_access.postgres_exe,
+        ] {
+            std::fs::write(exe, b"").unwrap();
+        }
+        std::fs::write(base.join("cache").join("notes.txt"), b"").unwrap();
+        assert!(!pg_access.pg_executables_cached()?);
+
+        assert!(pg_access.maybe_acquire_postgres().await.is_err());
+        assert!(!base.join("cache").join("bin").exists());
+        assert!(base.join("cache").join("notes.txt").exists());
+        match pg_access.acquisition_status().await {
+            PgAcquisitionStatus::Failed(cause) => assert!(cause.contains("ftp://localhost")),
+            status => panic!("unexpected acquisition status {:?}", status),
+        }
+        std::fs::remove_dir_all(&base).unwrap();
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn unmarked_cache_is_adopted() -> Result<(), PgEmbedError> {
+        use std::os::unix::fs::PermissionsExt;
+
+        let base = std::env::temp_dir().join(format!("pg_embed_unmarked_{}", std::process::id()));
+        let fetch_settings = PgFetchSettings {
+            host: "ftp://localhost".to_string(),
+            ..Default::default()
+        };
+        let pg_access =
+            PgAccess::new(&fetch_settings, &base.join("db"), Some(&base.join("cache"))).await?;
+        // binaries cached before the completion marker was introduced
+        std::fs::create_dir_all(base.join("cache").join("bin")).unwrap();
+        for exe in [&pg_access.init_db_exe, &pg_access.pg_ctl_exe] {
+            std::fs::write(exe, b"").unwrap();
+        }
+        let script = format!(
+            "#!/bin/sh\necho 'postgres (PostgreSQL) {}.1'\n",
+            fetch_settings.version.major()
+        );
+        std::fs::write(&pg_access.postgres_exe, script).unwrap();
+        std::fs::set_permissions(
+            &pg_access.postgres_exe,
+            std::fs::Permissions::from_mode(0o755),
+        )
+        .unwrap();
+        assert!(!pg_access.pg_executables_cached()?);
+
+        pg_access.maybe_acquire_postgres().await?;
+        assert!(pg_access.pg_executables_cached()?);
+        assert!(pg_access.postgres_exe.exists());
+        let marker = std::fs::read_to_string(base.join("cache").join(CACHE_COMPLETE_FILE_NAME));
+        std::fs::remove_dir_all(&base).unwrap();
+        assert_eq!(
+            format!("{}.1.0", fetch_settings.version.major()),
+            marker.unwrap()
+        );
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn bundle_roundtrip() -> Result<(), PgEmbedError> {
+        use std::os::unix::fs::PermissionsExt;
+
+        let base = std::env::temp_dir().join(format!("pg_embed_bundle_{}", std::process::id()));
+        let source = PgAccess::new(
+            &PgFetchSettings::default(),
+            &base.join("db"),
+            Some(&base.join("source")),
+        )
+        .await?;
+        std::fs::create_dir_all(base.join("source").join("bin")).unwrap();
+        for exe in [&source.init_db_exe, &source.pg_ctl_exe] {
+            std::fs::write(exe, b"").unwrap();
+        }
+        let script = format!(
+            "#!/bin/sh\necho 'postgres (PostgreSQL) {}.1'\n",
+            source.fetch_settings.version.major()
+        );
+        std::fs::write(&source.postgres_exe, &script).unwrap();
+        std::fs::set_permissions(&source.postgres_exe, std::fs::Permissions::from_mode(0o755))
+            .unwrap();
+        source.mark_cache_complete(&source.fetch_settings.version)?;
+        std::fs::create_dir_all(base.join("source").join("templates")).unwrap();
+        std::fs::write(
+            base.join("source").join("templates").join("pg_hba.conf"),
+            b"",
+        )
+        .unwrap();
+        let bundle = base.join("bundle.tar.gz");
+        source.export_bundle(&bundle).await?;
+        assert!(bundle.is_file());
+
+        let target = PgAccess::new(
+            &PgFetchSettings::default(),
+            &base.join("db"),
+            Some(&base.join("target")),
+        )
+        .await?;
+        assert!(!target.pg_executables_cached()?);
+        target.import_bundle(&bundle).await?;
+        assert!(target.pg_executables_cached()?);
+        let postgres = std::fs::read_to_string(&target.postgres_exe);
+        let marker = std::fs::read_to_string(base.join("target").join(CACHE_COMPLETE_FILE_NAME));
+        let templates_imported = base.join("target").join("templates").exists();
+
+        // a bundle of another major version is rejected
+        let newer = PgAccess::new(
+            &PgFetchSettings {
+                version: crate::pg_fetch::PG_V16,
+                ..Default::default()
+            },
+            &base.join("db"),
+            Some(&base.join("newer")),
+        )
+        .await?;
+        let mismatch = newer.import_bundle(&bundle).await;
+        let newer_cached = newer.pg_executables_cached()?;
+
+        // a bundle without the executables is rejected
+        std::fs::create_dir_all(base.join("empty")).unwrap();
+        std::fs::write(base.join("empty").join("README"), b"").unwrap();
+        let empty_bundle = base.join("empty.tar.gz");
+        pg_archive::archive_dir(&base.join("empty"), &empty_bundle, PgCompression::default())?;
+        let other = PgAccess::new(
+            &PgFetchSettings::default(),
+            &base.join("db"),
+            Some(&base.join("other")),
+        )
+        .await?;
+        let rejected = other.import_bundle(&empty_bundle).await;
+        std::fs::remove_dir_all(&base).unwrap();
+        assert_eq!(script, postgres.unwrap());
+        assert_eq!(
+            format!("{}.1.0", source.fetch_settings.version.major()),
+            marker.unwrap()
+        );
+        assert!(!templates_imported);
+        assert!(matches!(mismatch, Err(PgEmbedError::InvalidPgVersion(_))));
+        assert!(!newer_cached);
+        assert!(matches!(rejected, Err(PgEmbedError::InvalidPgPackage)));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn bootstrap_marker() -> Result<(), PgEmbedError> {
+        let base = std::env::temp_dir().join(format!("pg_embed_bootstrap_{}", std::process::id()));
+        let pg_access = PgAccess::new(
+            &PgFetchSettings::default(),
+            &base.join("db"),
+            Some(&base.join("cache")),
+        )
+        .await?;
+        std::fs::create_dir_all(base.join("db")).unwrap();
+        assert!(!pg_access.bootstrap_pending());
+        pg_access.mark_bootstrap_pending()?;
+        assert!(pg_access.bootstrap_pending());
+        assert!(pg_access.bootstrap_completed()?.is_empty());
+        pg_access.mark_bootstrap_completed("01_schema.sql")?;
+        pg_access.mark_bootstrap_completed("02_data.sql")?;
+        assert_eq!(
+            vec!["01_schema.sql".to_string(), "02_data.sql".to_string()],
+            pg_access.bootstrap_completed()?
+        );
+        pg_access.clear_bootstrap_pending()?;
+        assert!(!pg_access.bootstrap_pending());
+        assert!(pg_access.bootstrap_completed()?.is_empty());
+        std::fs::remove_dir_all(&base).unwrap();
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn tool_paths() -> Result<(), PgEmbedError> {
+        let base = std::env::temp_dir().join(format!("pg_embed_tools_{}", std::process::id()));
+        let pg_access = PgAccess::new(
+            &PgFetchSettings::default(),
+            &base.join("db"),
+            Some(&base.join("cache")),
+        )
+        .await?;
+        std::fs::create_dir_all(base.join("cache").join("bin")).unwrap();
+        std::fs::write(&pg_access.pg_ctl_exe, b"").unwrap();
+        assert_eq!(
+            Some(pg_access.pg_ctl_exe.clone()),
+            pg_access.tool_path("pg_ctl")
+        );
+        assert_eq!(None, pg_access.tool_path("pgbench"));
+        assert_eq!(None, pg_access.tool_path("../bin/pg_ctl"));
+        assert_eq!(None, pg_access.tool_path(""));
+        std::fs::remove_dir_all(&base).unwrap();
+        Ok(())
+    }
 }
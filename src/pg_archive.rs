@@ -0,0 +1,321 @@
+//!
+//! Archive database directories
+//!
+//! Create and extract (compressed) tar archives of postgresql database clusters
+//!
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+
+use crate::pg_errors::PgEmbedError;
+use crate::pg_types::PgResult;
+
+/// Files which are not included in cluster archives
+const EXCLUDED_FILES: [&str; 5] = [
+    "postmaster.pid",
+    crate::pg_cache::CACHE_LAST_USED_FILE_NAME,
+    crate::pg_access::CACHE_LOCK_FILE_NAME,
+    crate::pg_access::CACHE_COMPLETE_FILE_NAME,
+    crate::pg_access::CACHE_MANIFEST_FILE_NAME,
+];
+
+///
+/// Archive compression
+///
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum PgCompression {
+    /// plain tar archive
+    None,
+    /// gzip compressed tar archive
+    Gzip {
+        /// compression level (*0 - 9*)
+        level: u32,
+    },
+    /// zstd compressed tar archive
+    Zstd {
+        /// compression level (*1 - 22*)
+        level: i32,
+        /// number of compression worker threads, 0 compresses on the calling thread
+        threads: u32,
+    },
+}
+
+impl Default for PgCompression {
+    fn default() -> Self {
+        PgCompression::Zstd {
+            level: 3,
+            threads: 0,
+        }
+    }
+}
+
+impl PgCompression {
+    ///
+    /// The archive file extension
+    ///
+    pub fn file_extension(&self) -> &'static str {
+        match self {
+            PgCompression::None => "tar",
+            PgCompression::Gzip { .. } => "tar.gz",
+            PgCompression::Zstd { .. } => "tar.zst",
+        }
+    }
+
+    ///
+    /// Detect the compression of an archive by its magic bytes
+    ///
+    pub fn detect(header: &[u8]) -> Self {
+        if header.starts_with(&[0x1f, 0x8b]) {
+            PgCompression::Gzip { level: 6 }
+        } else if header.starts_with(&[0x28, 0xb5, 0x2f, 0xfd]) {
+            PgCompression::Zstd {
+                level: 3,
+                threads: 0,
+            }
+        } else {
+            PgCompression::None
+        }
+    }
+}
+
+///
+/// Archive the content of `dir` into the file `archive_path`
+///
+/// Returns `Ok(())` on success, otherwise returns an error.
+///
+pub fn archive_dir(dir: &Path, archive_path: &Path, compression: PgCompression) -> PgResult<()> {
+    write_archive(dir, None, archive_path, compression)
+}
+
+///
+/// Archive the entries `names` (*files or directories*) of `dir` into the file `archive_path`
+///
+/// Missing entries are skipped.
+///
+/// Returns `Ok(())` on success, otherwise returns an error.
+///
+pub(crate) fn archive_entries(
+    dir: &Path,
+    names: &[&str],
+    archive_path: &Path,
+    compression: PgCompression,
+) -> PgResult<()> {
+    write_archive(dir, Some(names), archive_path, compression)
+}
+
+///
+/// Archive the entries `names` of `dir` into the file `archive_path`, all entries if `names`
+/// is None
+///
+fn write_archive(
+    dir: &Path,
+    names: Option<&[&str]>,
+    archive_path: &Path,
+    compression: PgCompression,
+) -> PgResult<()> {
+    let write_error = |e| PgEmbedError::WriteFileError {
+        path: archive_path.to_path_buf(),
+        e,
+    };
+    let file = BufWriter::new(File::create(archive_path).map_err(write_error)?);
+    match compression {
+        PgCompression::None => {
+            write_tar(dir, names, file)?.flush().map_err(write_error)?;
+        }
+        PgCompression::Gzip { level } => {
+            let encoder = flate2::write::GzEncoder::new(file, flate2::Compression::new(level));
+            write_tar(dir, names, encoder)?
+                .finish()
+                .and_then(|mut w| w.flush())
+                .map_err(write_error)?;
+        }
+        PgCompression::Zstd { level, threads } => {
+            let mut encoder =
+                zstd::stream::write::Encoder::new(file, level).map_err(write_error)?;
+            if threads > 0 {
+                encoder.multithread(threads).map_err(write_error)?;
+            }
+            write_tar(dir, names, encoder)?
+                .finish()
+                .and_then(|mut w| w.flush())
+                .map_err(write_error)?;
+        }
+    }
+    Ok(())
+}
+
+///
+/// Write the content of `dir` as tar archive into `writer`, only the entries `names` if set
+///
+/// Returns the writer on success, otherwise returns an error.
+///
+fn write_tar<W: Write>(dir: &Path, names: Option<&[&str]>, writer: W) -> PgResult<W> {
+    let mut builder = tar::Builder::new(writer);
+    builder.follow_symlinks(false);
+    match names {
+        None => append_dir(&mut builder, dir, Path::new(""))?,
+        Some(names) => {
+            for name in names {
+                let path = dir.join(name);
+                let read_error = |e| PgEmbedError::ReadFileError {
+                    path: path.clone(),
+                    e,
+                };
+                if path.is_dir() {
+                    builder.append_dir(name, &path).map_err(read_error)?;
+                    append_dir(&mut builder, &path, Path::new(name))?;
+                } else if path.is_file() {
+                    builder
+                        .append_path_with_name(&path, name)
+                        .map_err(read_error)?;
+                }
+            }
+        }
+    }
+    builder
+        .into_inner()
+        .map_err(|e| PgEmbedError::ReadFileError {
+            path: dir.to_path_buf(),
+            e,
+        })
+}
+
+///
+/// Recursively append the directory `dir` to the tar archive under `archive_dir`
+///
+fn append_dir<W: Write>(
+    builder: &mut tar::Builder<W>,
+    dir: &Path,
+    archive_dir: &Path,
+) -> PgResult<()> {
+    let read_error = |e| PgEmbedError::ReadFileError {
+        path: dir.to_path_buf(),
+        e,
+    };
+    let mut entries = std::fs::read_dir(dir)
+        .map_err(read_error)?
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(read_error)?;
+    entries.sort_by_key(|entry| entry.file_name());
+    for entry in entries {
+        let file_name = entry.file_name();
+        if EXCLUDED_FILES.iter().any(|excluded| file_name == *excluded) {
+            continue;
+        }
+        let path = entry.path();
+        let archive_path = archive_dir.join(&file_name);
+        let file_type = entry.file_type().map_err(read_error)?;
+        if file_type.is_dir() {
+            builder
+                .append_dir(&archive_path, &path)
+                .map_err(read_error)?;
+            append_dir(builder, &path, &archive_path)?;
+        } else {
+            builder
+                .append_path_with_name(&path, &archive_path)
+                .map_err(read_error)?;
+        }
+    }
+    Ok(())
+}
+
+///
+/// Extract the archive `archive_path` into `target_dir`
+///
+/// The archive compression is detected automatically.
+///
+/// Returns `Ok(())` on success, otherwise returns an error.
+///
+pub fn extract_archive(archive_path: &Path, target_dir: &Path) -> PgResult<()> {
+    let read_error = |e| PgEmbedError::ReadFileError {
+        path: archive_path.to_path_buf(),
+        e,
+    };
+    let mut header = [0u8; 4];
+    let header_len = File::open(archive_path)
+        .and_then(|mut file| file.read(&mut header))
+        .map_err(read_error)?;
+    let file = BufReader::new(File::open(archive_path).map_err(read_error)?);
+    let reader: Box<dyn Read> = match PgCompression::detect(&header[..header_len]) {
+        PgCompression::None => Box::new(file),
+        PgCompression::Gzip { .. } => Box::new(flate2::read::GzDecoder::new(file)),
+        PgCompression::Zstd { .. } => {
+            Box::new(zstd::stream::read::Decoder::with_buffer(file).map_err(read_error)?)
+        }
+    };
+    std::fs::create_dir_all(target_dir).map_err(|e| PgEmbedError::DirCreationError {
+        dir: target_dir.to_path_buf(),
+        e,
+    })?;
+    let mut archive = tar::Archive::new(reader);
+    archive.set_preserve_permissions(true);
+    archive.set_preserve_mtime(true);
+    archive
+        .unpack(target_dir)
+        .map_err(PgEmbedError::UnpackFailure)?;
+    Ok(())
+}
+
+///
+/// Run an archive operation on the blocking thread pool
+///
+pub(crate) async fn run_blocking<F, T>(operation: F) -> PgResult<T>
+where
+    F: FnOnce() -> PgResult<T> + Send + 'static,
+    T: Send + 'static,
+{
+    tokio::task::spawn_blocking(operation)
+        .await
+        .map_err(|e| PgEmbedError::PgError {
+            source: Box::new(e),
+            message: "archive task failed".to_string(),
+        })?
+}
+
+///
+/// Default archive file path for `name` inside `dir`
+///
+pub fn archive_path(dir: &Path, name: &str, compression: PgCompression) -> PathBuf {
+    dir.join(format!("{}.{}", name, compression.file_extension()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn archive_round_trip() -> Result<(), PgEmbedError> {
+        let base = std::env::temp_dir().join(format!("pg_embed_archive_{}", std::process::id()));
+        let source = base.join("source");
+        std::fs::create_dir_all(source.join("base")).unwrap();
+        std::fs::write(source.join("PG_VERSION"), "16\n").unwrap();
+        std::fs::write(source.join("postmaster.pid"), "1").unwrap();
+        std::fs::write(source.join("base").join("1"), vec![7u8; 4096]).unwrap();
+
+        for compression in [
+            PgCompression::None,
+            PgCompression::Gzip { level: 1 },
+            PgCompression::Zstd {
+                level: 1,
+                threads: 0,
+            },
+        ] {
+            let archive = archive_path(&base, "cluster", compression);
+            archive_dir(&source, &archive, compression)?;
+            let target = base.join(format!("target_{}", compression.file_extension()));
+            extract_archive(&archive, &target)?;
+            assert_eq!(
+                "16\n",
+                std::fs::read_to_string(target.join("PG_VERSION")).unwrap()
+            );
+            assert_eq!(
+                4096,
+                std::fs::read(target.join("base").join("1")).unwrap().len()
+            );
+            assert!(!target.join("postmaster.pid").exists());
+        }
+
+        std::fs::remove_dir_all(&base).unwrap();
+        Ok(())
+    }
+}
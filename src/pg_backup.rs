@@ -0,0 +1,353 @@
+//!
+//! Scheduled database backups
+//!
+//! Dumps a database of a running instance into a rotated backup directory, at a fixed interval
+//! or following a cron expression
+//!
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use chrono::{DateTime, Local};
+use tokio::task::JoinHandle;
+
+use crate::command_executor::AsyncCommand;
+use crate::pg_commands::{PgClientConnection, PgCommand};
+use crate::pg_errors::PgEmbedError;
+use crate::pg_os_user::PgOsUser;
+use crate::pg_types::PgResult;
+
+const BACKUP_FILE_EXTENSION: &str = "dump";
+
+///
+/// Backup settings
+///
+#[derive(Debug, Clone)]
+pub struct PgBackupSettings {
+    /// Directory the backups are written to
+    pub backup_dir: PathBuf,
+    /// Database to back up
+    pub database_name: String,
+    /// When backups are taken
+    pub schedule: PgBackupSchedule,
+    /// Number of backups to keep, older backups are removed. `0` keeps all backups
+    pub keep: usize,
+    /// pg_dump process timeout
+    pub timeout: Option<Duration>,
+}
+
+///
+/// Backup schedule
+///
+#[derive(Debug, Clone)]
+pub enum PgBackupSchedule {
+    /// Time between two backups, the first backup is taken after one interval
+    Interval(Duration),
+    /// Cron expression evaluated in local time, see [PgBackupSchedule::cron]
+    Cron(Box<cron::Schedule>),
+}
+
+impl PgBackupSchedule {
+    ///
+    /// Parse a cron expression
+    ///
+    /// The expression has the fields `sec min hour day-of-month month day-of-week [year]`,
+    /// e.g. `0 30 2 * * *` takes a backup every night at 2:30.
+    ///
+    pub fn cron(expression: &str) -> PgResult<Self> {
+        cron::Schedule::from_str(expression)
+            .map(|schedule| PgBackupSchedule::Cron(Box::new(schedule)))
+            .map_err(|e| PgEmbedError::InvalidBackupSchedule {
+                expression: expression.to_string(),
+                reason: e.to_string(),
+            })
+    }
+
+    ///
+    /// Time of the first backup after `after`
+    ///
+    /// Returns `None` if the schedule has no upcoming backup time.
+    ///
+    fn next_after(&self, after: DateTime<Local>) -> Option<DateTime<Local>> {
+        match self {
+            PgBackupSchedule::Interval(interval) => {
+                Some(after + chrono::Duration::from_std(*interval).ok()?)
+            }
+            PgBackupSchedule::Cron(schedule) => schedule.after(&after).next(),
+        }
+    }
+}
+
+impl From<Duration> for PgBackupSchedule {
+    fn from(interval: Duration) -> Self {
+        PgBackupSchedule::Interval(interval)
+    }
+}
+
+///
+/// Called with the path of the created backup
+///
+pub type BackupSuccessHook = Arc<dyn Fn(&Path) + Send + Sync>;
+///
+/// Called with the error of a failed backup
+///
+pub type BackupFailureHook = Arc<dyn Fn(&PgEmbedError) + Send + Sync>;
+
+///
+/// Periodic pg_dump backups with rotation
+///
+/// Created by [crate::postgres::PgEmbed::backup_scheduler].
+///
+pub struct PgBackupScheduler {
+    settings: PgBackupSettings,
+    pg_dump_exe: PathBuf,
+    connection: PgClientConnection,
+    os_user: Option<PgOsUser>,
+    on_success: Option<BackupSuccessHook>,
+    on_failure: Option<BackupFailureHook>,
+}
+
+impl PgBackupScheduler {
+    pub(crate) fn new(
+        settings: PgBackupSettings,
+        pg_dump_exe: PathBuf,
+        connection: PgClientConnection,
+        os_user: Option<PgOsUser>,
+    ) -> Self {
+        PgBackupScheduler {
+            settings,
+            pg_dump_exe,
+            connection,
+            os_user,
+            on_success: None,
+            on_failure: None,
+        }
+    }
+
+    ///
+    /// Set the hook called after each successful backup
+    ///
+    pub fn set_on_success(&mut self, hook: impl Fn(&Path) + Send + Sync + 'static) {
+        self.on_success = Some(Arc::new(hook));
+    }
+
+    ///
+    /// Set the hook called after each failed backup
+    ///
+    pub fn set_on_failure(&mut self, hook: impl Fn(&PgEmbedError) + Send + Sync + 'static) {
+        self.on_failure = Some(Arc::new(hook));
+    }
+
+    ///
+    /// Take a backup immediately and rotate the backup directory
+    ///
+    /// Returns the path of the created backup. The hooks are called with the result.
+    ///
+    pub async fn backup_now(&self) -> PgResult<PathBuf> {
+        let result = self.backup().await;
+        match &result {
+            Ok(path) => {
+                if let Some(on_success) = &self.on_success {
+                    on_success(path);
+                }
+            }
+            Err(e) => {
+                log::error!("Backup of {} failed: {}", self.settings.database_name, e);
+                if let Some(on_failure) = &self.on_failure {
+                    on_failure(e);
+                }
+            }
+        }
+        result
+    }
+
+    ///
+    /// Start taking backups according to the schedule in a background task
+    ///
+    /// Backups continue until the returned handle is stopped or dropped. Backup times missed
+    /// while a backup was running are skipped.
+    ///
+    pub fn start(self) -> PgBackupHandle {
+        let task = tokio::spawn(async move {
+            let mut last = Local::now();
+            loop {
+                let now = Local::now();
+                let next = match self.settings.schedule.next_after(last.max(now)) {
+                    Some(next) => next,
+                    None => {
+                        log::warn!(
+                            "Backup schedule of {} has no upcoming backup time",
+                            self.settings.database_name
+                        );
+                        break;
+                    }
+                };
+                tokio::time::sleep((next - now).to_std().unwrap_or_default()).await;
+                last = next;
+                // failures are reported through the hooks
+                let _ = self.backup_now().await;
+            }
+        });
+        PgBackupHandle { task }
+    }
+
+    async fn backup(&self) -> PgResult<PathBuf> {
+        let backup_dir = &self.settings.backup_dir;
+        std::fs::create_dir_all(backup_dir).map_err(|e| PgEmbedError::DirCreationError {
+            dir: backup_dir.clone(),
+            e,
+        })?;
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis();
+        let file_name = backup_file_name(&self.settings.database_name, timestamp);
+        let backup_path = backup_dir.join(&file_name);
+        // dump into a temporary file first, so an interrupted dump is never taken for a backup
+        let partial_path = backup_dir.join(format!("{}.partial", file_name));
+
+        let mut executor = PgCommand::dump_db_executor(
+            &self.pg_dump_exe,
+            &self.connection,
+            &self.settings.database_name,
+            &partial_path,
+            self.os_user.as_ref(),
+        )?;
+        if let Err(e) = executor.execute(self.settings.timeout).await {
+            let _ = std::fs::remove_file(&partial_path);
+            return Err(e);
+        }
+        std::fs::rename(&partial_path, &backup_path).map_err(|e| PgEmbedError::WriteFileError {
+            e,
+            path: backup_path.clone(),
+        })?;
+        rotate_backups(backup_dir, &self.settings.database_name, self.settings.keep)?;
+        Ok(backup_path)
+    }
+}
+
+///
+/// Handle of a running backup schedule
+///
+pub struct PgBackupHandle {
+    task: JoinHandle<()>,
+}
+
+impl PgBackupHandle {
+    ///
+    /// Stop taking backups
+    ///
+    pub fn stop(self) {
+        self.task.abort();
+    }
+}
+
+impl Drop for PgBackupHandle {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+///
+/// Backup file name, the zero padded timestamp keeps the names sortable
+///
+fn backup_file_name(database_name: &str, timestamp: u128) -> String {
+    format!(
+        "{}-{:020}.{}",
+        database_name, timestamp, BACKUP_FILE_EXTENSION
+    )
+}
+
+///
+/// Remove all but the newest `keep` backups of `database_name`
+///
+fn rotate_backups(backup_dir: &Path, database_name: &str, keep: usize) -> PgResult<()> {
+    if keep == 0 {
+        return Ok(());
+    }
+    let prefix = format!("{}-", database_name);
+    let suffix = format!(".{}", BACKUP_FILE_EXTENSION);
+    let entries = std::fs::read_dir(backup_dir).map_err(|e| PgEmbedError::ReadFileError {
+        e,
+        path: backup_dir.to_path_buf(),
+    })?;
+    let mut backups: Vec<PathBuf> = entries
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .filter(|path| {
+            path.file_name()
+                .and_then(|name| name.to_str())
+                .and_then(|name| name.strip_prefix(&prefix))
+                .and_then(|name| name.strip_suffix(&suffix))
+                .is_some_and(|timestamp| {
+                    !timestamp.is_empty() && timestamp.bytes().all(|b| b.is_ascii_digit())
+                })
+        })
+        .collect();
+    backups.sort();
+    let remove = backups.len().saturating_sub(keep);
+    for path in backups.into_iter().take(remove) {
+        std::fs::remove_file(&path).map_err(|e| PgEmbedError::PgCleanUpFailure { e, path })?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rotate_keeps_newest_backups() -> Result<(), PgEmbedError> {
+        let dir = std::env::temp_dir().join(format!("pg_embed_backup_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        for timestamp in [3, 1, 2, 10] {
+            std::fs::write(dir.join(backup_file_name("app", timestamp)), b"").unwrap();
+        }
+        std::fs::write(dir.join(backup_file_name("other", 0)), b"").unwrap();
+        std::fs::write(dir.join("app-notes.dump"), b"").unwrap();
+
+        rotate_backups(&dir, "app", 2)?;
+
+        let mut remaining: Vec<String> = std::fs::read_dir(&dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+            .collect();
+        remaining.sort();
+        assert_eq!(
+            vec![
+                "app-00000000000000000003.dump".to_string(),
+                "app-00000000000000000010.dump".to_string(),
+                "app-notes.dump".to_string(),
+                "other-00000000000000000000.dump".to_string(),
+            ],
+            remaining
+        );
+        std::fs::remove_dir_all(&dir).unwrap();
+        Ok(())
+    }
+
+    #[test]
+    fn backup_schedules() -> Result<(), PgEmbedError> {
+        use chrono::TimeZone;
+
+        let start = Local.with_ymd_and_hms(2026, 1, 14, 12, 0, 0).unwrap();
+        let nightly = PgBackupSchedule::cron("0 30 2 * * *")?;
+        assert_eq!(
+            Some(Local.with_ymd_and_hms(2026, 1, 15, 2, 30, 0).unwrap()),
+            nightly.next_after(start)
+        );
+        let hourly = PgBackupSchedule::from(Duration::from_secs(3600));
+        assert_eq!(
+            Some(Local.with_ymd_and_hms(2026, 1, 14, 13, 0, 0).unwrap()),
+            hourly.next_after(start)
+        );
+        let past = PgBackupSchedule::cron("0 0 0 1 1 * 2020")?;
+        assert_eq!(None, past.next_after(start));
+        assert!(matches!(
+            PgBackupSchedule::cron("every night"),
+            Err(PgEmbedError::InvalidBackupSchedule { .. })
+        ));
+        Ok(())
+    }
+}
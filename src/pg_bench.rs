@@ -0,0 +1,241 @@
+//!
+//! Benchmarks
+//!
+//! [crate::postgres::PgEmbed::pgbench] runs the bundled `pgbench` against the server, e.g. to
+//! catch performance regressions of a schema in CI:
+//!
+//! ```rust, ignore
+//! let options = PgBenchOptions {
+//!     script: Some(PathBuf::from("benches/orders.sql")),
+//!     clients: 4,
+//!     ..Default::default()
+//! };
+//! let result = pg.pgbench(&options).await?;
+//! assert!(result.tps > 500.0);
+//! ```
+//!
+use std::ffi::OsString;
+use std::path::PathBuf;
+use std::time::Duration;
+
+///
+/// Length of a benchmark run
+///
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum PgBenchDuration {
+    /// run for a fixed time, `-T`
+    Time(Duration),
+    /// run a fixed number of transactions per client, `-t`
+    Transactions(u32),
+}
+
+///
+/// pgbench options
+///
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PgBenchOptions {
+    /// database to benchmark
+    /// if set to None the maintenance database is used
+    pub database: Option<String>,
+    /// create and fill the pgbench tables before the run, replacing existing ones
+    pub initialize: bool,
+    /// scale factor of the pgbench tables, 100000 `pgbench_accounts` rows per unit
+    pub scale: u32,
+    /// number of concurrent clients
+    pub clients: u32,
+    /// number of worker threads
+    pub jobs: u32,
+    /// length of the run
+    pub duration: PgBenchDuration,
+    /// custom transaction script, e.g. of the queries of an application
+    /// if set to None the builtin `tpcb-like` transaction is run
+    pub script: Option<PathBuf>,
+}
+
+impl Default for PgBenchOptions {
+    fn default() -> Self {
+        PgBenchOptions {
+            database: None,
+            initialize: true,
+            scale: 1,
+            clients: 1,
+            jobs: 1,
+            duration: PgBenchDuration::Transactions(1000),
+            script: None,
+        }
+    }
+}
+
+impl PgBenchOptions {
+    ///
+    /// pgbench arguments initializing the tables of `database`
+    ///
+    pub(crate) fn init_args(&self, database: &str) -> Vec<OsString> {
+        vec![
+            "-i".into(),
+            "-q".into(),
+            format!("--scale={}", self.scale).into(),
+            database.into(),
+        ]
+    }
+
+    ///
+    /// pgbench arguments of the benchmark run on `database`
+    ///
+    pub(crate) fn run_args(&self, database: &str) -> Vec<OsString> {
+        let mut args: Vec<OsString> = vec![
+            format!("--client={}", self.clients).into(),
+            format!("--jobs={}", self.jobs).into(),
+        ];
+        match self.duration {
+            PgBenchDuration::Time(time) => {
+                args.push(format!("--time={}", time.as_secs().max(1)).into())
+            }
+            PgBenchDuration::Transactions(transactions) => {
+                args.push(format!("--transactions={}", transactions).into())
+            }
+        }
+        if let Some(script) = &self.script {
+            let mut arg = OsString::from("--file=");
+            arg.push(script);
+            args.push(arg);
+        }
+        args.push(database.into());
+        args
+    }
+}
+
+///
+/// Result of a benchmark run
+///
+#[derive(Debug, Clone, PartialEq)]
+pub struct PgBenchResult {
+    /// transactions per second, without the time to establish the connections
+    pub tps: f64,
+    /// number of processed transactions
+    pub transactions: u64,
+    /// number of failed transactions, reported since postgresql 15
+    pub failed_transactions: u64,
+    /// average transaction latency
+    pub latency_average: Option<Duration>,
+    /// standard deviation of the transaction latency
+    pub latency_stddev: Option<Duration>,
+    /// the report of pgbench
+    pub output: String,
+}
+
+impl PgBenchResult {
+    ///
+    /// Parse the report printed by pgbench
+    ///
+    /// Returns `None` if the report contains no throughput.
+    ///
+    pub(crate) fn parse(output: &str) -> Option<Self> {
+        let mut tps = None;
+        let mut transactions = 0;
+        let mut failed_transactions = 0;
+        let mut latency_average = None;
+        let mut latency_stddev = None;
+        for line in output.lines().map(str::trim) {
+            if let Some(value) = line.strip_prefix("tps = ") {
+                let value = value.split_whitespace().next()?.parse().ok();
+                // before postgresql 14 the report ends with the tps excluding connections
+                if tps.is_none() || line.contains("excluding") {
+                    tps = value;
+                }
+            } else if let Some(value) =
+                line.strip_prefix("number of transactions actually processed: ")
+            {
+                transactions = value.split('/').next()?.trim().parse().ok()?;
+            } else if let Some(value) = line.strip_prefix("number of failed transactions: ") {
+                failed_transactions = value.split_whitespace().next()?.parse().ok()?;
+            } else if let Some(value) = line.strip_prefix("latency average = ") {
+                latency_average = parse_millis(value);
+            } else if let Some(value) = line.strip_prefix("latency stddev = ") {
+                latency_stddev = parse_millis(value);
+            }
+        }
+        Some(PgBenchResult {
+            tps: tps?,
+            transactions,
+            failed_transactions,
+            latency_average,
+            latency_stddev,
+            output: output.to_string(),
+        })
+    }
+}
+
+///
+/// Parse a latency like `1.508 ms`
+///
+fn parse_millis(value: &str) -> Option<Duration> {
+    let millis: f64 = value.strip_suffix("ms")?.trim().parse().ok()?;
+    Some(Duration::from_secs_f64(millis.max(0.0) / 1000.0))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pgbench_arguments() {
+        let options = PgBenchOptions {
+            scale: 10,
+            clients: 4,
+            jobs: 2,
+            duration: PgBenchDuration::Time(Duration::from_secs(30)),
+            script: Some(PathBuf::from("orders.sql")),
+            ..Default::default()
+        };
+        assert_eq!(
+            vec!["-i", "-q", "--scale=10", "app"],
+            options.init_args("app")
+        );
+        assert_eq!(
+            vec![
+                "--client=4",
+                "--jobs=2",
+                "--time=30",
+                "--file=orders.sql",
+                "app"
+            ],
+            options.run_args("app")
+        );
+        assert_eq!(
+            vec!["--client=1", "--jobs=1", "--transactions=1000", "app"],
+            PgBenchOptions::default().run_args("app")
+        );
+    }
+
+    #[test]
+    fn parse_pgbench_report() {
+        let report = "transaction type: <builtin: TPC-B (sort of)>
+scaling factor: 1
+number of clients: 1
+number of transactions per client: 10
+number of transactions actually processed: 10/10
+number of failed transactions: 0 (0.000%)
+latency average = 1.508 ms
+initial connection time = 3.211 ms
+tps = 663.137516 (without initial connection time)
+";
+        let result = PgBenchResult::parse(report).unwrap();
+        assert_eq!(663.137516, result.tps);
+        assert_eq!(10, result.transactions);
+        assert_eq!(0, result.failed_transactions);
+        assert_eq!(Some(Duration::from_micros(1508)), result.latency_average);
+        assert_eq!(None, result.latency_stddev);
+
+        let report = "number of transactions actually processed: 200/200
+latency average = 2.000 ms
+latency stddev = 0.500 ms
+tps = 480.5 (including connections establishing)
+tps = 495.25 (excluding connections establishing)
+";
+        let result = PgBenchResult::parse(report).unwrap();
+        assert_eq!(495.25, result.tps);
+        assert_eq!(Some(Duration::from_micros(500)), result.latency_stddev);
+        assert!(PgBenchResult::parse("pgbench: error: connection failed").is_none());
+    }
+}
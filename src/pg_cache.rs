@@ -0,0 +1,371 @@
+//!
+//! Cache maintenance
+//!
+//! Enumerate the cached postgresql binaries and reclaim disk space
+//!
+use std::fs::File;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use fs4::FileExt;
+
+use crate::pg_enums::{Architecture, OperationSystem};
+use crate::pg_errors::PgEmbedError;
+use crate::pg_fetch::PostgresVersion;
+use crate::pg_retry;
+use crate::pg_types::PgResult;
+
+/// Records the last time the cached binaries were used
+pub(crate) const CACHE_LAST_USED_FILE_NAME: &str = ".last_used";
+/// Suffix of the version directories of binaries bundling PostGIS
+const POSTGIS_DIR_SUFFIX: &str = "-postgis";
+
+///
+/// Garbage collection policy of the binaries cache
+///
+/// All configured rules are applied, unset rules are ignored.
+///
+#[derive(Debug, Clone, Default)]
+pub struct PgCacheGcPolicy {
+    /// Keep only the newest N versions per operating system and architecture
+    pub keep_newest: Option<usize>,
+    /// Remove the least recently used versions until the cache is smaller than this many bytes
+    pub max_total_size: Option<u64>,
+    /// Remove versions that have not been used for longer than this
+    pub max_unused: Option<Duration>,
+}
+
+///
+/// Cached postgresql binaries of one version
+///
+#[derive(Debug, Clone, PartialEq)]
+pub struct PgCacheEntry {
+    /// Cache directory of the binaries
+    pub path: PathBuf,
+    /// The operation system
+    pub operating_system: OperationSystem,
+    /// The cpu architecture
+    pub architecture: Architecture,
+    /// The postgresql version
+    pub version: PostgresVersion,
+    /// The binaries bundle PostGIS, see [crate::pg_fetch::PgFetchSettings::with_postgis]
+    pub postgis: bool,
+    /// Disk usage in bytes
+    pub size: u64,
+    /// Last time the binaries were acquired, if known
+    pub last_used: Option<SystemTime>,
+}
+
+///
+/// Record that the binaries in `cache_dir` have been used now
+///
+pub(crate) fn touch_last_used(cache_dir: &Path) -> PgResult<()> {
+    let path = cache_dir.join(CACHE_LAST_USED_FILE_NAME);
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    std::fs::write(&path, now.to_string()).map_err(|e| PgEmbedError::WriteFileError { e, path })
+}
+
+///
+/// Enumerate the cached versions in the `{os}/{arch}/{version}` layout below `cache_root`
+///
+/// Directories not matching the layout are ignored.
+///
+pub(crate) fn list_entries(cache_root: &Path) -> PgResult<Vec<PgCacheEntry>> {
+    let mut entries = Vec::new();
+    for os_dir in sub_dirs(cache_root)? {
+        let operating_system = match parse_os_dir_name(&file_name(&os_dir)) {
+            Some(operating_system) => operating_system,
+            None => continue,
+        };
+        for arch_dir in sub_dirs(&os_dir)? {
+            let architecture = match file_name(&arch_dir).parse::<Architecture>() {
+                Ok(architecture) => architecture,
+                Err(_) => continue,
+            };
+            for version_dir in sub_dirs(&arch_dir)? {
+                let name = file_name(&version_dir);
+                let (name, postgis) = match name.strip_suffix(POSTGIS_DIR_SUFFIX) {
+                    Some(name) => (name, true),
+                    None => (name.as_str(), false),
+                };
+                let version = match name.parse::<PostgresVersion>() {
+                    Ok(version) => version,
+                    Err(_) => continue,
+                };
+                entries.push(PgCacheEntry {
+                    operating_system,
+                    architecture,
+                    version,
+                    postgis,
+                    size: dir_size(&version_dir)?,
+                    last_used: last_used(&version_dir),
+                    path: version_dir,
+                });
+            }
+        }
+    }
+    entries.sort_by(|a, b| (a.path.parent(), a.version).cmp(&(b.path.parent(), b.version)));
+    Ok(entries)
+}
+
+///
+/// Name of the version directory in the cache
+///
+pub(crate) fn version_dir_name(version: &PostgresVersion, postgis: bool) -> String {
+    if postgis {
+        format!("{}{}", version, POSTGIS_DIR_SUFFIX)
+    } else {
+        version.to_string()
+    }
+}
+
+///
+/// Name of the operating system directory in the cache
+///
+pub(crate) fn os_dir_name(operating_system: &OperationSystem) -> String {
+    match operating_system {
+        OperationSystem::Darwin | OperationSystem::Windows | OperationSystem::Linux => {
+            operating_system.to_string()
+        }
+        OperationSystem::AlpineLinux => {
+            format!("arch_{}", operating_system)
+        }
+    }
+}
+
+fn parse_os_dir_name(name: &str) -> Option<OperationSystem> {
+    [
+        OperationSystem::Darwin,
+        OperationSystem::Windows,
+        OperationSystem::Linux,
+        OperationSystem::AlpineLinux,
+    ]
+    .iter()
+    .copied()
+    .find(|operating_system| os_dir_name(operating_system) == name)
+}
+
+///
+/// Remove the cached versions below `cache_root` violating `policy`
+///
+/// Versions locked by an ongoing acquisition are skipped.
+/// Returns the removed directories.
+///
+pub(crate) fn gc(
+    cache_root: &Path,
+    policy: &PgCacheGcPolicy,
+    now: SystemTime,
+) -> PgResult<Vec<PathBuf>> {
+    let entries = list_entries(cache_root)?;
+    let mut remove = vec![false; entries.len()];
+
+    if let Some(keep) = policy.keep_newest {
+        for (i, entry) in entries.iter().enumerate() {
+            let newer = entries
+                .iter()
+                .filter(|other| {
+                    other.operating_system == entry.operating_system
+                        && other.architecture == entry.architecture
+                        && other.postgis == entry.postgis
+                        && other.version > entry.version
+                })
+                .count();
+            remove[i] |= newer >= keep;
+        }
+    }
+    if let Some(max_unused) = policy.max_unused {
+        for (i, entry) in entries.iter().enumerate() {
+            let unused = entry
+                .last_used
+                .and_then(|last_used| now.duration_since(last_used).ok())
+                .unwrap_or_default();
+            remove[i] |= unused > max_unused;
+        }
+    }
+    if let Some(max_total_size) = policy.max_total_size {
+        let mut kept: Vec<usize> = (0..entries.len()).filter(|i| !remove[*i]).collect();
+        // least recently used first
+        kept.sort_by_key(|i| entries[*i].last_used.unwrap_or(UNIX_EPOCH));
+        let mut total: u64 = kept.iter().map(|i| entries[*i].size).sum();
+        for i in kept {
+            if total <= max_total_size {
+                break;
+            }
+            remove[i] = true;
+            total -= entries[i].size;
+        }
+    }
+
+    let mut removed = Vec::new();
+    for (entry, _) in entries
+        .into_iter()
+        .zip(remove)
+        .filter(|(_, remove)| *remove)
+    {
+        if remove_entry(&entry.path)? {
+            removed.push(entry.path);
+        }
+    }
+    Ok(removed)
+}
+
+///
+/// Remove a cached version unless its acquisition is in progress
+///
+/// Returns `Ok(false)` if the version is locked.
+///
+pub(crate) fn remove_entry(path: &Path) -> PgResult<bool> {
+    let lock_path = path.join(crate::pg_access::CACHE_LOCK_FILE_NAME);
+    let lock = match File::open(&lock_path) {
+        Ok(lock) => Some(lock),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => None,
+        Err(e) => return Err(PgEmbedError::CacheLockError { e, path: lock_path }),
+    };
+    if let Some(lock) = &lock {
+        if FileExt::try_lock(lock).is_err() {
+            log::info!("Skipping {}, it is in use", path.display());
+            return Ok(false);
+        }
+    }
+    log::info!("Removing cached postgresql binaries {}", path.display());
+    pg_retry::retry_blocking("removing cached binaries", || std::fs::remove_dir_all(path))
+        .map_err(|e| PgEmbedError::PgCleanUpFailure {
+            path: path.to_path_buf(),
+            e,
+        })?;
+    Ok(true)
+}
+
+fn sub_dirs(dir: &Path) -> PgResult<Vec<PathBuf>> {
+    if !dir.is_dir() {
+        return Ok(Vec::new());
+    }
+    let read_error = |e| PgEmbedError::ReadFileError {
+        path: dir.to_path_buf(),
+        e,
+    };
+    let mut dirs = Vec::new();
+    for entry in std::fs::read_dir(dir).map_err(read_error)? {
+        let entry = entry.map_err(read_error)?;
+        if entry.file_type().map_err(read_error)?.is_dir() {
+            dirs.push(entry.path());
+        }
+    }
+    Ok(dirs)
+}
+
+fn file_name(path: &Path) -> String {
+    path.file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_default()
+}
+
+fn dir_size(dir: &Path) -> PgResult<u64> {
+    let read_error = |e| PgEmbedError::ReadFileError {
+        path: dir.to_path_buf(),
+        e,
+    };
+    let mut size = 0;
+    for entry in std::fs::read_dir(dir).map_err(read_error)? {
+        let entry = entry.map_err(read_error)?;
+        let metadata = entry.metadata().map_err(read_error)?;
+        if metadata.is_dir() {
+            size += dir_size(&entry.path())?;
+        } else {
+            size += metadata.len();
+        }
+    }
+    Ok(size)
+}
+
+///
+/// Last use recorded by [touch_last_used], falls back to the directory modification time
+///
+fn last_used(dir: &Path) -> Option<SystemTime> {
+    std::fs::read_to_string(dir.join(CACHE_LAST_USED_FILE_NAME))
+        .ok()
+        .and_then(|secs| secs.trim().parse().ok())
+        .map(|secs| UNIX_EPOCH + Duration::from_secs(secs))
+        .or_else(|| std::fs::metadata(dir).and_then(|m| m.modified()).ok())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn gc_policies() -> Result<(), PgEmbedError> {
+        let root = std::env::temp_dir().join(format!("pg_embed_cache_gc_{}", std::process::id()));
+        let day = Duration::from_secs(24 * 60 * 60);
+        let now = UNIX_EPOCH + 1000 * day;
+        let create = |version: &str, size: usize, used_days_ago: u32| {
+            let dir = root.join("linux").join("amd64").join(version);
+            std::fs::create_dir_all(dir.join("bin")).unwrap();
+            std::fs::write(dir.join("bin").join("postgres"), vec![0u8; size]).unwrap();
+            let last_used = now - used_days_ago * day;
+            let secs = last_used.duration_since(UNIX_EPOCH).unwrap().as_secs();
+            std::fs::write(dir.join(CACHE_LAST_USED_FILE_NAME), secs.to_string()).unwrap();
+            dir
+        };
+        let v14 = create("14.11.0", 100, 1);
+        let v15 = create("15.6.0", 100, 60);
+        let v16 = create("16.2.0", 100, 2);
+        let v13 = create("13.14.0", 100, 3);
+
+        let removed = gc(
+            &root,
+            &PgCacheGcPolicy {
+                keep_newest: Some(3),
+                ..Default::default()
+            },
+            now,
+        )?;
+        assert_eq!(vec![v13], removed);
+
+        let removed = gc(
+            &root,
+            &PgCacheGcPolicy {
+                max_unused: Some(30 * day),
+                ..Default::default()
+            },
+            now,
+        )?;
+        assert_eq!(vec![v15], removed);
+
+        // sizes include the last used file
+        let removed = gc(
+            &root,
+            &PgCacheGcPolicy {
+                max_total_size: Some(150),
+                ..Default::default()
+            },
+            now,
+        )?;
+        assert_eq!(vec![v16], removed);
+        let entries = list_entries(&root)?;
+        assert_eq!(1, entries.len());
+        assert_eq!(v14, entries[0].path);
+        assert_eq!(OperationSystem::Linux, entries[0].operating_system);
+        assert_eq!(Architecture::Amd64, entries[0].architecture);
+        assert_eq!(PostgresVersion::new(14, 11, 0), entries[0].version);
+        assert!(!entries[0].postgis);
+        let postgis = create(
+            &version_dir_name(&PostgresVersion::new(16, 2, 0), true),
+            100,
+            1,
+        );
+        let entries = list_entries(&root)?;
+        assert_eq!(postgis, entries[1].path);
+        assert_eq!(PostgresVersion::new(16, 2, 0), entries[1].version);
+        assert!(entries[1].postgis);
+        assert_eq!(
+            Some(OperationSystem::AlpineLinux),
+            parse_os_dir_name(&os_dir_name(&OperationSystem::AlpineLinux))
+        );
+        std::fs::remove_dir_all(&root).unwrap();
+        Ok(())
+    }
+}
@@ -1,14 +1,20 @@
 //!
 //! Create postgres command executor
 //!
-//! Command executors for initdb, pg_ctl start, pg_ctl stop
+//! Command executors for initdb, pg_ctl start, pg_ctl stop, pg_ctl promote, pg_dump,
+//! pg_restore, psql, pg_basebackup
 //!
+use std::ffi::{OsStr, OsString};
+use std::io::Read;
 use std::path::Path;
+use std::time::Duration;
 
-use crate::command_executor::{AsyncCommand, AsyncCommandExecutor};
-use crate::pg_enums::{PgAuthMethod, PgProcessType, PgServerStatus};
+use crate::command_executor::AsyncCommandExecutor;
+use crate::pg_enums::{PgAuthMethod, PgProcessType, PgServerStatus, PgShutdownMode, PgUpgradeMode};
 use crate::pg_errors::PgEmbedError;
-use crate::pg_types::PgResult;
+use crate::pg_fetch::PostgresVersion;
+use crate::pg_os_user::PgOsUser;
+use crate::pg_types::{PgResult, SecretString};
 
 ///
 /// Postgres command executors
@@ -16,48 +22,107 @@ use crate::pg_types::PgResult;
 pub struct PgCommand {}
 
 impl PgCommand {
+    ///
+    /// Create a command executor, running the process as `os_user` if specified
+    ///
+    fn executor<A, B>(
+        executable: &OsStr,
+        args: A,
+        process_type: PgProcessType,
+        os_user: Option<&PgOsUser>,
+    ) -> PgResult<AsyncCommandExecutor<PgServerStatus, PgEmbedError, PgProcessType>>
+    where
+        A: IntoIterator<Item = B>,
+        B: AsRef<OsStr>,
+    {
+        let mut command =
+            AsyncCommandExecutor::<PgServerStatus, PgEmbedError, PgProcessType>::generate_command(
+                executable, args,
+            );
+        if let Some(os_user) = os_user {
+            os_user.apply(&mut command);
+        }
+        AsyncCommandExecutor::from_command(command, process_type)
+    }
+
     ///
     /// Create initdb command
     ///
+    /// With `no_sync` initdb does not wait for the files to be flushed to disk.
+    ///
+    #[allow(clippy::too_many_arguments)]
     pub fn init_db_executor(
         init_db_exe: &Path,
         database_dir: &Path,
         pw_file_path: &Path,
         user: &str,
         auth_method: &PgAuthMethod,
+        options: &PgInitDbOptions,
+        no_sync: bool,
+        os_user: Option<&PgOsUser>,
     ) -> PgResult<AsyncCommandExecutor<PgServerStatus, PgEmbedError, PgProcessType>> {
         let init_db_executable = init_db_exe.as_os_str();
-        let password_file_arg = format!("--pwfile={}", pw_file_path.to_str().unwrap());
-        let auth_host = match auth_method {
-            PgAuthMethod::Plain => "password",
-            PgAuthMethod::MD5 => "md5",
-            PgAuthMethod::ScramSha256 => "scram-sha-256",
-        };
-        let args = [
-            "-A",
-            auth_host,
-            "-U",
-            user,
-            // The postgres-tokio driver uses utf8 encoding, however on windows
-            // if -E is not specified WIN1252 encoding is chosen by default
-            // which can lead to encoding errors like this:
-            //
-            // ERROR: character with byte sequence 0xe0 0xab 0x87 in encoding
-            // "UTF8" has no equivalent in encoding "WIN1252"
-            "-E=UTF8",
-            "-D",
-            database_dir.to_str().unwrap(),
-            &password_file_arg,
+        let auth_host = auth_method.hba_method();
+        let mut args: Vec<OsString> = vec![
+            "-A".into(),
+            auth_host.into(),
+            "-U".into(),
+            user.into(),
+            "-D".into(),
+            path_arg(database_dir),
         ];
+        args.extend(options.args());
+        if auth_method.requires_password() {
+            let mut password_file_arg = OsString::from("--pwfile=");
+            password_file_arg.push(path_arg(pw_file_path));
+            args.push(password_file_arg);
+        }
+        if no_sync {
+            // short option, the long one was renamed from --nosync in postgresql 10
+            args.push("-N".into());
+        }
 
-        let command_executor =
-            AsyncCommandExecutor::<PgServerStatus, PgEmbedError, PgProcessType>::new(
-                init_db_executable,
-                args,
-                PgProcessType::InitDb,
-            )?;
+        Self::executor(init_db_executable, args, PgProcessType::InitDb, os_user)
+    }
 
-        Ok(command_executor)
+    ///
+    /// Create pg_upgrade command migrating the cluster of `old_database_dir` into the new
+    /// cluster of `new_database_dir`
+    ///
+    /// pg_upgrade writes its logs and sockets into the working directory `work_dir`.
+    ///
+    #[allow(clippy::too_many_arguments)]
+    pub fn upgrade_db_executor(
+        pg_upgrade_exe: &Path,
+        old_bin_dir: &Path,
+        new_bin_dir: &Path,
+        old_database_dir: &Path,
+        new_database_dir: &Path,
+        work_dir: &Path,
+        user: &str,
+        password: &SecretString,
+        mode: PgUpgradeMode,
+        os_user: Option<&PgOsUser>,
+    ) -> PgResult<AsyncCommandExecutor<PgServerStatus, PgEmbedError, PgProcessType>> {
+        let mut command =
+            AsyncCommandExecutor::<PgServerStatus, PgEmbedError, PgProcessType>::generate_command(
+                pg_upgrade_exe.as_os_str(),
+                upgrade_db_args(
+                    old_bin_dir,
+                    new_bin_dir,
+                    old_database_dir,
+                    new_database_dir,
+                    user,
+                    mode,
+                ),
+            );
+        command
+            .current_dir(work_dir)
+            .env("PGPASSWORD", password.expose_secret());
+        if let Some(os_user) = os_user {
+            os_user.apply(&mut command);
+        }
+        AsyncCommandExecutor::from_command(command, PgProcessType::UpgradeDb)
     }
 
     ///
@@ -67,25 +132,26 @@ impl PgCommand {
         pg_ctl_exe: &Path,
         database_dir: &Path,
         port: &u16,
+        ready_timeout: Option<Duration>,
+        os_user: Option<&PgOsUser>,
     ) -> PgResult<AsyncCommandExecutor<PgServerStatus, PgEmbedError, PgProcessType>> {
         let pg_ctl_executable = pg_ctl_exe.as_os_str();
         let port_arg = format!("-F -p {}", port);
-        let args = [
-            "-o",
-            &port_arg,
-            "start",
-            "-w",
-            "-D",
-            database_dir.to_str().unwrap(),
+        let mut args: Vec<OsString> = vec![
+            "-o".into(),
+            port_arg.into(),
+            "start".into(),
+            "-w".into(),
+            "-D".into(),
+            path_arg(database_dir),
         ];
-        let command_executor =
-            AsyncCommandExecutor::<PgServerStatus, PgEmbedError, PgProcessType>::new(
-                pg_ctl_executable,
-                args,
-                PgProcessType::StartDb,
-            )?;
-
-        Ok(command_executor)
+        if let Some(ready_timeout) = ready_timeout {
+            // pg_ctl only accepts whole seconds
+            let seconds = ready_timeout.as_secs().max(1);
+            args.push("-t".into());
+            args.push(seconds.to_string().into());
+        }
+        Self::executor(pg_ctl_executable, args, PgProcessType::StartDb, os_user)
     }
 
     ///
@@ -94,16 +160,943 @@ impl PgCommand {
     pub fn stop_db_executor(
         pg_ctl_exe: &Path,
         database_dir: &Path,
+        mode: PgShutdownMode,
+        os_user: Option<&PgOsUser>,
+    ) -> PgResult<AsyncCommandExecutor<PgServerStatus, PgEmbedError, PgProcessType>> {
+        let pg_ctl_executable = pg_ctl_exe.as_os_str();
+        let args = stop_db_args(database_dir, mode);
+        Self::executor(pg_ctl_executable, args, PgProcessType::StopDb, os_user)
+    }
+
+    ///
+    /// Create pg_ctl status command, exiting with code 3 if the server is not running
+    ///
+    /// Meant to be run periodically, so the command is not logged.
+    ///
+    pub(crate) fn status_db_command(
+        pg_ctl_exe: &Path,
+        database_dir: &Path,
+        os_user: Option<&PgOsUser>,
+    ) -> tokio::process::Command {
+        let mut command = tokio::process::Command::new(pg_ctl_exe);
+        command.args(["status".into(), "-D".into(), path_arg(database_dir)] as [OsString; 3]);
+        #[cfg(target_os = "windows")]
+        {
+            const CREATE_NO_WINDOW: u32 = 0x08000000;
+            command.creation_flags(CREATE_NO_WINDOW);
+        }
+        if let Some(os_user) = os_user {
+            os_user.apply(&mut command);
+        }
+        command
+    }
+
+    ///
+    /// Create pg_ctl reload command, signaling the server to reload its configuration files
+    ///
+    pub fn reload_db_executor(
+        pg_ctl_exe: &Path,
+        database_dir: &Path,
+        os_user: Option<&PgOsUser>,
+    ) -> PgResult<AsyncCommandExecutor<PgServerStatus, PgEmbedError, PgProcessType>> {
+        let pg_ctl_executable = pg_ctl_exe.as_os_str();
+        let args: [OsString; 3] = ["reload".into(), "-D".into(), path_arg(database_dir)];
+        Self::executor(pg_ctl_executable, args, PgProcessType::ReloadDb, os_user)
+    }
+
+    ///
+    /// Create pg_ctl promote command, turning a standby into a primary and waiting until it
+    /// accepts writes
+    ///
+    pub fn promote_db_executor(
+        pg_ctl_exe: &Path,
+        database_dir: &Path,
+        os_user: Option<&PgOsUser>,
     ) -> PgResult<AsyncCommandExecutor<PgServerStatus, PgEmbedError, PgProcessType>> {
         let pg_ctl_executable = pg_ctl_exe.as_os_str();
-        let args = ["stop", "-w", "-D", database_dir.to_str().unwrap()];
-        let command_executor =
-            AsyncCommandExecutor::<PgServerStatus, PgEmbedError, PgProcessType>::new(
-                pg_ctl_executable,
+        let args: [OsString; 4] = [
+            "promote".into(),
+            "-w".into(),
+            "-D".into(),
+            path_arg(database_dir),
+        ];
+        Self::executor(pg_ctl_executable, args, PgProcessType::PromoteDb, os_user)
+    }
+
+    ///
+    /// Create pg_dump command writing a custom format archive of `database_name` to `target_file`
+    ///
+    pub fn dump_db_executor(
+        pg_dump_exe: &Path,
+        connection: &PgClientConnection,
+        database_name: &str,
+        target_file: &Path,
+        os_user: Option<&PgOsUser>,
+    ) -> PgResult<AsyncCommandExecutor<PgServerStatus, PgEmbedError, PgProcessType>> {
+        let mut args = connection.args(database_name);
+        args.extend(["-F".into(), "c".into(), "-f".into(), path_arg(target_file)]);
+        Self::client_executor(
+            pg_dump_exe,
+            args,
+            PgProcessType::DumpDb,
+            connection,
+            os_user,
+        )
+    }
+
+    ///
+    /// Create pg_restore command restoring the custom format archive `source_file` into
+    /// `database_name`, replacing existing objects
+    ///
+    pub fn restore_db_executor(
+        pg_restore_exe: &Path,
+        connection: &PgClientConnection,
+        database_name: &str,
+        source_file: &Path,
+        os_user: Option<&PgOsUser>,
+    ) -> PgResult<AsyncCommandExecutor<PgServerStatus, PgEmbedError, PgProcessType>> {
+        let mut args = connection.args(database_name);
+        args.extend([
+            "--clean".into(),
+            "--if-exists".into(),
+            path_arg(source_file),
+        ]);
+        Self::client_executor(
+            pg_restore_exe,
+            args,
+            PgProcessType::RestoreDb,
+            connection,
+            os_user,
+        )
+    }
+
+    ///
+    /// Create a command restoring the `format` dump `source` into `database_name`, with
+    /// pg_restore for archives and psql for plain sql dumps
+    ///
+    /// Both stop at the first error.
+    ///
+    #[allow(clippy::too_many_arguments)]
+    pub fn restore_executor(
+        pg_restore_exe: &Path,
+        psql_exe: &Path,
+        connection: &PgClientConnection,
+        database_name: &str,
+        source: &Path,
+        format: PgDumpFormat,
+        options: &PgRestoreOptions,
+        os_user: Option<&PgOsUser>,
+    ) -> PgResult<AsyncCommandExecutor<PgServerStatus, PgEmbedError, PgProcessType>> {
+        let executable = match format {
+            PgDumpFormat::Plain => psql_exe,
+            _ => pg_restore_exe,
+        };
+        let args = restore_args(connection, database_name, source, format, options);
+        Self::client_executor(
+            executable,
+            args,
+            PgProcessType::RestoreDb,
+            connection,
+            os_user,
+        )
+    }
+
+    ///
+    /// Create psql command running `script` on `database_name`, stopping at the first error
+    ///
+    pub fn sql_executor(
+        psql_exe: &Path,
+        connection: &PgClientConnection,
+        database_name: &str,
+        script: PgSqlScript<'_>,
+        os_user: Option<&PgOsUser>,
+    ) -> PgResult<AsyncCommandExecutor<PgServerStatus, PgEmbedError, PgProcessType>> {
+        let args = sql_args(connection, database_name, script);
+        Self::client_executor(psql_exe, args, PgProcessType::Sql, connection, os_user)
+    }
+
+    ///
+    /// Create pg_basebackup command writing a base backup of the server to `target_dir`
+    ///
+    pub fn base_backup_executor(
+        pg_basebackup_exe: &Path,
+        connection: &PgClientConnection,
+        target_dir: &Path,
+        options: &PgBaseBackupOptions,
+        os_user: Option<&PgOsUser>,
+    ) -> PgResult<AsyncCommandExecutor<PgServerStatus, PgEmbedError, PgProcessType>> {
+        let args = base_backup_args(connection, target_dir, options);
+        Self::client_executor(
+            pg_basebackup_exe,
+            args,
+            PgProcessType::BaseBackup,
+            connection,
+            os_user,
+        )
+    }
+
+    ///
+    /// Create a client command executor, passing the password through the environment
+    ///
+    fn client_executor(
+        executable: &Path,
+        args: Vec<OsString>,
+        process_type: PgProcessType,
+        connection: &PgClientConnection,
+        os_user: Option<&PgOsUser>,
+    ) -> PgResult<AsyncCommandExecutor<PgServerStatus, PgEmbedError, PgProcessType>> {
+        let mut command =
+            AsyncCommandExecutor::<PgServerStatus, PgEmbedError, PgProcessType>::generate_command(
+                executable.as_os_str(),
                 args,
-                PgProcessType::StopDb,
-            )?;
+            );
+        command.env("PGPASSWORD", connection.password.expose_secret());
+        if let Some(os_user) = os_user {
+            os_user.apply(&mut command);
+        }
+        AsyncCommandExecutor::from_command(command, process_type)
+    }
+}
+
+///
+/// Connection parameters of client commands like pg_dump
+///
+#[derive(Debug, Clone)]
+pub struct PgClientConnection {
+    /// server host
+    pub host: String,
+    /// server port
+    pub port: u16,
+    /// database user
+    pub user: String,
+    /// database password
+    pub password: SecretString,
+}
+
+///
+/// Sql run by psql
+///
+#[derive(Debug, Clone, Copy)]
+pub enum PgSqlScript<'a> {
+    /// sql statements, run in one transaction unless they contain transaction control
+    /// statements
+    Sql(&'a str),
+    /// sql script file, its statements run one after another
+    File(&'a Path),
+}
+
+///
+/// Format of a pg_dump dump
+///
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PgDumpFormat {
+    /// sql script
+    Plain,
+    /// custom format archive (*`pg_dump -F c`*)
+    Custom,
+    /// tar archive (*`pg_dump -F t`*)
+    Tar,
+    /// directory of a directory format dump (*`pg_dump -F d`*)
+    Directory,
+}
+
+impl PgDumpFormat {
+    ///
+    /// Detect the format of the dump `path`
+    ///
+    /// Files which are neither a custom format nor a tar archive are plain sql scripts.
+    ///
+    pub fn detect(path: &Path) -> PgResult<Self> {
+        let read_error = |e| PgEmbedError::ReadFileError {
+            path: path.to_path_buf(),
+            e,
+        };
+        if path.is_dir() {
+            return if path.join("toc.dat").is_file() {
+                Ok(PgDumpFormat::Directory)
+            } else {
+                Err(read_error(std::io::Error::new(
+                    std::io::ErrorKind::InvalidData,
+                    "directory contains no toc.dat of a directory format dump",
+                )))
+            };
+        }
+        let mut header = Vec::with_capacity(512);
+        std::fs::File::open(path)
+            .and_then(|file| file.take(512).read_to_end(&mut header))
+            .map_err(read_error)?;
+        Ok(if header.starts_with(b"PGDMP") {
+            PgDumpFormat::Custom
+        } else if header.len() >= 262 && &header[257..262] == b"ustar" {
+            PgDumpFormat::Tar
+        } else {
+            PgDumpFormat::Plain
+        })
+    }
+}
+
+impl std::fmt::Display for PgDumpFormat {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PgDumpFormat::Plain => write!(f, "plain"),
+            PgDumpFormat::Custom => write!(f, "custom"),
+            PgDumpFormat::Tar => write!(f, "tar"),
+            PgDumpFormat::Directory => write!(f, "directory"),
+        }
+    }
+}
+
+///
+/// Options of [crate::postgres::PgEmbed::restore]
+///
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PgRestoreOptions {
+    /// drop the objects of the dump before recreating them, archives only
+    pub clean: bool,
+    /// don't restore the ownership of the objects, they are owned by the restoring user,
+    /// archives only
+    pub no_owner: bool,
+    /// don't restore the access privileges, archives only
+    pub no_privileges: bool,
+    /// restore everything or nothing
+    pub single_transaction: bool,
+    /// number of concurrent jobs, custom and directory archives only, excludes
+    /// `single_transaction`
+    pub jobs: Option<u32>,
+}
+
+///
+/// Format of a pg_basebackup base backup
+///
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PgBaseBackupFormat {
+    /// copy of the data directory, can be started as is
+    Plain,
+    /// one tar archive per tablespace, `base.tar` for the data directory
+    Tar,
+}
+
+impl std::fmt::Display for PgBaseBackupFormat {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PgBaseBackupFormat::Plain => write!(f, "plain"),
+            PgBaseBackupFormat::Tar => write!(f, "tar"),
+        }
+    }
+}
+
+///
+/// How the wal needed to start the backup is included (*`pg_basebackup -X`*)
+///
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PgWalMethod {
+    /// no wal, the backup can only be started with wal from an archive
+    None,
+    /// wal collected at the end of the backup
+    Fetch,
+    /// wal streamed while the backup is taken
+    Stream,
+}
+
+impl std::fmt::Display for PgWalMethod {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PgWalMethod::None => write!(f, "none"),
+            PgWalMethod::Fetch => write!(f, "fetch"),
+            PgWalMethod::Stream => write!(f, "stream"),
+        }
+    }
+}
+
+///
+/// Options of [crate::postgres::PgEmbed::base_backup]
+///
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PgBaseBackupOptions {
+    /// backup format
+    pub format: PgBaseBackupFormat,
+    /// wal included in the backup
+    pub wal_method: PgWalMethod,
+    /// start with an immediate checkpoint instead of spreading it out
+    pub fast_checkpoint: bool,
+    /// gzip compression level of tar backups, 1 to 9
+    pub compress: Option<u32>,
+    /// write the settings to start the backup as a standby of the server
+    pub write_recovery_conf: bool,
+    /// backup label, `pg_basebackup base backup` if not set
+    pub label: Option<String>,
+    /// replication slot streaming the wal, keeps the wal on the server until it was consumed
+    pub slot: Option<String>,
+    /// create `slot` before the backup
+    pub create_slot: bool,
+}
+
+impl Default for PgBaseBackupOptions {
+    fn default() -> Self {
+        PgBaseBackupOptions {
+            format: PgBaseBackupFormat::Plain,
+            wal_method: PgWalMethod::Stream,
+            fast_checkpoint: true,
+            compress: None,
+            write_recovery_conf: false,
+            label: None,
+            slot: None,
+            create_slot: false,
+        }
+    }
+}
+
+///
+/// Provider of the default collation and character classification
+///
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
+pub enum PgLocaleProvider {
+    /// locales of the operating system c library
+    Libc,
+    /// ICU locale (*e.g. `en-US` or `und`*), collations behave the same on every platform
+    ///
+    /// Requires postgresql 15 or later.
+    Icu(String),
+}
+
+///
+/// Database cluster options passed to initdb
+///
+/// Unset options are chosen by initdb based on the environment, e.g. the locale of the host.
+///
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(default))]
+pub struct PgInitDbOptions {
+    /// default locale of the cluster (*e.g. `en_US.UTF-8` or `C`*)
+    pub locale: Option<String>,
+    /// character encoding of the template databases
+    /// defaults to `UTF8`
+    pub encoding: Option<String>,
+    /// collation order, overrides `locale`
+    pub lc_collate: Option<String>,
+    /// character classification, overrides `locale`
+    pub lc_ctype: Option<String>,
+    /// provider of the default collation
+    pub locale_provider: PgLocaleProvider,
+    /// checksum data pages to detect corruption
+    pub data_checksums: bool,
+    /// wal segment size in megabytes, a power of 2 between 1 and 1024
+    pub wal_segsize: Option<u32>,
+}
+
+impl Default for PgInitDbOptions {
+    fn default() -> Self {
+        PgInitDbOptions {
+            locale: None,
+            // The postgres-tokio driver uses utf8 encoding, however on windows
+            // if the encoding is not specified WIN1252 encoding is chosen by default
+            // which can lead to encoding errors like this:
+            //
+            // ERROR: character with byte sequence 0xe0 0xab 0x87 in encoding
+            // "UTF8" has no equivalent in encoding "WIN1252"
+            encoding: Some("UTF8".to_string()),
+            lc_collate: None,
+            lc_ctype: None,
+            locale_provider: PgLocaleProvider::Libc,
+            data_checksums: false,
+            wal_segsize: None,
+        }
+    }
+}
+
+impl PgInitDbOptions {
+    ///
+    /// Check that the options are accepted by the initdb of postgresql `version`
+    ///
+    pub fn validate(&self, version: PostgresVersion) -> PgResult<()> {
+        let invalid = |name: &str, reason: &str| {
+            Err(PgEmbedError::InvalidServerConfig {
+                name: name.to_string(),
+                reason: reason.to_string(),
+            })
+        };
+        if let Some(wal_segsize) = self.wal_segsize {
+            if !wal_segsize.is_power_of_two() || wal_segsize > 1024 {
+                return invalid("wal_segsize", "must be a power of 2 between 1 and 1024");
+            }
+            if version.major() < 11 {
+                return invalid("wal_segsize", "requires postgresql 11 or later");
+            }
+        }
+        if let PgLocaleProvider::Icu(locale) = &self.locale_provider {
+            if locale.trim().is_empty() {
+                return invalid("locale_provider", "icu locale must not be empty");
+            }
+            if version.major() < 15 {
+                return invalid("locale_provider", "icu requires postgresql 15 or later");
+            }
+        }
+        Ok(())
+    }
+
+    ///
+    /// The initdb arguments
+    ///
+    pub(crate) fn args(&self) -> Vec<OsString> {
+        let options = [
+            ("--locale", &self.locale),
+            ("--encoding", &self.encoding),
+            ("--lc-collate", &self.lc_collate),
+            ("--lc-ctype", &self.lc_ctype),
+        ];
+        let mut args: Vec<OsString> = options
+            .iter()
+            .filter_map(|(name, value)| {
+                value
+                    .as_ref()
+                    .map(|value| format!("{}={}", name, value).into())
+            })
+            .collect();
+        if let PgLocaleProvider::Icu(locale) = &self.locale_provider {
+            args.push("--locale-provider=icu".into());
+            args.push(format!("--icu-locale={}", locale).into());
+        }
+        if self.data_checksums {
+            args.push("--data-checksums".into());
+        }
+        if let Some(wal_segsize) = self.wal_segsize {
+            args.push(format!("--wal-segsize={}", wal_segsize).into());
+        }
+        args
+    }
+}
+
+///
+/// Arguments of the pg_ctl stop command
+///
+pub(crate) fn stop_db_args(database_dir: &Path, mode: PgShutdownMode) -> [OsString; 6] {
+    [
+        "stop".into(),
+        "-w".into(),
+        "-m".into(),
+        mode.to_string().into(),
+        "-D".into(),
+        path_arg(database_dir),
+    ]
+}
+
+///
+/// pg_upgrade arguments
+///
+fn upgrade_db_args(
+    old_bin_dir: &Path,
+    new_bin_dir: &Path,
+    old_database_dir: &Path,
+    new_database_dir: &Path,
+    user: &str,
+    mode: PgUpgradeMode,
+) -> Vec<OsString> {
+    vec![
+        "-b".into(),
+        path_arg(old_bin_dir),
+        "-B".into(),
+        path_arg(new_bin_dir),
+        "-d".into(),
+        path_arg(old_database_dir),
+        "-D".into(),
+        path_arg(new_database_dir),
+        "-U".into(),
+        user.into(),
+        format!("--{}", mode).into(),
+    ]
+}
+
+///
+/// Convert a path into a command argument
+///
+/// Paths are passed as `OsStr` so non UTF-8 paths and paths containing spaces are handed to the
+/// process unaltered. On Windows absolute paths exceeding `MAX_PATH` are converted to extended-length
+/// paths (`\\?\`), which is required for data directories below deeply nested temp directories.
+///
+pub(crate) fn path_arg(path: &Path) -> OsString {
+    #[cfg(windows)]
+    {
+        if let Some(extended) = path.to_str().and_then(extended_length_path) {
+            return extended.into();
+        }
+    }
+    path.as_os_str().to_os_string()
+}
+
+/// Longest directory path accepted by the win32 api (*MAX_PATH minus a 8.3 file name*)
+#[cfg(any(windows, test))]
+const MAX_DIR_PATH: usize = 248;
+
+///
+/// Extended-length form of an absolute windows path longer than [MAX_DIR_PATH]
+///
+/// Returns `None` if the path is short, relative or already in extended-length form.
+///
+#[cfg(any(windows, test))]
+fn extended_length_path(path: &str) -> Option<String> {
+    if path.len() < MAX_DIR_PATH || path.starts_with(r"\\?\") {
+        return None;
+    }
+    let path = path.replace('/', r"\");
+    if let Some(share) = path.strip_prefix(r"\\") {
+        Some(format!(r"\\?\UNC\{}", share))
+    } else if path.as_bytes().get(1) == Some(&b':') && path.as_bytes().get(2) == Some(&b'\\') {
+        Some(format!(r"\\?\{}", path))
+    } else {
+        None
+    }
+}
+
+impl PgClientConnection {
+    fn args(&self, database_name: &str) -> Vec<OsString> {
+        let mut args = self.server_args();
+        args.extend(["-d".into(), database_name.into()]);
+        args
+    }
+
+    fn server_args(&self) -> Vec<OsString> {
+        [
+            "-h",
+            &self.host,
+            "-p",
+            &self.port.to_string(),
+            "-U",
+            &self.user,
+        ]
+        .iter()
+        .map(OsString::from)
+        .collect()
+    }
+}
+
+///
+/// psql arguments, `-X` skips the `.psqlrc` of the user
+///
+fn sql_args(
+    connection: &PgClientConnection,
+    database_name: &str,
+    script: PgSqlScript<'_>,
+) -> Vec<OsString> {
+    let mut args = connection.args(database_name);
+    args.extend(
+        ["-X", "-q", "-v", "ON_ERROR_STOP=1"]
+            .iter()
+            .map(OsString::from),
+    );
+    match script {
+        PgSqlScript::Sql(sql) => args.extend(["-c".into(), sql.into()]),
+        PgSqlScript::File(path) => args.extend(["-f".into(), path_arg(path)]),
+    }
+    args
+}
+
+///
+/// pg_restore or psql arguments restoring `source`
+///
+fn restore_args(
+    connection: &PgClientConnection,
+    database_name: &str,
+    source: &Path,
+    format: PgDumpFormat,
+    options: &PgRestoreOptions,
+) -> Vec<OsString> {
+    if format == PgDumpFormat::Plain {
+        let mut args = sql_args(connection, database_name, PgSqlScript::File(source));
+        if options.single_transaction {
+            args.push("--single-transaction".into());
+        }
+        return args;
+    }
+    let mut args = connection.args(database_name);
+    args.push("--exit-on-error".into());
+    let flags = [
+        (options.clean, "--clean"),
+        (options.clean, "--if-exists"),
+        (options.no_owner, "--no-owner"),
+        (options.no_privileges, "--no-privileges"),
+        (options.single_transaction, "--single-transaction"),
+    ];
+    args.extend(
+        flags
+            .iter()
+            .filter(|(set, _)| *set)
+            .map(|(_, flag)| OsString::from(flag)),
+    );
+    if let Some(jobs) = options.jobs {
+        args.push(format!("--jobs={}", jobs).into());
+    }
+    args.push(path_arg(source));
+    args
+}
+
+///
+/// pg_basebackup arguments, `-w` fails instead of prompting for a password
+///
+fn base_backup_args(
+    connection: &PgClientConnection,
+    target_dir: &Path,
+    options: &PgBaseBackupOptions,
+) -> Vec<OsString> {
+    let mut args = connection.server_args();
+    args.extend([
+        "-w".into(),
+        "-D".into(),
+        path_arg(target_dir),
+        format!("--format={}", options.format).into(),
+        format!("--wal-method={}", options.wal_method).into(),
+    ]);
+    if options.fast_checkpoint {
+        args.push("--checkpoint=fast".into());
+    }
+    if let Some(level) = options.compress {
+        args.push(format!("--compress={}", level).into());
+    }
+    if options.write_recovery_conf {
+        args.push("--write-recovery-conf".into());
+    }
+    if let Some(label) = &options.label {
+        args.extend(["--label".into(), label.into()]);
+    }
+    if let Some(slot) = &options.slot {
+        args.push(format!("--slot={}", slot).into());
+        if options.create_slot {
+            args.push("--create-slot".into());
+        }
+    }
+    args
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pg_fetch::{PG_V14, PG_V15};
+
+    #[test]
+    fn init_db_options() {
+        assert_eq!(
+            vec![OsString::from("--encoding=UTF8")],
+            PgInitDbOptions::default().args()
+        );
+        let options = PgInitDbOptions {
+            locale: Some("en_US.UTF-8".to_string()),
+            locale_provider: PgLocaleProvider::Icu("und".to_string()),
+            data_checksums: true,
+            wal_segsize: Some(64),
+            ..Default::default()
+        };
+        assert!(options.validate(PG_V15).is_ok());
+        assert!(options.validate(PG_V14).is_err());
+        assert_eq!(
+            vec![
+                "--locale=en_US.UTF-8",
+                "--encoding=UTF8",
+                "--locale-provider=icu",
+                "--icu-locale=und",
+                "--data-checksums",
+                "--wal-segsize=64",
+            ],
+            options.args()
+        );
+        let invalid = PgInitDbOptions {
+            wal_segsize: Some(48),
+            ..Default::default()
+        };
+        assert!(invalid.validate(PG_V15).is_err());
+        let empty_icu_locale = PgInitDbOptions {
+            locale_provider: PgLocaleProvider::Icu(" ".to_string()),
+            ..Default::default()
+        };
+        assert!(matches!(
+            empty_icu_locale.validate(PG_V15),
+            Err(PgEmbedError::InvalidServerConfig { name, .. }) if name == "locale_provider"
+        ));
+    }
+
+    #[test]
+    fn extended_length_paths() {
+        let deep = "a very deep directory\\".repeat(12);
+        let local = format!(r"C:\Users\runner\AppData\Local\Temp\{}data", deep);
+        assert_eq!(
+            Some(format!(r"\\?\{}", local)),
+            extended_length_path(&local)
+        );
+        let share = format!(r"\\server\share\{}data", deep);
+        assert_eq!(
+            Some(format!(r"\\?\UNC\server\share\{}data", deep)),
+            extended_length_path(&share)
+        );
+        let mixed = format!("C:/Temp/{}data", deep.replace('\\', "/"));
+        assert_eq!(
+            Some(format!(r"\\?\C:\Temp\{}data", deep)),
+            extended_length_path(&mixed)
+        );
+        assert_eq!(
+            None,
+            extended_length_path(r"C:\Program Files\pg-embed\data")
+        );
+        assert_eq!(None, extended_length_path(&format!(r"\\?\{}", local)));
+        assert_eq!(None, extended_length_path(&format!(r"relative\{}", deep)));
+        assert_eq!(
+            OsString::from("/tmp/dir with spaces/data"),
+            path_arg(Path::new("/tmp/dir with spaces/data"))
+        );
+    }
+
+    #[test]
+    fn upgrade_db_arguments() {
+        let args = upgrade_db_args(
+            Path::new("old/bin"),
+            Path::new("new/bin"),
+            Path::new("db"),
+            Path::new("db.upgrade"),
+            "postgres",
+            PgUpgradeMode::Link,
+        );
+        assert_eq!(
+            vec![
+                "-b",
+                "old/bin",
+                "-B",
+                "new/bin",
+                "-d",
+                "db",
+                "-D",
+                "db.upgrade",
+                "-U",
+                "postgres",
+                "--link"
+            ],
+            args
+        );
+    }
+
+    #[test]
+    fn sql_arguments() {
+        let connection = PgClientConnection {
+            host: "localhost".to_string(),
+            port: 5432,
+            user: "postgres".to_string(),
+            password: SecretString::from("password"),
+        };
+        let prefix = [
+            "-h",
+            "localhost",
+            "-p",
+            "5432",
+            "-U",
+            "postgres",
+            "-d",
+            "app",
+            "-X",
+            "-q",
+            "-v",
+            "ON_ERROR_STOP=1",
+        ];
+        let args = sql_args(&connection, "app", PgSqlScript::Sql("SELECT 1"));
+        assert_eq!(&prefix[..], &args[..prefix.len()]);
+        assert_eq!(vec!["-c", "SELECT 1"], args[prefix.len()..].to_vec());
+        let args = sql_args(
+            &connection,
+            "app",
+            PgSqlScript::File(Path::new("setup.sql")),
+        );
+        assert_eq!(vec!["-f", "setup.sql"], args[prefix.len()..].to_vec());
+
+        let options = PgRestoreOptions {
+            clean: true,
+            jobs: Some(4),
+            ..Default::default()
+        };
+        let dump = Path::new("app.dump");
+        let args = restore_args(&connection, "app", dump, PgDumpFormat::Custom, &options);
+        assert_eq!(
+            vec![
+                "--exit-on-error",
+                "--clean",
+                "--if-exists",
+                "--jobs=4",
+                "app.dump"
+            ],
+            args[8..].to_vec()
+        );
+        let dump = Path::new("app.sql");
+        let args = restore_args(&connection, "app", dump, PgDumpFormat::Plain, &options);
+        assert_eq!(vec!["-f", "app.sql"], args[prefix.len()..].to_vec());
+    }
+
+    #[test]
+    fn base_backup_arguments() {
+        let connection = PgClientConnection {
+            host: "localhost".to_string(),
+            port: 5432,
+            user: "postgres".to_string(),
+            password: SecretString::from("password"),
+        };
+        let target = Path::new("backup");
+        let args = base_backup_args(&connection, target, &PgBaseBackupOptions::default());
+        assert_eq!(
+            vec![
+                "-h",
+                "localhost",
+                "-p",
+                "5432",
+                "-U",
+                "postgres",
+                "-w",
+                "-D",
+                "backup",
+                "--format=plain",
+                "--wal-method=stream",
+                "--checkpoint=fast",
+            ],
+            args
+        );
+        let options = PgBaseBackupOptions {
+            format: PgBaseBackupFormat::Tar,
+            wal_method: PgWalMethod::Fetch,
+            fast_checkpoint: false,
+            compress: Some(6),
+            write_recovery_conf: true,
+            label: Some("nightly".to_string()),
+            slot: Some("standby".to_string()),
+            create_slot: true,
+        };
+        let args = base_backup_args(&connection, target, &options);
+        assert_eq!(
+            vec![
+                "--format=tar",
+                "--wal-method=fetch",
+                "--compress=6",
+                "--write-recovery-conf",
+                "--label",
+                "nightly",
+                "--slot=standby",
+                "--create-slot",
+            ],
+            args[9..].to_vec()
+        );
+    }
 
-        Ok(command_executor)
+    #[test]
+    fn dump_formats() {
+        let dir = std::env::temp_dir().join(format!("pg_embed_dumps_{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("directory")).unwrap();
+        std::fs::write(dir.join("custom.dump"), b"PGDMP\x01\x0e").unwrap();
+        let mut tar = vec![0u8; 512];
+        tar[257..262].copy_from_slice(b"ustar");
+        std::fs::write(dir.join("dump.tar"), tar).unwrap();
+        std::fs::write(dir.join("dump.sql"), "CREATE TABLE t (id INTEGER);").unwrap();
+        let detect = |name: &str| PgDumpFormat::detect(&dir.join(name)).ok();
+        assert_eq!(Some(PgDumpFormat::Custom), detect("custom.dump"));
+        assert_eq!(Some(PgDumpFormat::Tar), detect("dump.tar"));
+        assert_eq!(Some(PgDumpFormat::Plain), detect("dump.sql"));
+        assert_eq!(None, detect("directory"));
+        std::fs::write(dir.join("directory").join("toc.dat"), b"PGDMP").unwrap();
+        assert_eq!(Some(PgDumpFormat::Directory), detect("directory"));
+        assert_eq!(None, detect("missing.sql"));
+        std::fs::remove_dir_all(&dir).unwrap();
     }
 }
@@ -0,0 +1,815 @@
+//!
+//! Server configuration
+//!
+//! Write the configured server parameters into the database cluster before the server starts
+//!
+use std::collections::HashMap;
+use std::fmt;
+use std::net::IpAddr;
+use std::path::Path;
+use std::time::Duration;
+
+use crate::pg_enums::PgAuthMethod;
+use crate::pg_errors::PgEmbedError;
+use crate::pg_types::PgResult;
+
+/// Configuration file holding the parameters of [crate::postgres::PgSettings::server_config]
+pub const SERVER_CONFIG_FILE_NAME: &str = "pg_embed.conf";
+/// Main configuration file created by initdb
+const POSTGRESQL_CONF_FILE_NAME: &str = "postgresql.conf";
+/// Client authentication configuration file created by initdb
+const PG_HBA_CONF_FILE_NAME: &str = "pg_hba.conf";
+/// Start of the client authentication rules managed by pg-embed
+const HBA_BEGIN_MARKER: &str = "# pg-embed rules begin";
+/// End of the client authentication rules managed by pg-embed
+const HBA_END_MARKER: &str = "# pg-embed rules end";
+
+///
+/// Network interfaces the server listens on
+///
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
+pub enum PgListenAddresses {
+    /// Only accept local connections on `127.0.0.1`
+    #[default]
+    Loopback,
+    /// Accept connections on all interfaces
+    ///
+    /// Remote hosts are allowed to connect, authenticating with the configured method.
+    AllInterfaces,
+    /// Accept connections on a single address
+    ///
+    /// Use `::1` on IPv6 only hosts. An unspecified address (`0.0.0.0`, `::`) accepts
+    /// connections on all interfaces of its address family.
+    Address(IpAddr),
+}
+
+impl PgListenAddresses {
+    ///
+    /// Value of the `listen_addresses` server parameter
+    ///
+    pub fn listen_addresses(&self) -> String {
+        match self {
+            PgListenAddresses::Loopback => "127.0.0.1".to_string(),
+            PgListenAddresses::AllInterfaces => "*".to_string(),
+            PgListenAddresses::Address(address) => address.to_string(),
+        }
+    }
+
+    ///
+    /// Host clients connect to, e.g. for `psql -h`
+    ///
+    /// Unspecified addresses are connected to through the loopback address of their family.
+    ///
+    pub fn connect_host(&self) -> String {
+        match self {
+            PgListenAddresses::Loopback | PgListenAddresses::AllInterfaces => {
+                "localhost".to_string()
+            }
+            PgListenAddresses::Address(IpAddr::V4(address)) if address.is_unspecified() => {
+                "127.0.0.1".to_string()
+            }
+            PgListenAddresses::Address(IpAddr::V6(address)) if address.is_unspecified() => {
+                "::1".to_string()
+            }
+            PgListenAddresses::Address(address) => address.to_string(),
+        }
+    }
+
+    ///
+    /// Host part of connection uris, IPv6 addresses are enclosed in brackets (*`[::1]`*)
+    ///
+    pub fn uri_host(&self) -> String {
+        let host = self.connect_host();
+        if host.contains(':') {
+            format!("[{}]", host)
+        } else {
+            host
+        }
+    }
+
+    ///
+    /// Client authentication rules needed in addition to the loopback rules created by initdb
+    ///
+    pub(crate) fn hba_rules(&self, auth_method: &PgAuthMethod) -> Vec<String> {
+        match self {
+            PgListenAddresses::Loopback => Vec::new(),
+            PgListenAddresses::AllInterfaces => ["0.0.0.0/0", "::/0"]
+                .iter()
+                .map(|address| format!("host all all {} {}", address, auth_method.hba_method()))
+                .collect(),
+            // initdb allows 127.0.0.1/32 and ::1/128
+            PgListenAddresses::Address(address) if address.is_loopback() => Vec::new(),
+            PgListenAddresses::Address(address) => {
+                let network = match address {
+                    IpAddr::V4(address) if address.is_unspecified() => "0.0.0.0/0",
+                    IpAddr::V6(address) if address.is_unspecified() => "::/0",
+                    // hosts of the subnets the server is part of
+                    _ => "samenet",
+                };
+                vec![format!(
+                    "host all all {} {}",
+                    network,
+                    auth_method.hba_method()
+                )]
+            }
+        }
+    }
+}
+
+///
+/// Memory size of a server parameter
+///
+/// Rendered with the largest unit dividing the size, e.g. `128MB`.
+///
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct PgMemory {
+    kilobytes: u64,
+}
+
+impl PgMemory {
+    /// Size in kilobytes
+    pub const fn kb(kilobytes: u64) -> Self {
+        PgMemory { kilobytes }
+    }
+
+    /// Size in megabytes
+    pub const fn mb(megabytes: u64) -> Self {
+        PgMemory::kb(megabytes * 1024)
+    }
+
+    /// Size in gigabytes
+    pub const fn gb(gigabytes: u64) -> Self {
+        PgMemory::mb(gigabytes * 1024)
+    }
+
+    /// Size in kilobytes
+    pub fn kilobytes(&self) -> u64 {
+        self.kilobytes
+    }
+}
+
+impl fmt::Display for PgMemory {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let kilobytes = self.kilobytes;
+        if kilobytes > 0 && kilobytes % (1024 * 1024) == 0 {
+            write!(f, "{}GB", kilobytes / (1024 * 1024))
+        } else if kilobytes > 0 && kilobytes % 1024 == 0 {
+            write!(f, "{}MB", kilobytes / 1024)
+        } else {
+            write!(f, "{}kB", kilobytes)
+        }
+    }
+}
+
+impl std::str::FromStr for PgMemory {
+    type Err = PgEmbedError;
+
+    ///
+    /// Parse a size like `64kB`, `128MB` or `1GB`, plain numbers are kilobytes
+    ///
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        let (number, unit) = s.split_at(s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len()));
+        let multiplier = match unit.trim() {
+            "" | "kB" => Some(1),
+            "MB" => Some(1024),
+            "GB" => Some(1024 * 1024),
+            _ => None,
+        };
+        multiplier
+            .zip(number.parse::<u64>().ok())
+            .and_then(|(multiplier, number)| number.checked_mul(multiplier))
+            .map(PgMemory::kb)
+            .ok_or_else(|| PgEmbedError::InvalidServerConfig {
+                name: s.to_string(),
+                reason: "expected a memory size like 64kB, 128MB or 1GB".to_string(),
+            })
+    }
+}
+
+#[cfg(feature = "serde")]
+crate::pg_types::serde_as_string!(PgMemory);
+
+///
+/// Transaction commit durability (*`synchronous_commit`*)
+///
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
+pub enum SynchronousCommit {
+    /// wait for the local flush and synchronous standbys
+    On,
+    /// do not wait for the wal to be flushed
+    Off,
+    /// wait for the local flush only
+    Local,
+    /// wait for synchronous standbys to write the wal
+    RemoteWrite,
+    /// wait for synchronous standbys to apply the wal
+    RemoteApply,
+}
+
+impl fmt::Display for SynchronousCommit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            SynchronousCommit::On => "on",
+            SynchronousCommit::Off => "off",
+            SynchronousCommit::Local => "local",
+            SynchronousCommit::RemoteWrite => "remote_write",
+            SynchronousCommit::RemoteApply => "remote_apply",
+        };
+        write!(f, "{}", s)
+    }
+}
+
+///
+/// Statements written to the server log (*`log_statement`*)
+///
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
+pub enum LogStatement {
+    /// no statements
+    None,
+    /// data definition statements
+    Ddl,
+    /// data definition and data modifying statements
+    Mod,
+    /// all statements
+    All,
+}
+
+impl fmt::Display for LogStatement {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            LogStatement::None => "none",
+            LogStatement::Ddl => "ddl",
+            LogStatement::Mod => "mod",
+            LogStatement::All => "all",
+        };
+        write!(f, "{}", s)
+    }
+}
+
+///
+/// Information written to the wal (*`wal_level`*)
+///
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
+pub enum WalLevel {
+    /// crash recovery only
+    Minimal,
+    /// wal archiving and replication
+    Replica,
+    /// logical decoding
+    Logical,
+}
+
+impl fmt::Display for WalLevel {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            WalLevel::Minimal => "minimal",
+            WalLevel::Replica => "replica",
+            WalLevel::Logical => "logical",
+        };
+        write!(f, "{}", s)
+    }
+}
+
+///
+/// When completed wal segments are archived (*`archive_mode`*)
+///
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
+pub enum ArchiveMode {
+    /// archive on the primary
+    #[default]
+    On,
+    /// archive on standbys as well
+    Always,
+}
+
+impl fmt::Display for ArchiveMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            ArchiveMode::On => "on",
+            ArchiveMode::Always => "always",
+        };
+        write!(f, "{}", s)
+    }
+}
+
+///
+/// Wal archiving, e.g. for point in time recovery
+///
+/// Requires a `wal_level` of at least [WalLevel::Replica].
+///
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(default))]
+pub struct PgWalArchiving {
+    /// when segments are archived
+    pub mode: ArchiveMode,
+    /// shell command archiving the segment `%p` under the file name `%f`
+    /// if set to None the segments are copied into the archive directory managed by pg-embed,
+    /// see [crate::postgres::PgEmbed::wal_archive_dir]
+    pub archive_command: Option<String>,
+}
+
+impl PgWalArchiving {
+    ///
+    /// Server parameters archiving the segments, into `archive_dir` without an archive command
+    ///
+    pub(crate) fn parameters(&self, archive_dir: &Path) -> HashMap<String, String> {
+        let archive_command = self
+            .archive_command
+            .clone()
+            .unwrap_or_else(|| copy_archive_command(archive_dir));
+        let mut parameters = HashMap::new();
+        parameters.insert("archive_mode".to_string(), self.mode.to_string());
+        parameters.insert("archive_command".to_string(), archive_command);
+        parameters
+    }
+}
+
+///
+/// `archive_command` copying the segments into `archive_dir`, keeping archived segments
+///
+pub(crate) fn copy_archive_command(archive_dir: &Path) -> String {
+    let dir = archive_command_dir(archive_dir);
+    if cfg!(windows) {
+        format!(r#"if not exist "{0}%f" copy "%p" "{0}%f""#, dir)
+    } else {
+        format!("test ! -f {0}%f && cp %p {0}%f", shell_quote(&dir))
+    }
+}
+
+///
+/// `restore_command` copying the segments from `archive_dir`
+///
+pub(crate) fn copy_restore_command(archive_dir: &Path) -> String {
+    let dir = archive_command_dir(archive_dir);
+    if cfg!(windows) {
+        format!(r#"copy "{}%f" "%p""#, dir)
+    } else {
+        format!("cp {}%f %p", shell_quote(&dir))
+    }
+}
+
+///
+/// `archive_dir` with a trailing separator, `%` escaped for the archive and restore commands
+///
+fn archive_command_dir(archive_dir: &Path) -> String {
+    let separator = std::path::MAIN_SEPARATOR;
+    let dir = archive_dir.to_string_lossy();
+    let dir = dir.trim_end_matches(separator).replace('%', "%%");
+    format!("{}{}", dir, separator)
+}
+
+///
+/// Quote a shell word, e.g. a path containing spaces
+///
+fn shell_quote(word: &str) -> String {
+    format!("'{}'", word.replace('\'', r"'\''"))
+}
+
+///
+/// Typed server configuration of common tuning parameters
+///
+/// Unset parameters keep the postgresql defaults.
+///
+#[derive(Debug, Clone, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(default))]
+pub struct PostgresServerConfig {
+    /// maximum number of concurrent connections
+    pub max_connections: Option<u32>,
+    /// memory used for shared buffers
+    pub shared_buffers: Option<PgMemory>,
+    /// memory used by sort and hash operations before spilling to disk
+    pub work_mem: Option<PgMemory>,
+    /// memory used by maintenance operations like `VACUUM` and `CREATE INDEX`
+    pub maintenance_work_mem: Option<PgMemory>,
+    /// flush writes to disk
+    pub fsync: Option<bool>,
+    /// commit durability
+    pub synchronous_commit: Option<SynchronousCommit>,
+    /// write full pages to the wal after checkpoints
+    pub full_page_writes: Option<bool>,
+    /// run the autovacuum launcher
+    pub autovacuum: Option<bool>,
+    /// information written to the wal
+    pub wal_level: Option<WalLevel>,
+    /// statements written to the server log
+    pub log_statement: Option<LogStatement>,
+    /// abort statements running longer than this, in whole milliseconds
+    pub statement_timeout: Option<Duration>,
+    /// time zone of the server and of the sessions (*e.g. `UTC` or `Europe/Berlin`*)
+    ///
+    /// Makes timestamps independent of the time zone of the host machine.
+    pub timezone: Option<String>,
+}
+
+impl PostgresServerConfig {
+    ///
+    /// Apply the test performance preset
+    ///
+    /// Trades durability for speed: disables `fsync`, `synchronous_commit`, `full_page_writes`
+    /// and autovacuum and shrinks `shared_buffers`. A crash may corrupt the database,
+    /// only use it for throwaway databases like test fixtures.
+    ///
+    pub fn test_performance_preset(self) -> Self {
+        PostgresServerConfig {
+            shared_buffers: Some(PgMemory::mb(32)),
+            fsync: Some(false),
+            synchronous_commit: Some(SynchronousCommit::Off),
+            full_page_writes: Some(false),
+            autovacuum: Some(false),
+            ..self
+        }
+    }
+
+    ///
+    /// Check if writes are not flushed to disk
+    ///
+    pub(crate) fn no_sync(&self) -> bool {
+        self.fsync == Some(false)
+    }
+
+    ///
+    /// Check that the parameters are within the ranges accepted by postgresql
+    ///
+    pub fn validate(&self) -> PgResult<()> {
+        let invalid = |name: &str, reason: &str| {
+            Err(PgEmbedError::InvalidServerConfig {
+                name: name.to_string(),
+                reason: reason.to_string(),
+            })
+        };
+        if let Some(max_connections) = self.max_connections {
+            if !(1..=262_143).contains(&max_connections) {
+                return invalid("max_connections", "must be between 1 and 262143");
+            }
+        }
+        if self.shared_buffers.is_some_and(|m| m < PgMemory::kb(128)) {
+            return invalid("shared_buffers", "must be at least 128kB");
+        }
+        if self.work_mem.is_some_and(|m| m < PgMemory::kb(64)) {
+            return invalid("work_mem", "must be at least 64kB");
+        }
+        if self
+            .maintenance_work_mem
+            .is_some_and(|m| m < PgMemory::mb(1))
+        {
+            return invalid("maintenance_work_mem", "must be at least 1MB");
+        }
+        if self
+            .statement_timeout
+            .is_some_and(|t| t.as_millis() > i32::MAX as u128)
+        {
+            return invalid("statement_timeout", "must be at most 2147483647ms");
+        }
+        if let Some(timezone) = &self.timezone {
+            if timezone.is_empty() || timezone.contains(char::is_whitespace) {
+                return invalid("timezone", "must be a time zone name without whitespace");
+            }
+        }
+        Ok(())
+    }
+
+    ///
+    /// Server parameters of the configured values
+    ///
+    /// Returns an error if the configuration is invalid.
+    ///
+    pub fn parameters(&self) -> PgResult<HashMap<String, String>> {
+        self.validate()?;
+        let on_off = |on: bool| if on { "on" } else { "off" }.to_string();
+        let parameters = [
+            (
+                "max_connections",
+                self.max_connections.map(|v| v.to_string()),
+            ),
+            ("shared_buffers", self.shared_buffers.map(|v| v.to_string())),
+            ("work_mem", self.work_mem.map(|v| v.to_string())),
+            (
+                "maintenance_work_mem",
+                self.maintenance_work_mem.map(|v| v.to_string()),
+            ),
+            ("fsync", self.fsync.map(on_off)),
+            (
+                "synchronous_commit",
+                self.synchronous_commit.map(|v| v.to_string()),
+            ),
+            ("full_page_writes", self.full_page_writes.map(on_off)),
+            ("autovacuum", self.autovacuum.map(on_off)),
+            ("wal_level", self.wal_level.map(|v| v.to_string())),
+            ("log_statement", self.log_statement.map(|v| v.to_string())),
+            (
+                "statement_timeout",
+                self.statement_timeout
+                    .map(|v| format!("{}ms", v.as_millis())),
+            ),
+            ("timezone", self.timezone.clone()),
+            ("log_timezone", self.timezone.clone()),
+        ];
+        Ok(parameters
+            .iter()
+            .filter_map(|(name, value)| value.clone().map(|value| (name.to_string(), value)))
+            .collect())
+    }
+}
+
+///
+/// Write the server parameters into the database directory
+///
+/// The parameters are written to [SERVER_CONFIG_FILE_NAME], which is included at the end of
+/// `postgresql.conf` and therefore takes precedence over its settings.
+/// The file is rewritten on every call, removed parameters fall back to their defaults.
+///
+/// Returns `Ok(())` on success, otherwise returns an error.
+///
+pub(crate) fn write_server_config(
+    database_dir: &Path,
+    server_config: &HashMap<String, String>,
+) -> PgResult<()> {
+    let content = render_server_config(server_config)?;
+    let config_path = database_dir.join(SERVER_CONFIG_FILE_NAME);
+    std::fs::write(&config_path, content).map_err(|e| PgEmbedError::WriteFileError {
+        path: config_path,
+        e,
+    })?;
+
+    let conf_path = database_dir.join(POSTGRESQL_CONF_FILE_NAME);
+    let conf = std::fs::read_to_string(&conf_path).map_err(|e| PgEmbedError::ReadFileError {
+        path: conf_path.clone(),
+        e,
+    })?;
+    let include = format!("include_if_exists = '{}'", SERVER_CONFIG_FILE_NAME);
+    if !conf.lines().any(|line| line.trim() == include) {
+        let separator = if conf.is_empty() || conf.ends_with('\n') {
+            ""
+        } else {
+            "\n"
+        };
+        std::fs::write(&conf_path, format!("{}{}{}\n", conf, separator, include))
+            .map_err(|e| PgEmbedError::WriteFileError { path: conf_path, e })?;
+    }
+    Ok(())
+}
+
+///
+/// Replace the client authentication rules managed by pg-embed in `pg_hba.conf`
+///
+/// The rules are kept between marker comments at the end of the file, rules outside the
+/// markers are left untouched. Without rules the managed block is removed.
+///
+/// Returns `Ok(())` on success, otherwise returns an error.
+///
+pub(crate) fn write_hba_rules(database_dir: &Path, rules: &[String]) -> PgResult<()> {
+    let hba_path = database_dir.join(PG_HBA_CONF_FILE_NAME);
+    let hba = std::fs::read_to_string(&hba_path).map_err(|e| PgEmbedError::ReadFileError {
+        path: hba_path.clone(),
+        e,
+    })?;
+    let mut content = String::new();
+    let mut managed = false;
+    for line in hba.lines() {
+        match line.trim() {
+            HBA_BEGIN_MARKER => managed = true,
+            HBA_END_MARKER => managed = false,
+            _ if !managed => {
+                content.push_str(line);
+                content.push('\n');
+            }
+            _ => {}
+        }
+    }
+    if !rules.is_empty() {
+        content.push_str(HBA_BEGIN_MARKER);
+        content.push('\n');
+        for rule in rules {
+            content.push_str(rule);
+            content.push('\n');
+        }
+        content.push_str(HBA_END_MARKER);
+        content.push('\n');
+    }
+    if content != hba {
+        std::fs::write(&hba_path, content)
+            .map_err(|e| PgEmbedError::WriteFileError { path: hba_path, e })?;
+    }
+    Ok(())
+}
+
+///
+/// Render the parameters in `postgresql.conf` syntax, sorted by name
+///
+fn render_server_config(server_config: &HashMap<String, String>) -> PgResult<String> {
+    let mut names: Vec<&String> = server_config.keys().collect();
+    names.sort();
+    let mut content = String::from("# Written by pg-embed, changes are overwritten on start\n");
+    for name in names {
+        let value = &server_config[name];
+        let valid_name = !name.is_empty()
+            && name
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '.');
+        if !valid_name {
+            return Err(PgEmbedError::InvalidServerConfig {
+                name: name.clone(),
+                reason: "only letters, digits, '_' and '.' are allowed in parameter names"
+                    .to_string(),
+            });
+        }
+        if value.contains(['\n', '\r']) {
+            return Err(PgEmbedError::InvalidServerConfig {
+                name: name.clone(),
+                reason: "values must not contain line breaks".to_string(),
+            });
+        }
+        content.push_str(&format!("{} = {}\n", name, quote_value(value)));
+    }
+    Ok(content)
+}
+
+///
+/// Quote a parameter value, escaping quotes and backslashes
+///
+fn quote_value(value: &str) -> String {
+    format!("'{}'", value.replace('\\', "\\\\").replace('\'', "''"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    #[cfg(unix)]
+    fn wal_archiving_commands() {
+        let archive_dir = Path::new("/tmp/it's 100%/wal");
+        let parameters = PgWalArchiving::default().parameters(archive_dir);
+        assert_eq!("on", parameters["archive_mode"]);
+        assert_eq!(
+            r"test ! -f '/tmp/it'\''s 100%%/wal/'%f && cp %p '/tmp/it'\''s 100%%/wal/'%f",
+            parameters["archive_command"]
+        );
+        assert_eq!(
+            r"cp '/tmp/it'\''s 100%%/wal/'%f %p",
+            copy_restore_command(archive_dir)
+        );
+        let archiving = PgWalArchiving {
+            mode: ArchiveMode::Always,
+            archive_command: Some("true".to_string()),
+        };
+        let parameters = archiving.parameters(archive_dir);
+        assert_eq!("always", parameters["archive_mode"]);
+        assert_eq!("true", parameters["archive_command"]);
+    }
+
+    #[test]
+    fn typed_server_config() -> Result<(), PgEmbedError> {
+        let config = PostgresServerConfig {
+            max_connections: Some(20),
+            shared_buffers: Some(PgMemory::mb(128)),
+            work_mem: Some(PgMemory::kb(1536)),
+            fsync: Some(false),
+            synchronous_commit: Some(SynchronousCommit::RemoteApply),
+            statement_timeout: Some(Duration::from_secs(30)),
+            timezone: Some("UTC".to_string()),
+            ..Default::default()
+        };
+        let parameters = config.parameters()?;
+        assert_eq!(8, parameters.len());
+        assert_eq!("UTC", parameters["timezone"]);
+        assert_eq!("20", parameters["max_connections"]);
+        assert_eq!("128MB", parameters["shared_buffers"]);
+        assert_eq!("1536kB", parameters["work_mem"]);
+        assert_eq!("off", parameters["fsync"]);
+        assert_eq!("remote_apply", parameters["synchronous_commit"]);
+        assert_eq!("30000ms", parameters["statement_timeout"]);
+        assert_eq!("2GB", PgMemory::gb(2).to_string());
+        assert_eq!(PgMemory::mb(128), "128MB".parse()?);
+        assert_eq!(PgMemory::kb(64), "64".parse()?);
+        assert!("12 apples".parse::<PgMemory>().is_err());
+
+        let invalid = PostgresServerConfig {
+            max_connections: Some(0),
+            ..Default::default()
+        };
+        assert!(invalid.parameters().is_err());
+        let invalid = PostgresServerConfig {
+            work_mem: Some(PgMemory::kb(8)),
+            ..Default::default()
+        };
+        assert!(invalid.validate().is_err());
+        let invalid = PostgresServerConfig {
+            timezone: Some("Europe/ Berlin".to_string()),
+            ..Default::default()
+        };
+        assert!(invalid.validate().is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_performance_preset() -> Result<(), PgEmbedError> {
+        let config = PostgresServerConfig {
+            max_connections: Some(20),
+            ..Default::default()
+        }
+        .test_performance_preset();
+        assert!(config.no_sync());
+        let parameters = config.parameters()?;
+        assert_eq!("20", parameters["max_connections"]);
+        assert_eq!("32MB", parameters["shared_buffers"]);
+        assert_eq!("off", parameters["synchronous_commit"]);
+        assert_eq!("off", parameters["full_page_writes"]);
+        assert_eq!("off", parameters["autovacuum"]);
+        Ok(())
+    }
+
+    #[test]
+    fn hba_rules_are_replaced() -> Result<(), PgEmbedError> {
+        let dir = std::env::temp_dir().join(format!("pg_embed_hba_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let initial = "local all all md5\nhost all all 127.0.0.1/32 md5\n";
+        std::fs::write(dir.join(PG_HBA_CONF_FILE_NAME), initial).unwrap();
+
+        let rules = PgListenAddresses::AllInterfaces.hba_rules(&PgAuthMethod::ScramSha256);
+        write_hba_rules(&dir, &rules)?;
+        write_hba_rules(&dir, &rules)?;
+        assert_eq!(
+            format!(
+                "{}{}\nhost all all 0.0.0.0/0 scram-sha-256\nhost all all ::/0 scram-sha-256\n{}\n",
+                initial, HBA_BEGIN_MARKER, HBA_END_MARKER
+            ),
+            std::fs::read_to_string(dir.join(PG_HBA_CONF_FILE_NAME)).unwrap()
+        );
+
+        write_hba_rules(
+            &dir,
+            &PgListenAddresses::Loopback.hba_rules(&PgAuthMethod::MD5),
+        )?;
+        assert_eq!(
+            initial,
+            std::fs::read_to_string(dir.join(PG_HBA_CONF_FILE_NAME)).unwrap()
+        );
+        std::fs::remove_dir_all(&dir).unwrap();
+        Ok(())
+    }
+
+    #[test]
+    fn listen_addresses() {
+        let ipv6_loopback = PgListenAddresses::Address("::1".parse().unwrap());
+        assert_eq!("::1", ipv6_loopback.listen_addresses());
+        assert_eq!("::1", ipv6_loopback.connect_host());
+        assert_eq!("[::1]", ipv6_loopback.uri_host());
+        assert!(ipv6_loopback.hba_rules(&PgAuthMethod::MD5).is_empty());
+
+        let ipv6_any = PgListenAddresses::Address("::".parse().unwrap());
+        assert_eq!("[::1]", ipv6_any.uri_host());
+        assert_eq!(
+            vec!["host all all ::/0 md5"],
+            ipv6_any.hba_rules(&PgAuthMethod::MD5)
+        );
+
+        let ipv4 = PgListenAddresses::Address("192.168.1.20".parse().unwrap());
+        assert_eq!("192.168.1.20", ipv4.uri_host());
+        assert_eq!(
+            vec!["host all all samenet md5"],
+            ipv4.hba_rules(&PgAuthMethod::MD5)
+        );
+        assert_eq!("localhost", PgListenAddresses::Loopback.uri_host());
+    }
+
+    #[test]
+    fn server_config_is_included() -> Result<(), PgEmbedError> {
+        let dir = std::env::temp_dir().join(format!("pg_embed_config_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join(POSTGRESQL_CONF_FILE_NAME), "max_connections = 100").unwrap();
+
+        let mut server_config = HashMap::new();
+        server_config.insert("shared_buffers".to_string(), "128MB".to_string());
+        server_config.insert("log_line_prefix".to_string(), "it's C:\\logs".to_string());
+        write_server_config(&dir, &server_config)?;
+        write_server_config(&dir, &server_config)?;
+
+        assert_eq!(
+            "max_connections = 100\ninclude_if_exists = 'pg_embed.conf'\n",
+            std::fs::read_to_string(dir.join(POSTGRESQL_CONF_FILE_NAME)).unwrap()
+        );
+        let content = std::fs::read_to_string(dir.join(SERVER_CONFIG_FILE_NAME)).unwrap();
+        assert!(
+            content.ends_with("log_line_prefix = 'it''s C:\\\\logs'\nshared_buffers = '128MB'\n")
+        );
+
+        server_config.insert("work mem".to_string(), "4MB".to_string());
+        assert!(write_server_config(&dir, &server_config).is_err());
+        std::fs::remove_dir_all(&dir).unwrap();
+        Ok(())
+    }
+}
@@ -0,0 +1,168 @@
+//!
+//! Configuration file
+//!
+//! Load fetch and server settings from a `pg-embed.toml` file, so one configuration can be
+//! shared by several crates.
+//!
+//! ```toml
+//! [fetch]
+//! version = "16.2"
+//! minimal_install = true
+//!
+//! [server]
+//! database_dir = "target/pg/db"
+//! port = 5433
+//! password = "password"
+//! auth_method = "scram_sha_256"
+//!
+//! [server.server_tuning]
+//! shared_buffers = "32MB"
+//! fsync = false
+//! ```
+//!
+//! Both tables are optional, unset server settings get the defaults of
+//! [PgSettings::builder]. Relative paths are resolved against the directory of the file.
+//!
+use std::path::{Path, PathBuf};
+
+use serde::Deserialize;
+
+use crate::pg_errors::PgEmbedError;
+use crate::pg_fetch::{PgCertificate, PgFetchSettings};
+use crate::pg_types::PgResult;
+use crate::postgres::{PgSettings, PgSettingsBuilder};
+
+/// Conventional name of the configuration file
+pub const CONFIG_FILE_NAME: &str = "pg-embed.toml";
+
+///
+/// Content of a configuration file
+///
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PgEmbedConfig {
+    /// settings of the postgresql binaries
+    pub fetch: PgFetchSettings,
+    /// settings of the database server
+    pub server: PgSettingsBuilder,
+}
+
+impl PgEmbedConfig {
+    ///
+    /// Read a configuration file
+    ///
+    /// Returns the configuration on success, otherwise returns an error.
+    ///
+    pub fn from_file(path: impl AsRef<Path>) -> PgResult<Self> {
+        let path = path.as_ref();
+        let content = std::fs::read_to_string(path).map_err(|e| PgEmbedError::ReadFileError {
+            path: path.to_path_buf(),
+            e,
+        })?;
+        let config = Self::from_toml(&content).map_err(|e| match e {
+            PgEmbedError::InvalidConfigFile { reason, .. } => PgEmbedError::InvalidConfigFile {
+                path: path.to_path_buf(),
+                reason,
+            },
+            e => e,
+        })?;
+        let base_dir = path.parent().unwrap_or_else(|| Path::new(""));
+        Ok(config.resolve_paths(base_dir))
+    }
+
+    ///
+    /// Parse a toml configuration document
+    ///
+    /// Relative paths are kept as they are.
+    ///
+    pub fn from_toml(content: &str) -> PgResult<Self> {
+        toml::from_str(content).map_err(|e| PgEmbedError::InvalidConfigFile {
+            path: PathBuf::new(),
+            reason: e.message().to_string(),
+        })
+    }
+
+    ///
+    /// Resolve the relative paths of the settings against `base_dir`
+    ///
+    fn resolve_paths(mut self, base_dir: &Path) -> Self {
+        let resolve = |path: &mut PathBuf| {
+            if path.is_relative() {
+                *path = base_dir.join(&*path);
+            }
+        };
+        for certificate in self.fetch.root_certificates.iter_mut() {
+            if let PgCertificate::Path(path) = certificate {
+                resolve(path);
+            }
+        }
+        #[cfg(feature = "verify-signatures")]
+        if let Some(keyring) = &mut self.fetch.signature_keyring {
+            resolve(keyring);
+        }
+        self.server = self.server.map_paths(resolve);
+        self
+    }
+}
+
+impl PgSettings {
+    ///
+    /// Read the server settings of a configuration file, see [PgEmbedConfig]
+    ///
+    /// Returns the settings on success, otherwise returns an error.
+    ///
+    pub fn from_file(path: impl AsRef<Path>) -> PgResult<Self> {
+        PgEmbedConfig::from_file(path)?.server.build()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pg_config::PgMemory;
+    use crate::pg_enums::PgAuthMethod;
+    use crate::pg_fetch::PG_V16;
+
+    #[test]
+    fn read_config_file() -> Result<(), PgEmbedError> {
+        let dir = std::env::temp_dir().join(format!("pg_embed_config_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join(CONFIG_FILE_NAME);
+        std::fs::write(
+            &path,
+            r#"
+            [fetch]
+            version = "16.2"
+
+            [server]
+            database_dir = "db"
+            port = 5433
+            auth_method = "trust"
+
+            [server.server_tuning]
+            shared_buffers = "32MB"
+            "#,
+        )
+        .unwrap();
+
+        let config = PgEmbedConfig::from_file(&path)?;
+        assert_eq!(PG_V16, config.fetch.version);
+        let settings = config.server.build()?;
+        assert_eq!(dir.join("db"), settings.database_dir);
+        assert_eq!(5433, settings.port);
+        assert_eq!(PgAuthMethod::Trust, settings.auth_method);
+        assert_eq!(
+            Some(PgMemory::mb(32)),
+            settings.server_tuning.shared_buffers
+        );
+        assert_eq!("postgres", PgSettings::from_file(&path)?.user);
+
+        std::fs::write(&path, "[server]\nprot = 5433\n").unwrap();
+        assert!(matches!(
+            PgEmbedConfig::from_file(&path),
+            Err(PgEmbedError::InvalidConfigFile { .. })
+        ));
+        std::fs::remove_dir_all(&dir).unwrap();
+        Ok(())
+    }
+}
@@ -0,0 +1,118 @@
+//!
+//! Logical decoding
+//!
+//! [crate::postgres::PgEmbed::logical_decoding_stream] creates a logical replication slot and
+//! streams the decoded changes of a database, e.g. to test change data capture consumers:
+//!
+//! ```rust, ignore
+//! pg.set_wal_level(WalLevel::Logical).await?;
+//! let mut changes = pg
+//!     .logical_decoding_stream("app", "cdc", &PgDecodingPlugin::TestDecoding, interval)
+//!     .await?;
+//! while let Some(message) = changes.try_next().await? {
+//!     println!("{} {}", message.lsn, message.text().unwrap_or_default());
+//! }
+//! ```
+//!
+use crate::postgres::quote_identifier;
+
+///
+/// Output plugin decoding the changes of a logical replication slot
+///
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PgDecodingPlugin {
+    /// `test_decoding`, one text message per changed row and per transaction begin and commit
+    TestDecoding,
+    /// `pgoutput`, the binary protocol of logical replication, changes of the tables of
+    /// `publications`
+    PgOutput { publications: Vec<String> },
+}
+
+impl PgDecodingPlugin {
+    ///
+    /// Name of the output plugin
+    ///
+    pub fn name(&self) -> &'static str {
+        match self {
+            PgDecodingPlugin::TestDecoding => "test_decoding",
+            PgDecodingPlugin::PgOutput { .. } => "pgoutput",
+        }
+    }
+
+    ///
+    /// Query consuming the pending changes of the slot `$1`, returning the lsn, transaction
+    /// id and data of each message
+    ///
+    pub(crate) fn changes_query(&self) -> String {
+        match self {
+            PgDecodingPlugin::TestDecoding => "SELECT lsn::text, xid::text::bigint, \
+                 convert_to(data, 'UTF8') \
+                 FROM pg_logical_slot_get_changes($1::text::name, NULL, NULL)"
+                .to_string(),
+            PgDecodingPlugin::PgOutput { publications } => {
+                let publications = publications
+                    .iter()
+                    .map(|publication| quote_identifier(publication))
+                    .collect::<Vec<_>>()
+                    .join(",");
+                format!(
+                    "SELECT lsn::text, xid::text::bigint, data \
+                     FROM pg_logical_slot_get_binary_changes($1::text::name, NULL, NULL, \
+                     'proto_version', '1', 'publication_names', {})",
+                    crate::postgres::quote_literal(&publications)
+                )
+            }
+        }
+    }
+}
+
+///
+/// Message decoded from the wal by a [PgDecodingPlugin]
+///
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WalMessage {
+    /// log sequence number of the message, e.g. `0/16B3748`
+    pub lsn: String,
+    /// id of the transaction of the change
+    pub xid: i64,
+    /// message as written by the output plugin
+    pub data: Vec<u8>,
+}
+
+impl WalMessage {
+    ///
+    /// Message text of text output plugins like `test_decoding`
+    ///
+    /// Returns `None` if the message is not valid utf-8, e.g. a `pgoutput` message.
+    ///
+    pub fn text(&self) -> Option<&str> {
+        std::str::from_utf8(&self.data).ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn changes_queries() {
+        let plugin = PgDecodingPlugin::TestDecoding;
+        assert_eq!("test_decoding", plugin.name());
+        assert!(plugin
+            .changes_query()
+            .contains("pg_logical_slot_get_changes($1::text::name"));
+        let plugin = PgDecodingPlugin::PgOutput {
+            publications: vec!["orders".to_string(), "it's".to_string()],
+        };
+        assert_eq!("pgoutput", plugin.name());
+        assert!(plugin
+            .changes_query()
+            .ends_with(r#"'publication_names', '"orders","it''s"')"#));
+        let message = WalMessage {
+            lsn: "0/16B3748".to_string(),
+            xid: 740,
+            data: b"BEGIN 740".to_vec(),
+        };
+        assert_eq!(Some("BEGIN 740"), message.text());
+    }
+}
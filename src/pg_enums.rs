@@ -4,7 +4,7 @@
 
 use std::error::Error;
 
-use crate::command_executor::ProcessStatus;
+use crate::command_executor::{CommandOutcome, ProcessStatus};
 use crate::pg_errors::PgEmbedError;
 
 ///
@@ -13,13 +13,123 @@ use crate::pg_errors::PgEmbedError;
 /// Choose between plain password, md5 or scram_sha_256 authentication.
 /// Scram_sha_256 authentication is only available on postgresql versions >= 11
 ///
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
 pub enum PgAuthMethod {
     /// plain-text
     Plain,
     /// md5
+    #[cfg_attr(feature = "serde", serde(rename = "md5"))]
     MD5,
     /// scram_sha_256
+    #[cfg_attr(
+        feature = "serde",
+        serde(rename = "scram_sha_256", alias = "scram-sha-256")
+    )]
     ScramSha256,
+    /// no password, every local connection is accepted
+    ///
+    /// Only meant for throwaway databases, no password file is created.
+    Trust,
+}
+
+impl PgAuthMethod {
+    ///
+    /// Check if the authentication method needs a password
+    ///
+    pub fn requires_password(&self) -> bool {
+        !matches!(self, PgAuthMethod::Trust)
+    }
+
+    ///
+    /// Name of the authentication method in `pg_hba.conf` and for initdb
+    ///
+    pub fn hba_method(&self) -> &'static str {
+        match self {
+            PgAuthMethod::Plain => "password",
+            PgAuthMethod::MD5 => "md5",
+            PgAuthMethod::ScramSha256 => "scram-sha-256",
+            PgAuthMethod::Trust => "trust",
+        }
+    }
+}
+
+///
+/// Shutdown mode of `pg_ctl stop`
+///
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum PgShutdownMode {
+    /// wait for all clients to disconnect
+    Smart,
+    /// abort open transactions and disconnect the clients
+    #[default]
+    Fast,
+    /// quit all processes without a clean shutdown, recovery runs on the next start
+    Immediate,
+}
+
+impl std::fmt::Display for PgShutdownMode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PgShutdownMode::Smart => write!(f, "smart"),
+            PgShutdownMode::Fast => write!(f, "fast"),
+            PgShutdownMode::Immediate => write!(f, "immediate"),
+        }
+    }
+}
+
+///
+/// How to handle a server left running by a previous process on start, e.g. after a test
+/// binary was killed before it could stop the server
+///
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
+pub enum StalePidPolicy {
+    /// kill the running server and start a new one
+    ///
+    /// Only a server verified to serve the database directory is killed, otherwise this
+    /// behaves like [StalePidPolicy::Error].
+    #[default]
+    Terminate,
+    /// use the running server as if it was started
+    Attach,
+    /// fail with [crate::pg_errors::PgEmbedError::StalePostmaster]
+    Error,
+}
+
+///
+/// How pg_upgrade transfers the data files into the new database cluster
+///
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum PgUpgradeMode {
+    /// copy the files, the old cluster stays usable
+    #[default]
+    Copy,
+    /// hard link the files, much faster but the old cluster must not be started anymore
+    Link,
+}
+
+impl std::fmt::Display for PgUpgradeMode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PgUpgradeMode::Copy => write!(f, "copy"),
+            PgUpgradeMode::Link => write!(f, "link"),
+        }
+    }
+}
+
+///
+/// How the files of a database directory were cloned
+///
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PgCloneMethod {
+    /// copy-on-write clones sharing the data blocks (*reflinks on Btrfs and XFS, clonefile on
+    /// APFS*)
+    Reflink,
+    /// full copies
+    Copy,
 }
 
 ///
@@ -57,30 +167,65 @@ pub enum PgProcessType {
     StartDb,
     /// pg_ctl stop process
     StopDb,
+    /// pg_dump process
+    DumpDb,
+    /// pg_restore process
+    RestoreDb,
+    /// pg_ctl reload process
+    ReloadDb,
+    /// pg_upgrade process
+    UpgradeDb,
+    /// psql process
+    Sql,
+    /// pg_basebackup process
+    BaseBackup,
+    /// pg_ctl promote process
+    PromoteDb,
 }
 
 impl ProcessStatus<PgServerStatus, PgEmbedError> for PgProcessType {
     fn status_entry(&self) -> PgServerStatus {
         match self {
-            PgProcessType::InitDb => PgServerStatus::Initializing,
+            PgProcessType::InitDb | PgProcessType::UpgradeDb => PgServerStatus::Initializing,
             PgProcessType::StartDb => PgServerStatus::Starting,
             PgProcessType::StopDb => PgServerStatus::Stopping,
+            // dump, restore, reload, sql, base backups and promotion run against a started
+            // server
+            PgProcessType::DumpDb
+            | PgProcessType::RestoreDb
+            | PgProcessType::ReloadDb
+            | PgProcessType::Sql
+            | PgProcessType::BaseBackup
+            | PgProcessType::PromoteDb => PgServerStatus::Started,
         }
     }
 
     fn status_exit(&self) -> PgServerStatus {
         match self {
-            PgProcessType::InitDb => PgServerStatus::Initialized,
+            PgProcessType::InitDb | PgProcessType::UpgradeDb => PgServerStatus::Initialized,
             PgProcessType::StartDb => PgServerStatus::Started,
             PgProcessType::StopDb => PgServerStatus::Stopped,
+            PgProcessType::DumpDb
+            | PgProcessType::RestoreDb
+            | PgProcessType::ReloadDb
+            | PgProcessType::Sql
+            | PgProcessType::BaseBackup
+            | PgProcessType::PromoteDb => PgServerStatus::Started,
         }
     }
 
     fn error_type(&self) -> PgEmbedError {
         match self {
-            PgProcessType::InitDb => PgEmbedError::PgInitFailure,
-            PgProcessType::StartDb => PgEmbedError::PgStartFailure,
-            PgProcessType::StopDb => PgEmbedError::PgStopFailure,
+            PgProcessType::InitDb => PgEmbedError::PgInitFailure { outcome: None },
+            PgProcessType::StartDb => PgEmbedError::PgStartFailure { outcome: None },
+            PgProcessType::StopDb => PgEmbedError::PgStopFailure { outcome: None },
+            PgProcessType::DumpDb => PgEmbedError::PgDumpFailure,
+            PgProcessType::RestoreDb => PgEmbedError::PgRestoreFailure,
+            PgProcessType::ReloadDb => PgEmbedError::PgReloadFailure,
+            PgProcessType::UpgradeDb => PgEmbedError::PgUpgradeFailure,
+            PgProcessType::Sql => PgEmbedError::PgSqlFailure,
+            PgProcessType::BaseBackup => PgEmbedError::PgBaseBackupFailure,
+            PgProcessType::PromoteDb => PgEmbedError::PgPromoteFailure,
         }
     }
 
@@ -94,6 +239,24 @@ impl ProcessStatus<PgServerStatus, PgEmbedError> for PgProcessType {
             message,
         }
     }
+
+    fn failure_error(&self, outcome: CommandOutcome) -> PgEmbedError {
+        match self {
+            PgProcessType::InitDb => PgEmbedError::PgInitFailure {
+                outcome: Some(outcome),
+            },
+            PgProcessType::StartDb => PgEmbedError::PgStartFailure {
+                outcome: Some(outcome),
+            },
+            PgProcessType::StopDb => PgEmbedError::PgStopFailure {
+                outcome: Some(outcome),
+            },
+            _ => PgEmbedError::PgProcessFailure {
+                process: self.to_string(),
+                outcome,
+            },
+        }
+    }
 }
 
 impl std::fmt::Display for PgProcessType {
@@ -102,6 +265,13 @@ impl std::fmt::Display for PgProcessType {
             PgProcessType::InitDb => write!(f, "initdb"),
             PgProcessType::StartDb => write!(f, "start"),
             PgProcessType::StopDb => write!(f, "stop"),
+            PgProcessType::DumpDb => write!(f, "pg_dump"),
+            PgProcessType::RestoreDb => write!(f, "pg_restore"),
+            PgProcessType::ReloadDb => write!(f, "reload"),
+            PgProcessType::UpgradeDb => write!(f, "pg_upgrade"),
+            PgProcessType::Sql => write!(f, "psql"),
+            PgProcessType::BaseBackup => write!(f, "pg_basebackup"),
+            PgProcessType::PromoteDb => write!(f, "promote"),
         }
     }
 }
@@ -130,8 +300,64 @@ impl std::fmt::Display for OperationSystem {
     }
 }
 
-impl Default for OperationSystem {
-    fn default() -> Self {
+///
+/// Environment variable overriding the detected operating system
+///
+/// Accepts `darwin`, `windows`, `linux` or `alpine`
+///
+pub const PG_EMBED_OS_ENV: &str = "PG_EMBED_OS";
+
+lazy_static! {
+    ///
+    /// Operating system detected once per process, the musl detection may spawn `ldd`
+    ///
+    static ref DETECTED_OS: OperationSystem = OperationSystem::detect_platform();
+}
+
+impl std::str::FromStr for OperationSystem {
+    type Err = PgEmbedError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_str() {
+            "darwin" | "macos" => Ok(OperationSystem::Darwin),
+            "windows" => Ok(OperationSystem::Windows),
+            "linux" => Ok(OperationSystem::Linux),
+            "alpine" | "alpinelinux" | "musl" => Ok(OperationSystem::AlpineLinux),
+            _ => Err(PgEmbedError::InvalidOperationSystem(s.to_string())),
+        }
+    }
+}
+
+impl OperationSystem {
+    ///
+    /// Detect the operating system at runtime
+    ///
+    /// On linux Alpine (*or any other musl based distribution*) is detected by checking for
+    /// `/etc/alpine-release` or a musl `ldd`, so that binaries linked against musl are fetched.
+    ///
+    /// The detection can be overridden with the [PG_EMBED_OS_ENV] environment variable, an
+    /// invalid value is ignored with a warning. The detected operating system is cached.
+    ///
+    pub fn detect() -> Self {
+        Self::detect_with_override(std::env::var(PG_EMBED_OS_ENV).ok().as_deref())
+    }
+
+    ///
+    /// Detect the operating system unless overridden by a valid `os_override`
+    ///
+    fn detect_with_override(os_override: Option<&str>) -> Self {
+        match os_override.map(str::parse::<OperationSystem>) {
+            Some(Ok(os)) => return os,
+            Some(Err(e)) => log::warn!("Ignoring {}: {}", PG_EMBED_OS_ENV, e),
+            None => {}
+        }
+        *DETECTED_OS
+    }
+
+    ///
+    /// Detect the operating system of the running platform
+    ///
+    fn detect_platform() -> Self {
         #[cfg(not(any(target_os = "linux", target_os = "windows")))]
         {
             OperationSystem::Darwin
@@ -139,7 +365,11 @@ impl Default for OperationSystem {
 
         #[cfg(target_os = "linux")]
         {
-            OperationSystem::Linux
+            if Self::is_musl_linux() {
+                OperationSystem::AlpineLinux
+            } else {
+                OperationSystem::Linux
+            }
         }
 
         #[cfg(target_os = "windows")]
@@ -147,10 +377,55 @@ impl Default for OperationSystem {
             OperationSystem::Windows
         }
     }
+
+    ///
+    /// Check if the running linux distribution is musl based
+    ///
+    #[cfg(target_os = "linux")]
+    fn is_musl_linux() -> bool {
+        if cfg!(target_env = "musl") || std::path::Path::new("/etc/alpine-release").exists() {
+            return true;
+        }
+        // musl's ldd prints its version to stderr and exits with a non zero code
+        std::process::Command::new("ldd")
+            .arg("--version")
+            .output()
+            .map(|output| {
+                let stdout = String::from_utf8_lossy(&output.stdout).to_lowercase();
+                let stderr = String::from_utf8_lossy(&output.stderr).to_lowercase();
+                stdout.contains("musl") || stderr.contains("musl")
+            })
+            .unwrap_or(false)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl serde::Serialize for OperationSystem {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        // the platform name of alpine is `linux` too
+        match self {
+            OperationSystem::AlpineLinux => serializer.serialize_str("alpine"),
+            os => serializer.collect_str(os),
+        }
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for OperationSystem {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        s.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+impl Default for OperationSystem {
+    fn default() -> Self {
+        Self::detect()
+    }
 }
 
 /// The cpu architectures enum
-#[derive(Debug, PartialEq, Copy, Clone)]
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
 pub enum Architecture {
     Amd64,
     I386,
@@ -174,6 +449,115 @@ impl std::fmt::Display for Architecture {
     }
 }
 
+impl std::str::FromStr for Architecture {
+    type Err = PgEmbedError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_str() {
+            "amd64" | "x86_64" => Ok(Architecture::Amd64),
+            "i386" | "x86" => Ok(Architecture::I386),
+            "arm32v6" => Ok(Architecture::Arm32v6),
+            "arm32v7" | "arm" => Ok(Architecture::Arm32v7),
+            "arm64v8" | "aarch64" => Ok(Architecture::Arm64v8),
+            "ppc64le" | "powerpc64le" => Ok(Architecture::Ppc64le),
+            _ => Err(PgEmbedError::InvalidArchitecture(s.to_string())),
+        }
+    }
+}
+
+impl Architecture {
+    ///
+    /// Determine the cpu architecture of an executable from its header
+    ///
+    /// Supports ELF, Mach-O and PE executables. Returns `None` for unknown formats and
+    /// universal (*multi architecture*) Mach-O binaries.
+    ///
+    /// 32 bit arm executables are always reported as [Architecture::Arm32v7].
+    ///
+    pub fn from_binary_header(header: &[u8]) -> Option<Architecture> {
+        let u16_le = |offset: usize| {
+            header
+                .get(offset..offset + 2)
+                .map(|b| u16::from_le_bytes([b[0], b[1]]))
+        };
+        let u32_le = |offset: usize| {
+            header
+                .get(offset..offset + 4)
+                .map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+        };
+
+        // ELF
+        if header.starts_with(&[0x7f, b'E', b'L', b'F']) {
+            let machine = match header.get(5)? {
+                2 => header
+                    .get(18..20)
+                    .map(|b| u16::from_be_bytes([b[0], b[1]]))?,
+                _ => u16_le(18)?,
+            };
+            return match machine {
+                3 => Some(Architecture::I386),
+                62 => Some(Architecture::Amd64),
+                40 => Some(Architecture::Arm32v7),
+                183 => Some(Architecture::Arm64v8),
+                21 => Some(Architecture::Ppc64le),
+                _ => None,
+            };
+        }
+
+        // Mach-O (little endian, 32 and 64 bit)
+        if header.starts_with(&[0xce, 0xfa, 0xed, 0xfe])
+            || header.starts_with(&[0xcf, 0xfa, 0xed, 0xfe])
+        {
+            return match u32_le(4)? {
+                0x0000_0007 => Some(Architecture::I386),
+                0x0100_0007 => Some(Architecture::Amd64),
+                0x0000_000c => Some(Architecture::Arm32v7),
+                0x0100_000c => Some(Architecture::Arm64v8),
+                _ => None,
+            };
+        }
+
+        // PE
+        if header.starts_with(b"MZ") {
+            let pe_offset = u32_le(0x3c)? as usize;
+            if header.get(pe_offset..pe_offset + 4)? != b"PE\0\0" {
+                return None;
+            }
+            return match u16_le(pe_offset + 4)? {
+                0x014c => Some(Architecture::I386),
+                0x8664 => Some(Architecture::Amd64),
+                0x01c0 | 0x01c4 => Some(Architecture::Arm32v7),
+                0xaa64 => Some(Architecture::Arm64v8),
+                _ => None,
+            };
+        }
+
+        None
+    }
+
+    ///
+    /// Check if executables built for `architecture` can run on this (*host*) architecture
+    ///
+    pub fn can_execute(&self, architecture: Architecture) -> bool {
+        use Architecture::*;
+        match (self, architecture) {
+            (Arm32v6 | Arm32v7, Arm32v6 | Arm32v7) => true,
+            (Amd64, I386) => true,
+            // Apple Silicon executes amd64 binaries through Rosetta
+            (Arm64v8, Amd64) => {
+                cfg!(target_os = "macos")
+                    && std::path::Path::new("/Library/Apple/usr/libexec/oah/libRosettaRuntime")
+                        .exists()
+            }
+            (host, architecture) => *host == architecture,
+        }
+    }
+}
+
+#[cfg(feature = "serde")]
+crate::pg_types::serde_as_string!(Architecture);
+
+#[allow(clippy::derivable_impls)]
 impl Default for Architecture {
     fn default() -> Self {
         #[cfg(not(any(
@@ -209,12 +593,116 @@ impl Default for Architecture {
 }
 
 /// The postgresql binaries acquisition status
-#[derive(Copy, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum PgAcquisitionStatus {
     /// Acquiring postgresql binaries
     InProgress,
     /// Finished acquiring postgresql binaries
     Finished,
+    /// Acquiring postgresql binaries failed with the contained cause,
+    /// the next acquisition attempt retries
+    Failed(String),
     /// No acquisition
     Undefined,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn process_failures_carry_the_outcome() {
+        let outcome = CommandOutcome {
+            exit_code: Some(1),
+            stderr_tail: "could not bind IPv4 address".to_string(),
+            ..Default::default()
+        };
+        let error = PgProcessType::StartDb.failure_error(outcome.clone());
+        assert!(error.to_string().contains("could not bind IPv4 address"));
+        match error {
+            PgEmbedError::PgStartFailure {
+                outcome: Some(found),
+            } => assert_eq!(outcome, found),
+            error => panic!("unexpected error {:?}", error),
+        }
+        assert!(matches!(
+            PgProcessType::InitDb.failure_error(outcome.clone()),
+            PgEmbedError::PgInitFailure { outcome: Some(_) }
+        ));
+        assert!(matches!(
+            PgProcessType::Sql.failure_error(outcome),
+            PgEmbedError::PgProcessFailure { .. }
+        ));
+        assert_eq!(
+            "Postgresql could not be stopped",
+            PgProcessType::StopDb.error_type().to_string()
+        );
+    }
+
+    #[test]
+    fn operation_system_from_str() {
+        for (s, os) in [
+            ("darwin", OperationSystem::Darwin),
+            ("macOS", OperationSystem::Darwin),
+            ("windows", OperationSystem::Windows),
+            (" linux ", OperationSystem::Linux),
+            ("alpine", OperationSystem::AlpineLinux),
+            ("AlpineLinux", OperationSystem::AlpineLinux),
+            ("musl", OperationSystem::AlpineLinux),
+        ] {
+            assert_eq!(os, s.parse::<OperationSystem>().unwrap());
+        }
+        assert!(matches!(
+            "solaris".parse::<OperationSystem>(),
+            Err(PgEmbedError::InvalidOperationSystem(_))
+        ));
+    }
+
+    #[test]
+    fn operation_system_override() {
+        assert_eq!(
+            OperationSystem::AlpineLinux,
+            OperationSystem::detect_with_override(Some("alpine"))
+        );
+        // invalid overrides fall back to the cached detection
+        assert_eq!(
+            OperationSystem::detect_with_override(None),
+            OperationSystem::detect_with_override(Some("solaris"))
+        );
+    }
+
+    #[test]
+    fn architecture_from_binary_header() {
+        let mut elf = vec![0x7f, b'E', b'L', b'F', 2, 1, 1, 0];
+        elf.resize(20, 0);
+        elf[18] = 62;
+        assert_eq!(
+            Some(Architecture::Amd64),
+            Architecture::from_binary_header(&elf)
+        );
+        elf[18] = 183;
+        assert_eq!(
+            Some(Architecture::Arm64v8),
+            Architecture::from_binary_header(&elf)
+        );
+
+        let mut macho = vec![0xcf, 0xfa, 0xed, 0xfe];
+        macho.extend_from_slice(&0x0100_000c_u32.to_le_bytes());
+        assert_eq!(
+            Some(Architecture::Arm64v8),
+            Architecture::from_binary_header(&macho)
+        );
+
+        let mut pe = vec![0u8; 0x90];
+        pe[..2].copy_from_slice(b"MZ");
+        pe[0x3c..0x40].copy_from_slice(&0x80_u32.to_le_bytes());
+        pe[0x80..0x84].copy_from_slice(b"PE\0\0");
+        pe[0x84..0x86].copy_from_slice(&0x8664_u16.to_le_bytes());
+        assert_eq!(
+            Some(Architecture::Amd64),
+            Architecture::from_binary_header(&pe)
+        );
+
+        assert_eq!(None, Architecture::from_binary_header(b"#!/bin/sh"));
+    }
+}
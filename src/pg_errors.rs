@@ -6,6 +6,11 @@ use std::path::PathBuf;
 use thiserror::Error;
 use zip::result::ZipError;
 
+use crate::command_executor::CommandOutcome;
+use crate::pg_commands::PgDumpFormat;
+use crate::pg_enums::Architecture;
+use crate::pg_fetch::PostgresVersion;
+
 ///
 /// Common pg_embed errors, independent of features used
 ///
@@ -16,6 +21,9 @@ pub enum PgEmbedError {
     NoSystemCacheDirectory,
     #[error("Invalid postgresql binaries package")]
     InvalidPgPackage,
+    /// Invalid postgresql version string
+    #[error("Invalid postgresql version: {0}")]
+    InvalidPgVersion(String),
     #[error("Could not write file: {path} due to error {e}")]
     WriteFileError { e: std::io::Error, path: PathBuf },
     #[error("Failed to unzip: {path} due to error {e}")]
@@ -28,26 +36,198 @@ pub enum PgEmbedError {
     /// Failed to unpack postgresql binaries
     #[error("Failed to unpack postgresql binaries: {0}")]
     UnpackFailure(#[from] std::io::Error),
-    #[error("Postgresql could not be started")]
-    PgStartFailure,
-    #[error("Postgresql could not be stopped")]
-    PgStopFailure,
-    /// Postgresql could not be initialized
-    #[error("Failed to initialize postgres database")]
-    PgInitFailure,
+    /// Postgresql could not be started, with the pg_ctl output if it ran
+    #[error("Postgresql could not be started{}", with_outcome(.outcome))]
+    PgStartFailure { outcome: Option<CommandOutcome> },
+    /// Postgresql could not be stopped, with the pg_ctl output if it ran
+    #[error("Postgresql could not be stopped{}", with_outcome(.outcome))]
+    PgStopFailure { outcome: Option<CommandOutcome> },
+    /// Operation requires a stopped postgresql server
+    #[error("Postgresql server must be stopped to {0}")]
+    PgServerRunning(String),
+    /// Operation requires a started postgresql server
+    #[error("Postgresql server must be started to {0}")]
+    PgServerNotRunning(String),
+    /// Postgresql process exited unsuccessfully
+    #[error("Postgresql {process} process failed with {outcome}")]
+    PgProcessFailure {
+        process: String,
+        outcome: CommandOutcome,
+    },
+    /// pgbench succeeded, but its report contains no throughput
+    #[error("pgbench reported no throughput: {output}")]
+    PgBenchReportInvalid { output: String },
+    /// Postgresql could not be initialized, with the initdb output if it ran
+    #[error("Failed to initialize postgres database{}", with_outcome(.outcome))]
+    PgInitFailure { outcome: Option<CommandOutcome> },
+    /// Database dump failed
+    #[error("Failed to dump postgres database")]
+    PgDumpFailure,
+    /// Database restore failed
+    #[error("Failed to restore postgres database")]
+    PgRestoreFailure,
+    /// Restoring a dump with [crate::postgres::PgEmbed::restore] failed
+    #[error("Restoring the {format} dump {path} failed with {outcome}")]
+    RestoreFailure {
+        path: PathBuf,
+        format: PgDumpFormat,
+        outcome: CommandOutcome,
+    },
+    /// A server left running by a previous process holds the database directory
+    #[error("Postgresql server process {pid} is still running on {database_dir}")]
+    StalePostmaster { pid: u32, database_dir: PathBuf },
+    /// Failed to upgrade the database cluster
+    #[error("Failed to upgrade postgres database cluster")]
+    PgUpgradeFailure,
+    /// The postgresql binaries package contains no tool of this name
+    #[error("Postgresql binaries package contains no tool {0}")]
+    ToolNotFound(String),
+    /// Failed to run sql with psql
+    #[error("Failed to run sql")]
+    PgSqlFailure,
+    /// Failed to take a base backup with pg_basebackup
+    #[error("Failed to take postgres base backup")]
+    PgBaseBackupFailure,
+    /// Failed to promote a standby
+    #[error("Failed to promote postgres standby")]
+    PgPromoteFailure,
+    /// Operation requires a standby server
+    #[error("Postgresql server on port {0} is not a standby")]
+    PgNotStandby(u16),
+    /// Failed to reload the server configuration
+    #[error("Failed to reload postgres configuration")]
+    PgReloadFailure,
+    /// Cache directory lock error
+    #[error("Failed to lock {path} due to {e}")]
+    CacheLockError { e: std::io::Error, path: PathBuf },
+    /// The database directory has not been initialized
+    #[error("Database directory {0} is not initialized")]
+    DataDirNotInitialized(PathBuf),
+    /// The database directory has already been initialized
+    #[error("Database directory {0} is already initialized")]
+    DataDirInitialized(PathBuf),
+    /// The database directory was created by another postgresql major version
+    #[error("Database directory {path} was created by postgresql {found}, expected postgresql {}", expected.data_dir_version())]
+    DataDirVersionMismatch {
+        path: PathBuf,
+        found: String,
+        expected: PostgresVersion,
+    },
+    /// The database directory is locked by another instance
+    #[error("Database directory {path} is used by another PgEmbed instance")]
+    DataDirInUse { path: PathBuf },
+    /// Database directory lock error
+    #[error("Failed to lock database directory {path} due to {e}")]
+    DataDirLockError { e: std::io::Error, path: PathBuf },
     /// Clean up error
     #[error("Failed to remove {path} due to {e}")]
     PgCleanUpFailure { e: std::io::Error, path: PathBuf },
+    /// Unknown operating system name
+    #[error("Invalid operating system: {0}")]
+    InvalidOperationSystem(String),
+    /// Unknown cpu architecture name
+    #[error("Invalid architecture: {0}")]
+    InvalidArchitecture(String),
+    /// The fetched postgresql binaries can not be executed on this machine
+    #[error("Postgresql binary {path} is built for {found}, but this machine is {expected}, check the configured architecture")]
+    ArchitectureMismatch {
+        expected: Architecture,
+        found: Architecture,
+        path: PathBuf,
+    },
+    /// Operating system user does not exist
+    #[error("Operating system user {0} not found")]
+    OsUserNotFound(String),
+    /// Feature is not available on the current platform
+    #[error("Not supported on this platform: {0}")]
+    UnsupportedPlatform(String),
     /// Task join error
     #[error("{message} due to error: {source}")]
     PgError {
         source: Box<dyn std::error::Error + Sync + Send + 'static>,
         message: String,
     },
+    /// Postgresql version is not available for the platform
+    #[error(
+        "Postgresql {version} is not available for {platform}, available versions: [{}]",
+        .available.iter().map(|v| v.to_string()).collect::<Vec<_>>().join(", ")
+    )]
+    VersionUnavailable {
+        version: PostgresVersion,
+        platform: String,
+        available: Vec<PostgresVersion>,
+    },
+    /// Repository host or artifact url template is malformed
+    #[error("Invalid postgresql binaries url {url}: {reason}")]
+    InvalidFetchUrl { url: String, reason: String },
+    /// Server configuration parameter can not be written to the configuration file
+    #[error("Invalid server configuration parameter {name}: {reason}")]
+    InvalidServerConfig { name: String, reason: String },
+    /// Configuration file can not be read or does not describe valid settings
+    #[error("Invalid configuration file {path}: {reason}")]
+    InvalidConfigFile { path: PathBuf, reason: String },
+    /// Snapshot name is invalid or no snapshot of this name exists
+    #[error("Invalid snapshot {name}: {reason}")]
+    InvalidSnapshot { name: String, reason: String },
+    /// Cron expression of a backup schedule can not be parsed
+    #[error("Invalid backup schedule {expression}: {reason}")]
+    InvalidBackupSchedule { expression: String, reason: String },
+    /// Instance name of a [crate::pg_manager::PgEmbedManager] is invalid
+    #[error("Invalid instance name {name}: {reason}")]
+    InvalidInstanceName { name: String, reason: String },
+    /// Extension is not shipped with the postgresql binaries, see
+    /// [crate::postgres::PgEmbed::available_extensions]
+    #[error("Extension {name} is not available for postgresql {version}")]
+    ExtensionUnavailable {
+        name: String,
+        version: PostgresVersion,
+    },
+    /// Extension archive can not be installed, see [crate::pg_extension::PgExtensionInstaller]
+    #[error("Invalid extension archive {path}: {reason}")]
+    InvalidExtensionArchive { path: PathBuf, reason: String },
+    /// Extension could not be built from its sources
+    #[error("Building extension {name} failed: {reason}")]
+    ExtensionBuildFailure { name: String, reason: String },
+    /// Signature of the downloaded binaries is invalid or could not be checked
+    #[error("Signature verification of {url} failed: {message}")]
+    SignatureVerificationFailed { url: String, message: String },
+    /// Sha256 hash of a downloaded archive doesn't match the expected one
+    #[error("Checksum mismatch of {url}: expected sha256 {expected}, got {actual}")]
+    ChecksumMismatch {
+        url: String,
+        expected: String,
+        actual: String,
+    },
     #[error("Download failure: {0}")]
     DownloadFailure(#[from] reqwest::Error),
+    /// Download exceeded [crate::pg_types::Timeouts::download_connect] or
+    /// [crate::pg_types::Timeouts::download]
+    #[error("Download of {url} timed out: {source}")]
+    DownloadTimeout { url: String, source: reqwest::Error },
     #[error("Sqlx query error: {0}")]
     SqlxError(#[from] sqlx_tokio::error::Error),
+    /// Bootstrap sql script failed, see [crate::postgres::PgSettings::bootstrap_sql]
+    #[error("Bootstrap sql script {path} failed: {source}")]
+    BootstrapSqlFailure {
+        path: PathBuf,
+        source: sqlx_tokio::error::Error,
+    },
+    /// None of the maintenance databases could be connected to
+    #[error("Could not connect to a maintenance database (tried {databases:?}): {source}")]
+    NoMaintenanceDatabase {
+        databases: Vec<String>,
+        source: sqlx_tokio::error::Error,
+    },
     #[error("Migration error: {0}")]
     MigrationError(#[from] sqlx_tokio::migrate::MigrateError),
 }
+
+///
+/// Describe the outcome of a failed process, if it ran
+///
+fn with_outcome(outcome: &Option<CommandOutcome>) -> String {
+    outcome
+        .as_ref()
+        .map(|outcome| format!(" with {}", outcome))
+        .unwrap_or_default()
+}
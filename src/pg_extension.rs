@@ -0,0 +1,288 @@
+//!
+//! Install third party extensions
+//!
+//! Extension builds (*e.g. of pgvector or PostGIS*) matching the cached postgresql version are
+//! installed into the cached binaries, afterwards they are created like the bundled
+//! extensions:
+//!
+//! ```rust, ignore
+//! pg.pg_access.install_extension(Path::new("pgvector-pg16.tar.gz")).await?;
+//! pg.create_extension("app", "vector").await?;
+//! ```
+//!
+use std::path::{Path, PathBuf};
+
+use crate::pg_archive;
+use crate::pg_errors::PgEmbedError;
+use crate::pg_types::PgResult;
+
+/// Bundled extension locating the extension and library directories of the binaries
+const PROBE_EXTENSION: &str = "plpgsql";
+/// Directory inside the cache directory the archive is extracted to before installing
+const STAGING_DIR_NAME: &str = "pg_embed.extension";
+
+///
+/// Installs extension archives into cached postgresql binaries
+///
+/// The archive is a tar archive, optionally gzip or zstd compressed, of any layout. Its
+/// control files and sql scripts are installed into the extension directory and its shared
+/// libraries into the library directory of the binaries, other files like headers and
+/// documentation are skipped.
+///
+/// Installed extensions are lost when the binaries are acquired again, e.g. after
+/// [crate::pg_access::PgAccess::verify_cache] detected damaged binaries.
+///
+#[derive(Debug, Clone)]
+pub struct PgExtensionInstaller {
+    /// cache directory containing the postgresql binaries
+    cache_dir: PathBuf,
+}
+
+impl PgExtensionInstaller {
+    ///
+    /// Installer of the postgresql binaries cached in `cache_dir`
+    ///
+    pub fn new(cache_dir: impl Into<PathBuf>) -> Self {
+        PgExtensionInstaller {
+            cache_dir: cache_dir.into(),
+        }
+    }
+
+    ///
+    /// Directory of the extension control files and sql scripts, e.g. `share/extension`
+    ///
+    pub fn extension_dir(&self) -> PgResult<PathBuf> {
+        let control_file = format!("{}.control", PROBE_EXTENSION);
+        find_parent_dir(&self.cache_dir.join("share"), |name| name == control_file)?
+            .ok_or(PgEmbedError::InvalidPgPackage)
+    }
+
+    ///
+    /// Directory of the extension shared libraries, e.g. `lib`
+    ///
+    pub fn library_dir(&self) -> PgResult<PathBuf> {
+        find_parent_dir(&self.cache_dir.join("lib"), |name| {
+            Path::new(name)
+                .file_stem()
+                .is_some_and(|stem| stem == PROBE_EXTENSION)
+                && is_shared_library(name)
+        })?
+        .ok_or(PgEmbedError::InvalidPgPackage)
+    }
+
+    ///
+    /// Install the extensions of the archive `archive_path`, replacing installed files
+    ///
+    /// Returns the names of the installed extensions, an error if the archive contains no
+    /// extension control file.
+    ///
+    pub fn install(&self, archive_path: &Path) -> PgResult<Vec<String>> {
+        let extension_dir = self.extension_dir()?;
+        let library_dir = self.library_dir()?;
+        let staging_dir = self.cache_dir.join(STAGING_DIR_NAME);
+        remove_staging_dir(&staging_dir)?;
+        let result = pg_archive::extract_archive(archive_path, &staging_dir).and_then(|_| {
+            let mut files = Vec::new();
+            collect_files(&staging_dir, &mut files)?;
+            install_files(archive_path, files, &extension_dir, &library_dir)
+        });
+        remove_staging_dir(&staging_dir)?;
+        result
+    }
+
+    ///
+    /// Install the extensions of the unpacked package `package_dir`, see
+    /// [PgExtensionInstaller::install]
+    ///
+    pub fn install_dir(&self, package_dir: &Path) -> PgResult<Vec<String>> {
+        let extension_dir = self.extension_dir()?;
+        let library_dir = self.library_dir()?;
+        let mut files = Vec::new();
+        collect_files(package_dir, &mut files)?;
+        install_files(package_dir, files, &extension_dir, &library_dir)
+    }
+}
+
+///
+/// Move the extension `files` of the package `package_path` into the extension and library
+/// directories
+///
+/// Returns the names of the installed extensions.
+///
+fn install_files(
+    package_path: &Path,
+    files: Vec<PathBuf>,
+    extension_dir: &Path,
+    library_dir: &Path,
+) -> PgResult<Vec<String>> {
+    let mut extensions = Vec::new();
+    let mut targets = Vec::new();
+    for file in files {
+        let name = match file.file_name().and_then(|name| name.to_str()) {
+            Some(name) => name.to_string(),
+            None => continue,
+        };
+        if let Some(extension) = name.strip_suffix(".control") {
+            extensions.push(extension.to_string());
+        }
+        if name.ends_with(".control") || name.ends_with(".sql") {
+            targets.push((file, extension_dir.join(name)));
+        } else if is_shared_library(&name) {
+            targets.push((file, library_dir.join(name)));
+        }
+    }
+    if extensions.is_empty() {
+        return Err(PgEmbedError::InvalidExtensionArchive {
+            path: package_path.to_path_buf(),
+            reason: "the package contains no extension control file".to_string(),
+        });
+    }
+    for (file, target) in targets {
+        install_file(&file, &target)?;
+    }
+    extensions.sort();
+    extensions.dedup();
+    Ok(extensions)
+}
+
+///
+/// Check if the file `name` is a shared library, including versioned libraries like
+/// `libgeos_c.so.1`
+///
+fn is_shared_library(name: &str) -> bool {
+    name.ends_with(".so")
+        || name.contains(".so.")
+        || name.ends_with(".dylib")
+        || name.ends_with(".dll")
+}
+
+///
+/// Directory below `dir` containing a file matching `is_match`, searched depth first
+///
+pub(crate) fn find_parent_dir(
+    dir: &Path,
+    is_match: impl Fn(&str) -> bool + Copy,
+) -> PgResult<Option<PathBuf>> {
+    if !dir.is_dir() {
+        return Ok(None);
+    }
+    let read_error = |e| PgEmbedError::ReadFileError {
+        path: dir.to_path_buf(),
+        e,
+    };
+    let mut dirs = Vec::new();
+    for entry in std::fs::read_dir(dir).map_err(read_error)? {
+        let entry = entry.map_err(read_error)?;
+        let path = entry.path();
+        if entry.file_type().map_err(read_error)?.is_dir() {
+            dirs.push(path);
+        } else if entry.file_name().to_str().is_some_and(is_match) {
+            return Ok(Some(dir.to_path_buf()));
+        }
+    }
+    dirs.sort();
+    for dir in dirs {
+        if let Some(found) = find_parent_dir(&dir, is_match)? {
+            return Ok(Some(found));
+        }
+    }
+    Ok(None)
+}
+
+///
+/// Collect the files and symbolic links below `dir`
+///
+fn collect_files(dir: &Path, files: &mut Vec<PathBuf>) -> PgResult<()> {
+    let read_error = |e| PgEmbedError::ReadFileError {
+        path: dir.to_path_buf(),
+        e,
+    };
+    for entry in std::fs::read_dir(dir).map_err(read_error)? {
+        let entry = entry.map_err(read_error)?;
+        if entry.file_type().map_err(read_error)?.is_dir() {
+            collect_files(&entry.path(), files)?;
+        } else {
+            files.push(entry.path());
+        }
+    }
+    Ok(())
+}
+
+///
+/// Move the extracted `file` to `target`, replacing an installed file
+///
+fn install_file(file: &Path, target: &Path) -> PgResult<()> {
+    let write_error = |e| PgEmbedError::WriteFileError {
+        path: target.to_path_buf(),
+        e,
+    };
+    if std::fs::symlink_metadata(target).is_ok() {
+        std::fs::remove_file(target).map_err(write_error)?;
+    }
+    std::fs::rename(file, target).map_err(write_error)
+}
+
+///
+/// Remove the staging directory of an installation
+///
+pub(crate) fn remove_staging_dir(staging_dir: &Path) -> PgResult<()> {
+    if staging_dir.exists() {
+        std::fs::remove_dir_all(staging_dir).map_err(|e| PgEmbedError::PgCleanUpFailure {
+            path: staging_dir.to_path_buf(),
+            e,
+        })?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pg_archive::PgCompression;
+
+    #[test]
+    fn install_extension_archive() -> Result<(), PgEmbedError> {
+        let base = std::env::temp_dir().join(format!("pg_embed_extension_{}", std::process::id()));
+        let cache_dir = base.join("cache");
+        let extension_dir = cache_dir.join("share").join("postgresql").join("extension");
+        let library_dir = cache_dir.join("lib").join("postgresql");
+        std::fs::create_dir_all(&extension_dir).unwrap();
+        std::fs::create_dir_all(&library_dir).unwrap();
+        std::fs::write(extension_dir.join("plpgsql.control"), "").unwrap();
+        std::fs::write(library_dir.join("plpgsql.so"), "").unwrap();
+        let installer = PgExtensionInstaller::new(&cache_dir);
+        assert_eq!(extension_dir, installer.extension_dir()?);
+        assert_eq!(library_dir, installer.library_dir()?);
+
+        let package = base.join("pgvector");
+        std::fs::create_dir_all(package.join("sql")).unwrap();
+        std::fs::create_dir_all(package.join("include")).unwrap();
+        std::fs::write(
+            package.join("vector.control"),
+            "module_pathname = '$libdir/vector'",
+        )
+        .unwrap();
+        std::fs::write(package.join("sql").join("vector--0.7.4.sql"), "").unwrap();
+        std::fs::write(package.join("vector.so"), "").unwrap();
+        std::fs::write(package.join("include").join("vector.h"), "").unwrap();
+        let archive = base.join("pgvector.tar.gz");
+        pg_archive::archive_dir(&package, &archive, PgCompression::Gzip { level: 1 })?;
+        assert_eq!(vec!["vector".to_string()], installer.install(&archive)?);
+        assert!(extension_dir.join("vector.control").is_file());
+        assert!(extension_dir.join("vector--0.7.4.sql").is_file());
+        assert!(library_dir.join("vector.so").is_file());
+        assert!(!extension_dir.join("vector.h").exists());
+        // installing again replaces the files
+        installer.install(&archive)?;
+        assert!(!cache_dir.join(STAGING_DIR_NAME).exists());
+
+        std::fs::remove_file(package.join("vector.control")).unwrap();
+        pg_archive::archive_dir(&package, &archive, PgCompression::None)?;
+        assert!(matches!(
+            installer.install(&archive),
+            Err(PgEmbedError::InvalidExtensionArchive { .. })
+        ));
+        std::fs::remove_dir_all(&base).unwrap();
+        Ok(())
+    }
+}
@@ -4,64 +4,419 @@
 //! Download and unpack postgresql binaries
 //!
 
-use bytes::Bytes;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::Arc;
+
+use bytes::{Bytes, BytesMut};
 use futures::TryFutureExt;
-use reqwest::Response;
+use reqwest::{Client, Response, StatusCode, Url};
 
 use crate::pg_enums::{Architecture, OperationSystem};
 use crate::pg_errors::PgEmbedError;
-use crate::pg_types::PgResult;
+use crate::pg_types::{PgResult, Timeouts};
+
+///
+/// Postgresql version
+///
+/// Versions are ordered by major, minor and patch number and can be parsed from
+/// strings like `"16"`, `"16.2"` or `"16.2.0"`.
+///
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct PostgresVersion {
+    major: u32,
+    minor: u32,
+    patch: u32,
+}
+
+impl PostgresVersion {
+    ///
+    /// Create a new postgresql version
+    ///
+    pub const fn new(major: u32, minor: u32, patch: u32) -> Self {
+        PostgresVersion {
+            major,
+            minor,
+            patch,
+        }
+    }
+
+    /// The major version number
+    pub fn major(&self) -> u32 {
+        self.major
+    }
+
+    /// The minor version number
+    pub fn minor(&self) -> u32 {
+        self.minor
+    }
+
+    /// The patch version number
+    pub fn patch(&self) -> u32 {
+        self.patch
+    }
+
+    ///
+    /// The version string initdb writes into a data directory's `PG_VERSION` file
+    ///
+    /// Postgresql versions >= 10 only write the major number (*e.g. `16`*),
+    /// older versions write major and minor number (*e.g. `9.6`*).
+    ///
+    pub fn data_dir_version(&self) -> String {
+        if self.major >= 10 {
+            self.major.to_string()
+        } else {
+            format!("{}.{}", self.major, self.minor)
+        }
+    }
 
-/// Postgresql version struct (simple version wrapper)
-#[derive(Debug, Copy, Clone)]
-pub struct PostgresVersion(pub &'static str);
+    ///
+    /// Check if a data directory created by `pg_version` (*the content of the `PG_VERSION`
+    /// file*) can be used with this version
+    ///
+    pub fn is_data_dir_compatible(&self, pg_version: &str) -> bool {
+        pg_version.trim() == self.data_dir_version()
+    }
+}
 
 impl std::fmt::Display for PostgresVersion {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{}", self.0)
+        write!(f, "{}.{}.{}", self.major, self.minor, self.patch)
     }
 }
 
+impl FromStr for PostgresVersion {
+    type Err = PgEmbedError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || PgEmbedError::InvalidPgVersion(s.to_string());
+        let mut parts = s.trim().split('.');
+        let mut next_part = |required: bool| -> PgResult<u32> {
+            match parts.next() {
+                Some(part) => part.parse::<u32>().map_err(|_| invalid()),
+                None if required => Err(invalid()),
+                None => Ok(0),
+            }
+        };
+        let major = next_part(true)?;
+        let minor = next_part(false)?;
+        let patch = next_part(false)?;
+        if parts.next().is_some() {
+            return Err(invalid());
+        }
+        Ok(PostgresVersion::new(major, minor, patch))
+    }
+}
+
+#[cfg(feature = "serde")]
+crate::pg_types::serde_as_string!(PostgresVersion);
+
 /// Latest postgres version 16
-pub const PG_V16: PostgresVersion = PostgresVersion("16.2.0");
+pub const PG_V16: PostgresVersion = PostgresVersion::new(16, 2, 0);
 /// Latest postgres version 15
-pub const PG_V15: PostgresVersion = PostgresVersion("15.3.0");
+pub const PG_V15: PostgresVersion = PostgresVersion::new(15, 3, 0);
 /// Latest postgres version 14
-pub const PG_V14: PostgresVersion = PostgresVersion("14.8.0");
+pub const PG_V14: PostgresVersion = PostgresVersion::new(14, 8, 0);
 /// Latest postgres version 13
-pub const PG_V13: PostgresVersion = PostgresVersion("13.6.0");
+pub const PG_V13: PostgresVersion = PostgresVersion::new(13, 6, 0);
 /// Latest postgres version 12
-pub const PG_V12: PostgresVersion = PostgresVersion("12.10.0");
+pub const PG_V12: PostgresVersion = PostgresVersion::new(12, 10, 0);
 /// Latest pstgres version 11
-pub const PG_V11: PostgresVersion = PostgresVersion("11.15.0");
+pub const PG_V11: PostgresVersion = PostgresVersion::new(11, 15, 0);
 /// Latest postgres version 10
-pub const PG_V10: PostgresVersion = PostgresVersion("10.20.0");
+pub const PG_V10: PostgresVersion = PostgresVersion::new(10, 20, 0);
+
+///
+/// Error of a failed download, timeouts are reported as [PgEmbedError::DownloadTimeout]
+///
+pub(crate) fn download_failure(e: reqwest::Error) -> PgEmbedError {
+    if e.is_timeout() {
+        PgEmbedError::DownloadTimeout {
+            url: e.url().map(|url| url.to_string()).unwrap_or_default(),
+            source: e,
+        }
+    } else {
+        PgEmbedError::DownloadFailure(e)
+    }
+}
+
+///
+/// Download progress
+///
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct FetchProgress {
+    /// downloaded bytes
+    pub downloaded: u64,
+    /// total bytes, if the repository host reports the content length
+    pub total: Option<u64>,
+}
+
+///
+/// Download progress callback
+///
+/// Called every time a chunk of the postgresql binaries has been downloaded.
+///
+#[derive(Clone)]
+pub struct ProgressCallback(Arc<dyn Fn(FetchProgress) + Send + Sync>);
+
+impl ProgressCallback {
+    ///
+    /// Create a new progress callback
+    ///
+    pub fn new<F>(callback: F) -> Self
+    where
+        F: Fn(FetchProgress) + Send + Sync + 'static,
+    {
+        ProgressCallback(Arc::new(callback))
+    }
+
+    ///
+    /// Report download progress
+    ///
+    pub fn report(&self, progress: FetchProgress) {
+        (self.0)(progress)
+    }
+}
+
+impl std::fmt::Debug for ProgressCallback {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("ProgressCallback")
+    }
+}
+
+///
+/// Additional trusted root certificate
+///
+/// Needed when downloads go through a TLS intercepting proxy re-signing the traffic.
+///
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
+pub enum PgCertificate {
+    /// PEM file (*may contain multiple certificates*)
+    Path(PathBuf),
+    /// PEM encoded certificates
+    Pem(Vec<u8>),
+}
+
+impl PgCertificate {
+    ///
+    /// Read the PEM encoded certificates
+    ///
+    pub fn pem(&self) -> PgResult<Vec<u8>> {
+        match self {
+            PgCertificate::Path(path) => {
+                std::fs::read(path).map_err(|e| PgEmbedError::ReadFileError {
+                    path: path.clone(),
+                    e,
+                })
+            }
+            PgCertificate::Pem(pem) => Ok(pem.clone()),
+        }
+    }
+}
+
+/// Environment variable overriding [PgFetchSettings::host]
+pub const PG_EMBED_HOST_ENV: &str = "PG_EMBED_HOST";
+/// Environment variable overriding [PgFetchSettings::version]
+pub const PG_EMBED_VERSION_ENV: &str = "PG_EMBED_VERSION";
+
+///
+/// Placeholders supported by [PgFetchSettings::artifact_url_template]
+///
+pub const ARTIFACT_URL_PLACEHOLDERS: [&str; 5] =
+    ["{host}", "{platform}", "{os}", "{arch}", "{version}"];
 
 /// Settings that determine the postgres binary to be fetched
+///
+/// With the `serde` feature missing fields are taken from [PgFetchSettings::default],
+/// the progress callback is never (de)serialized.
 #[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(default))]
 pub struct PgFetchSettings {
     /// The repository host
+    ///
+    /// A missing scheme defaults to `https`, trailing slashes are ignored.
+    /// A path is kept, e.g. `https://nexus.local/repository/maven-central`.
     pub host: String,
+    /// Full download url of the binaries, overriding the maven layout of [PgFetchSettings::host]
+    ///
+    /// Supports the placeholders `{host}`, `{platform}`, `{os}`, `{arch}` and `{version}`, e.g.
+    /// `{host}/postgres/{version}/postgres-{platform}.jar`.
+    /// Versions can not be discovered with a template, so there is no version fallback.
+    /// Besides zonky jars, the url may point to a `.tar.xz`, `.tar.gz`, `.tar.zst` or `.tar`
+    /// archive of the installation.
+    pub artifact_url_template: Option<String>,
     /// The operation system
     pub operating_system: OperationSystem,
     /// The cpu architecture
     pub architecture: Architecture,
     /// The postgresql version
     pub version: PostgresVersion,
+    /// The download timeouts, only [Timeouts::download_connect] and [Timeouts::download] apply
+    pub timeouts: Timeouts,
+    /// Download progress callback
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub on_progress: Option<ProgressCallback>,
+    /// Additional trusted root certificates
+    pub root_certificates: Vec<PgCertificate>,
+    /// Fetch the newest available version of the same major version if the configured one
+    /// is not available for the platform
+    pub allow_version_fallback: bool,
+    /// Unpack only the files needed to run postgresql,
+    /// skipping headers, documentation and static libraries
+    pub minimal_install: bool,
+    /// Fetch the binaries bundling the PostGIS extension, see [PgFetchSettings::with_postgis]
+    pub postgis: bool,
+    /// Keyring used to verify the signatures of the downloaded binaries with `gpgv`
+    /// downloads fail with [PgEmbedError::SignatureVerificationFailed] if set to None
+    #[cfg(feature = "verify-signatures")]
+    pub signature_keyring: Option<PathBuf>,
 }
 
 impl Default for PgFetchSettings {
     fn default() -> Self {
         PgFetchSettings {
             host: "https://repo1.maven.org".to_string(),
+            artifact_url_template: None,
             operating_system: OperationSystem::default(),
             architecture: Architecture::default(),
             version: PG_V13,
+            timeouts: Timeouts::default(),
+            on_progress: None,
+            root_certificates: Vec::new(),
+            allow_version_fallback: false,
+            minimal_install: false,
+            postgis: false,
+            #[cfg(feature = "verify-signatures")]
+            signature_keyring: None,
         }
     }
 }
 
+///
+/// Builder of [PgFetchSettings]
+///
+/// Starts from [PgFetchSettings::default], see [PgFetchSettings::builder].
+///
+#[derive(Debug, Clone, Default)]
+pub struct PgFetchSettingsBuilder {
+    settings: PgFetchSettings,
+}
+
+impl PgFetchSettingsBuilder {
+    /// Set the repository host
+    pub fn host(mut self, host: impl Into<String>) -> Self {
+        self.settings.host = host.into();
+        self
+    }
+
+    /// Set the download url template
+    pub fn artifact_url_template(mut self, template: impl Into<String>) -> Self {
+        self.settings.artifact_url_template = Some(template.into());
+        self
+    }
+
+    /// Set the operation system
+    pub fn operating_system(mut self, operating_system: OperationSystem) -> Self {
+        self.settings.operating_system = operating_system;
+        self
+    }
+
+    /// Set the cpu architecture
+    pub fn architecture(mut self, architecture: Architecture) -> Self {
+        self.settings.architecture = architecture;
+        self
+    }
+
+    /// Set the postgresql version
+    pub fn version(mut self, version: PostgresVersion) -> Self {
+        self.settings.version = version;
+        self
+    }
+
+    /// Set the download timeouts
+    pub fn timeouts(mut self, timeouts: Timeouts) -> Self {
+        self.settings.timeouts = timeouts;
+        self
+    }
+
+    /// Set the download progress callback
+    pub fn on_progress<F>(mut self, callback: F) -> Self
+    where
+        F: Fn(FetchProgress) + Send + Sync + 'static,
+    {
+        self.settings.on_progress = Some(ProgressCallback::new(callback));
+        self
+    }
+
+    /// Add a trusted root certificate
+    pub fn root_certificate(mut self, certificate: PgCertificate) -> Self {
+        self.settings.root_certificates.push(certificate);
+        self
+    }
+
+    /// Fetch the newest available version of the same major version if the configured one is
+    /// not available
+    pub fn allow_version_fallback(mut self, allow_version_fallback: bool) -> Self {
+        self.settings.allow_version_fallback = allow_version_fallback;
+        self
+    }
+
+    /// Unpack only the files needed to run postgresql
+    pub fn minimal_install(mut self, minimal_install: bool) -> Self {
+        self.settings.minimal_install = minimal_install;
+        self
+    }
+
+    /// Fetch the binaries bundling the PostGIS extension
+    pub fn postgis(mut self, postgis: bool) -> Self {
+        self.settings.postgis = postgis;
+        self
+    }
+
+    /// Set the keyring used to verify the signatures of the downloaded binaries
+    #[cfg(feature = "verify-signatures")]
+    pub fn signature_keyring(mut self, signature_keyring: impl Into<PathBuf>) -> Self {
+        self.settings.signature_keyring = Some(signature_keyring.into());
+        self
+    }
+
+    ///
+    /// Build the settings
+    ///
+    /// Returns an error if the host or the url template are invalid.
+    ///
+    pub fn build(self) -> PgResult<PgFetchSettings> {
+        self.settings.validate()?;
+        Ok(self.settings)
+    }
+}
+
 impl PgFetchSettings {
+    ///
+    /// Create a settings builder starting from the defaults
+    ///
+    pub fn builder() -> PgFetchSettingsBuilder {
+        PgFetchSettingsBuilder::default()
+    }
+
+    ///
+    /// Fetch the binaries bundling the PostGIS extension
+    ///
+    /// Selects the `embedded-postgis-binaries-{platform}` artifacts of the repository, which
+    /// are cached apart from the plain binaries. After unpacking, the binaries are checked to
+    /// provide the `postgis` extension, otherwise [PgEmbedError::ExtensionUnavailable] is
+    /// returned. With [PgFetchSettings::artifact_url_template] the template has to point to
+    /// PostGIS binaries, e.g. of a mirror publishing them under another artifact id, see
+    /// [PgFetchSettings::artifact_id].
+    ///
+    pub fn with_postgis(mut self) -> Self {
+        self.postgis = true;
+        self
+    }
+
     /// The platform string (*needed to determine the download path*)
     pub fn platform(&self) -> String {
         let os = self.operating_system.to_string();
@@ -73,45 +428,703 @@ impl PgFetchSettings {
         format!("{}-{}", os, arch)
     }
 
+    ///
+    /// Create the http client used for downloads
+    ///
+    pub fn client(&self) -> PgResult<Client> {
+        let mut builder = Client::builder();
+        // rustls takes precedence if both tls features are enabled
+        #[cfg(all(feature = "fetch-native-tls", not(feature = "fetch-rustls")))]
+        {
+            builder = builder.use_native_tls();
+        }
+        #[cfg(feature = "fetch-rustls")]
+        {
+            builder = builder.use_rustls_tls();
+        }
+        #[cfg(any(feature = "fetch-native-tls", feature = "fetch-rustls"))]
+        for certificate in &self.root_certificates {
+            for root in reqwest::Certificate::from_pem_bundle(&certificate.pem()?)
+                .map_err(download_failure)?
+            {
+                builder = builder.add_root_certificate(root);
+            }
+        }
+        #[cfg(not(any(feature = "fetch-native-tls", feature = "fetch-rustls")))]
+        if !self.root_certificates.is_empty() {
+            return Err(PgEmbedError::UnsupportedPlatform(
+                "root certificates without a fetch tls feature".to_string(),
+            ));
+        }
+        if let Some(connect) = self.timeouts.download_connect {
+            builder = builder.connect_timeout(connect);
+        }
+        if let Some(download) = self.timeouts.download {
+            builder = builder.timeout(download);
+        }
+        builder.build().map_err(download_failure)
+    }
+
+    ///
+    /// Apply the [PG_EMBED_HOST_ENV] and [PG_EMBED_VERSION_ENV] environment overrides
+    ///
+    /// Returns [PgEmbedError::InvalidPgVersion] if the version override can not be parsed.
+    ///
+    pub fn with_env_overrides(self) -> PgResult<Self> {
+        self.with_overrides(|name| std::env::var(name).ok())
+    }
+
+    ///
+    /// Apply the overrides of the environment variables looked up with `lookup`
+    ///
+    fn with_overrides(mut self, lookup: impl Fn(&str) -> Option<String>) -> PgResult<Self> {
+        let env_override = |name| override_value(lookup(name));
+        if let Some(host) = env_override(PG_EMBED_HOST_ENV) {
+            log::info!(
+                "Using postgresql repository host {} from {}",
+                host,
+                PG_EMBED_HOST_ENV
+            );
+            self.host = host;
+        }
+        if let Some(version) = env_override(PG_EMBED_VERSION_ENV) {
+            log::info!(
+                "Using postgresql version {} from {}",
+                version,
+                PG_EMBED_VERSION_ENV
+            );
+            self.version = version.parse()?;
+        }
+        Ok(self)
+    }
+
+    ///
+    /// Validate the host and the artifact url template
+    ///
+    /// Returns a [PgEmbedError::InvalidFetchUrl] describing the problem if one is malformed.
+    ///
+    pub fn validate(&self) -> PgResult<()> {
+        self.normalized_host()?;
+        self.download_url(&self.version).map(|_| ())
+    }
+
+    ///
+    /// The repository host with scheme and without trailing slashes
+    ///
+    pub fn normalized_host(&self) -> PgResult<String> {
+        let host = self.host.trim();
+        let invalid = |reason: &str| PgEmbedError::InvalidFetchUrl {
+            url: self.host.clone(),
+            reason: reason.to_string(),
+        };
+        if host.is_empty() {
+            return Err(invalid("the host is empty"));
+        }
+        let host = if host.contains("://") {
+            host.to_string()
+        } else {
+            format!("https://{}", host)
+        };
+        let url = Url::parse(&host).map_err(|e| invalid(&e.to_string()))?;
+        if !matches!(url.scheme(), "http" | "https") {
+            return Err(invalid("the scheme must be http or https"));
+        }
+        if url.host_str().is_none() {
+            return Err(invalid("the host name is missing"));
+        }
+        if url.query().is_some() || url.fragment().is_some() {
+            return Err(invalid("a query or fragment is not allowed"));
+        }
+        Ok(url.as_str().trim_end_matches('/').to_string())
+    }
+
+    ///
+    /// The maven artifact id of the platform's postgresql binaries
+    ///
+    /// (*e.g. `embedded-postgres-binaries-linux-amd64` or, with
+    /// [PgFetchSettings::postgis], `embedded-postgis-binaries-linux-amd64`*)
+    ///
+    pub fn artifact_id(&self) -> String {
+        let artifact = if self.postgis {
+            "embedded-postgis-binaries"
+        } else {
+            "embedded-postgres-binaries"
+        };
+        format!("{}-{}", artifact, self.platform())
+    }
+
+    ///
+    /// The maven artifact directory of the platform's postgresql binaries
+    ///
+    pub fn artifact_base_url(&self) -> PgResult<String> {
+        Ok(format!(
+            "{}/maven2/io/zonky/test/postgres/{}",
+            self.normalized_host()?,
+            self.artifact_id()
+        ))
+    }
+
+    ///
+    /// The download url of the postgresql binaries of `version`
+    ///
+    pub fn download_url(&self, version: &PostgresVersion) -> PgResult<String> {
+        match &self.artifact_url_template {
+            None => Ok(format!(
+                "{}/{}/{}-{}.jar",
+                self.artifact_base_url()?,
+                version,
+                self.artifact_id(),
+                version
+            )),
+            Some(template) => self.render_template(template, version),
+        }
+    }
+
+    fn render_template(&self, template: &str, version: &PostgresVersion) -> PgResult<String> {
+        let invalid = |reason: String| PgEmbedError::InvalidFetchUrl {
+            url: template.to_string(),
+            reason,
+        };
+        let mut rest = template;
+        while let Some(start) = rest.find('{') {
+            let end = rest[start..]
+                .find('}')
+                .ok_or_else(|| invalid("unclosed placeholder".to_string()))?;
+            let placeholder = &rest[start..start + end + 1];
+            if !ARTIFACT_URL_PLACEHOLDERS.contains(&placeholder) {
+                return Err(invalid(format!("unknown placeholder {}", placeholder)));
+            }
+            rest = &rest[start + end + 1..];
+        }
+        let url = template
+            .replace("{host}", &self.normalized_host()?)
+            .replace("{platform}", &self.platform())
+            .replace("{os}", &self.operating_system.to_string())
+            .replace("{arch}", &self.architecture.to_string())
+            .replace("{version}", &version.to_string());
+        Url::parse(&url).map_err(|e| invalid(e.to_string()))?;
+        Ok(url)
+    }
+
+    ///
+    /// Query the postgresql versions available for the configured platform
+    ///
+    /// Reads the maven metadata of the platform's artifact, returns the versions sorted ascending.
+    ///
+    pub async fn available_versions(&self) -> PgResult<Vec<PostgresVersion>> {
+        self.available_versions_with(&self.client()?).await
+    }
+
+    async fn available_versions_with(&self, client: &Client) -> PgResult<Vec<PostgresVersion>> {
+        if self.artifact_url_template.is_some() {
+            return Ok(Vec::new());
+        }
+        let metadata_url = format!("{}/maven-metadata.xml", self.artifact_base_url()?);
+        let response = client
+            .get(metadata_url)
+            .send()
+            .map_err(download_failure)
+            .await?;
+        if response.status() == StatusCode::NOT_FOUND {
+            return Ok(Vec::new());
+        }
+        let metadata = response
+            .error_for_status()
+            .map_err(download_failure)?
+            .text()
+            .map_err(download_failure)
+            .await?;
+        Ok(parse_maven_versions(&metadata))
+    }
+
+    ///
+    /// Request the postgresql binaries of `version`
+    ///
+    /// Returns `Ok(None)` if the repository has no binaries of `version` for the configured platform.
+    ///
+    async fn request_version(
+        &self,
+        client: &Client,
+        version: &PostgresVersion,
+    ) -> PgResult<Option<Response>> {
+        let response: Response = client
+            .get(self.download_url(version)?)
+            .send()
+            .map_err(download_failure)
+            .await?;
+        if response.status() == StatusCode::NOT_FOUND {
+            return Ok(None);
+        }
+        response
+            .error_for_status()
+            .map(Some)
+            .map_err(download_failure)
+    }
+
     ///
     /// Fetch postgres binaries
     ///
+    /// If the configured version is not available for the platform, a
+    /// [PgEmbedError::VersionUnavailable] error listing the available versions is returned,
+    /// or, if [PgFetchSettings::allow_version_fallback] is set, the newest available version of
+    /// the same major version is fetched instead.
+    ///
     /// Returns the data of the downloaded binary in an `Ok([u8])` on success, otherwise returns an error.
     ///
     pub async fn fetch_postgres(&self) -> PgResult<Bytes> {
-        let platform = &self.platform();
-        let version = self.version.0;
-        let download_url = format!(
-            "{}/maven2/io/zonky/test/postgres/embedded-postgres-binaries-{}/{}/embedded-postgres-binaries-{}-{}.jar",
-            &self.host,
-            &platform,
-            version,
-            &platform,
-            version);
-
-        let response: Response = reqwest::get(download_url)
-            .map_err(PgEmbedError::DownloadFailure)
-            .await?;
+        self.fetch_postgres_version()
+            .await
+            .map(|(content, _)| content)
+    }
 
-        let content: Bytes = response
-            .bytes()
-            .map_err(PgEmbedError::DownloadFailure)
-            .await?;
+    ///
+    /// Fetch postgres binaries, returning them together with the version actually fetched
+    ///
+    pub(crate) async fn fetch_postgres_version(&self) -> PgResult<(Bytes, PostgresVersion)> {
+        self.validate()?;
+        let client = self.client()?;
+        let (response, version) = match self.request_version(&client, &self.version).await? {
+            Some(response) => (response, self.version),
+            None => {
+                let available = self.available_versions_with(&client).await?;
+                let unavailable = || PgEmbedError::VersionUnavailable {
+                    version: self.version,
+                    platform: self.platform(),
+                    available: available.clone(),
+                };
+                let fallback = select_fallback_version(&self.version, &available)
+                    .filter(|_| self.allow_version_fallback)
+                    .ok_or_else(unavailable)?;
+                log::warn!(
+                    "Postgresql {} is not available for {}, falling back to {}",
+                    self.version,
+                    self.platform(),
+                    fallback
+                );
+                let response = self
+                    .request_version(&client, &fallback)
+                    .await?
+                    .ok_or_else(unavailable)?;
+                (response, fallback)
+            }
+        };
+
+        let content: Bytes = match &self.on_progress {
+            None => response.bytes().map_err(download_failure).await?,
+            Some(on_progress) => Self::download_with_progress(response, on_progress).await?,
+        };
 
         log::debug!("Downloaded {} bytes", content.len());
+        #[cfg(feature = "verify-signatures")]
+        self.verify_signature(&client, &version, &content).await?;
         log::trace!(
             "First 1024 bytes: {:?}",
-            &String::from_utf8_lossy(&content[..1024])
+            &String::from_utf8_lossy(&content[..content.len().min(1024)])
         );
 
-        Ok(content)
+        Ok((content, version))
+    }
+
+    ///
+    /// Verify the signature of the downloaded binaries
+    ///
+    /// Downloads the `.asc` signature published next to the artifact and checks it with `gpgv`
+    /// against [PgFetchSettings::signature_keyring]. Fails if no keyring is configured, the
+    /// feature is opted into to never use unverified binaries.
+    ///
+    #[cfg(feature = "verify-signatures")]
+    async fn verify_signature(
+        &self,
+        client: &Client,
+        version: &PostgresVersion,
+        content: &[u8],
+    ) -> PgResult<()> {
+        let keyring = match &self.signature_keyring {
+            Some(keyring) => keyring,
+            None => {
+                return Err(PgEmbedError::SignatureVerificationFailed {
+                    url: self.download_url(version)?,
+                    message: "the verify-signatures feature is enabled, but no signature keyring \
+                              is configured"
+                        .to_string(),
+                });
+            }
+        };
+        let signature_url = format!("{}.asc", self.download_url(version)?);
+        let verification_failed = |message: String| PgEmbedError::SignatureVerificationFailed {
+            url: signature_url.clone(),
+            message,
+        };
+        let signature = client
+            .get(&signature_url)
+            .send()
+            .map_err(download_failure)
+            .await?
+            .error_for_status()
+            .map_err(download_failure)?
+            .bytes()
+            .map_err(download_failure)
+            .await?;
+
+        // a private directory of unpredictable name, removed when dropped
+        let temp_dir = tempfile::Builder::new()
+            .prefix("pg-embed-signature")
+            .tempdir()
+            .map_err(|e| PgEmbedError::DirCreationError {
+                dir: std::env::temp_dir(),
+                e,
+            })?;
+        let data_path = temp_dir.path().join("binaries.jar");
+        let signature_path = temp_dir.path().join("binaries.jar.asc");
+        for (path, bytes) in [(&data_path, content), (&signature_path, &signature[..])] {
+            std::fs::write(path, bytes).map_err(|e| PgEmbedError::WriteFileError {
+                path: path.clone(),
+                e,
+            })?;
+        }
+        let output = tokio::process::Command::new("gpgv")
+            .arg("--keyring")
+            .arg(keyring)
+            .arg(&signature_path)
+            .arg(&data_path)
+            .output()
+            .await;
+        drop(temp_dir);
+        let output =
+            output.map_err(|e| verification_failed(format!("failed to run gpgv: {}", e)))?;
+        if !output.status.success() {
+            return Err(verification_failed(
+                String::from_utf8_lossy(&output.stderr).trim().to_string(),
+            ));
+        }
+        log::debug!("Verified signature {}", signature_url);
+        Ok(())
+    }
+
+    ///
+    /// Download the response body chunk by chunk, reporting the progress
+    ///
+    async fn download_with_progress(
+        mut response: Response,
+        on_progress: &ProgressCallback,
+    ) -> PgResult<Bytes> {
+        let total = response.content_length();
+        let mut content = BytesMut::with_capacity(total.unwrap_or(0) as usize);
+        on_progress.report(FetchProgress {
+            downloaded: 0,
+            total,
+        });
+        while let Some(chunk) = response.chunk().map_err(download_failure).await? {
+            content.extend_from_slice(&chunk);
+            on_progress.report(FetchProgress {
+                downloaded: content.len() as u64,
+                total,
+            });
+        }
+        Ok(content.freeze())
     }
 }
 
+///
+/// Value of a set, non blank environment variable
+///
+pub(crate) fn env_override(name: &str) -> Option<String> {
+    override_value(std::env::var(name).ok())
+}
+
+///
+/// Trimmed `value` of an environment variable, unless blank
+///
+fn override_value(value: Option<String>) -> Option<String> {
+    value
+        .map(|value| value.trim().to_string())
+        .filter(|value| !value.is_empty())
+}
+
+///
+/// Parse the versions listed in a maven metadata document
+///
+/// Returns the versions sorted ascending
+///
+fn parse_maven_versions(metadata: &str) -> Vec<PostgresVersion> {
+    let mut versions: Vec<PostgresVersion> = metadata
+        .split("<version>")
+        .skip(1)
+        .filter_map(|part| part.split("</version>").next())
+        .filter_map(|version| version.parse().ok())
+        .collect();
+    versions.sort();
+    versions.dedup();
+    versions
+}
+
+///
+/// Select the available version closest to `requested`
+///
+/// Only versions of the same major version are considered, the binaries are cached and the
+/// data directory is checked against the configured major version.
+///
+fn select_fallback_version(
+    requested: &PostgresVersion,
+    available: &[PostgresVersion],
+) -> Option<PostgresVersion> {
+    available
+        .iter()
+        .filter(|v| v.major() == requested.major())
+        .max()
+        .copied()
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    #[test]
+    fn parse_postgres_version() -> Result<(), PgEmbedError> {
+        assert_eq!(PG_V16, "16.2.0".parse::<PostgresVersion>()?);
+        assert_eq!(
+            PostgresVersion::new(15, 0, 0),
+            "15".parse::<PostgresVersion>()?
+        );
+        assert_eq!(
+            PostgresVersion::new(9, 6, 0),
+            "9.6".parse::<PostgresVersion>()?
+        );
+        assert!("16.a".parse::<PostgresVersion>().is_err());
+        assert!("16.2.0.1".parse::<PostgresVersion>().is_err());
+        assert!(PG_V12 < PG_V13);
+        assert!(PostgresVersion::new(12, 9, 0) < PG_V12);
+        assert_eq!("16.2.0", PG_V16.to_string());
+        Ok(())
+    }
+
+    #[test]
+    fn version_fallback() {
+        let metadata = "<metadata><versioning><versions>\
+            <version>12.10.0</version><version>13.2.0</version>\
+            <version>13.6.0</version><version>14.8.0</version>\
+            </versions></versioning></metadata>";
+        let available = parse_maven_versions(metadata);
+        assert_eq!(4, available.len());
+        assert_eq!(
+            Some(PostgresVersion::new(13, 6, 0)),
+            select_fallback_version(&PostgresVersion::new(13, 9, 0), &available)
+        );
+        assert_eq!(None, select_fallback_version(&PG_V16, &available));
+        assert_eq!(None, select_fallback_version(&PG_V10, &available));
+    }
+
+    #[test]
+    fn normalize_host() -> Result<(), PgEmbedError> {
+        let settings = |host: &str| PgFetchSettings {
+            host: host.to_string(),
+            operating_system: OperationSystem::Linux,
+            architecture: Architecture::Amd64,
+            ..Default::default()
+        };
+        for host in [
+            "https://repo1.maven.org",
+            "https://repo1.maven.org//",
+            " repo1.maven.org/ ",
+        ] {
+            assert_eq!("https://repo1.maven.org", settings(host).normalized_host()?);
+        }
+        assert_eq!(
+            "http://nexus.local:8081/repository/maven",
+            settings("http://nexus.local:8081/repository/maven/").normalized_host()?
+        );
+        for host in ["", "ftp://repo1.maven.org", "https://", "https://repo?x=1"] {
+            assert!(settings(host).validate().is_err(), "{}", host);
+        }
+
+        let mut templated = settings("mirror.local");
+        templated.artifact_url_template = Some("{host}/pg/{version}/pg-{platform}.jar".to_string());
+        assert_eq!(
+            "https://mirror.local/pg/13.14.0/pg-linux-amd64.jar",
+            templated.download_url(&PostgresVersion::new(13, 14, 0))?
+        );
+        templated.artifact_url_template = Some("{host}/pg/{release}.jar".to_string());
+        assert!(templated.validate().is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn postgis_artifacts() -> Result<(), PgEmbedError> {
+        let settings = PgFetchSettings {
+            operating_system: OperationSystem::Linux,
+            architecture: Architecture::Amd64,
+            ..Default::default()
+        };
+        assert_eq!(
+            "embedded-postgres-binaries-linux-amd64",
+            settings.artifact_id()
+        );
+        let alpine = PgFetchSettings {
+            operating_system: OperationSystem::AlpineLinux,
+            architecture: Architecture::Arm64v8,
+            postgis: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            "embedded-postgis-binaries-linux-arm64v8-alpine",
+            alpine.artifact_id()
+        );
+        assert_eq!(
+            "https://repo1.maven.org/maven2/io/zonky/test/postgres/\
+             embedded-postgres-binaries-linux-amd64/16.2.0/\
+             embedded-postgres-binaries-linux-amd64-16.2.0.jar",
+            settings.download_url(&PG_V16)?
+        );
+        let settings = settings.with_postgis();
+        assert!(settings.postgis);
+        assert_eq!(
+            "https://repo1.maven.org/maven2/io/zonky/test/postgres/\
+             embedded-postgis-binaries-linux-amd64/16.2.0/\
+             embedded-postgis-binaries-linux-amd64-16.2.0.jar",
+            settings.download_url(&PG_V16)?
+        );
+        assert!(PgFetchSettings::builder().postgis(true).build()?.postgis);
+        Ok(())
+    }
+
+    #[test]
+    fn env_overrides() -> Result<(), PgEmbedError> {
+        // the process environment is shared by all tests, look up the variables from a map
+        let lookup = |vars: &[(&str, &str)]| {
+            let vars: std::collections::HashMap<String, String> = vars
+                .iter()
+                .map(|(name, value)| (name.to_string(), value.to_string()))
+                .collect();
+            move |name: &str| vars.get(name).cloned()
+        };
+        let settings = PgFetchSettings::default().with_overrides(lookup(&[
+            (PG_EMBED_HOST_ENV, "mirror.local"),
+            (PG_EMBED_VERSION_ENV, " 15.6.0 "),
+        ]))?;
+        assert_eq!("mirror.local", settings.host);
+        assert_eq!(PostgresVersion::new(15, 6, 0), settings.version);
+
+        let settings =
+            PgFetchSettings::default().with_overrides(lookup(&[(PG_EMBED_HOST_ENV, "  ")]))?;
+        assert_eq!(PgFetchSettings::default().host, settings.host);
+        assert!(PgFetchSettings::default()
+            .with_overrides(lookup(&[(PG_EMBED_VERSION_ENV, "fifteen")]))
+            .is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn settings_builder() -> Result<(), PgEmbedError> {
+        let settings = PgFetchSettings::builder()
+            .host("nexus.local/maven")
+            .version(PG_V16)
+            .minimal_install(true)
+            .build()?;
+        assert_eq!("nexus.local/maven", settings.host);
+        assert_eq!(PG_V16, settings.version);
+        assert!(settings.minimal_install);
+        assert!(!settings.allow_version_fallback);
+        assert!(PgFetchSettings::builder()
+            .host("ftp://nexus.local")
+            .build()
+            .is_err());
+        Ok(())
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn deserialize_settings() {
+        let settings: PgFetchSettings = serde_json::from_str(
+            r#"{"version": "15.3", "architecture": "arm64v8", "operating_system": "alpine"}"#,
+        )
+        .unwrap();
+        assert_eq!(PG_V15, settings.version);
+        assert_eq!(Architecture::Arm64v8, settings.architecture);
+        assert_eq!(OperationSystem::AlpineLinux, settings.operating_system);
+        assert_eq!(PgFetchSettings::default().host, settings.host);
+
+        let json = serde_json::to_string(&settings).unwrap();
+        assert!(json.contains(r#""operating_system":"alpine""#));
+        assert!(serde_json::from_str::<PgFetchSettings>(r#"{"version": "x"}"#).is_err());
+    }
+
+    #[test]
+    fn data_dir_compatibility() {
+        assert!(PG_V16.is_data_dir_compatible("16\n"));
+        assert!(!PG_V16.is_data_dir_compatible("15"));
+        assert!(PostgresVersion::new(9, 6, 24).is_data_dir_compatible("9.6"));
+    }
+
+    const TEST_ROOT_CERTIFICATE: &str = "-----BEGIN CERTIFICATE-----\n\
+MIIBkDCCATegAwIBAgIUWw0di0q7ZydqG+gC1CsKcXdG534wCgYIKoZIzj0EAwIw\n\
+HTEbMBkGA1UEAwwScGctZW1iZWQgdGVzdCByb290MCAXDTI2MTAxNzAxMzkxMFoY\n\
+DzIxMjYwOTIzMDEzOTEwWjAdMRswGQYDVQQDDBJwZy1lbWJlZCB0ZXN0IHJvb3Qw\n\
+WTATBgcqhkjOPQIBBggqhkjOPQMBBwNCAARfaek68cUcsEQSHmXPE0QnazuhK5d9\n\
+i57m2guBX6XGkShY+btmqPbRQ2GKpoyoPVRxV9cOmNOJu0icJDMlKSazo1MwUTAd\n\
+BgNVHQ4EFgQUfxdgpJ5x9iPpco6w0HzyVsFtzkQwHwYDVR0jBBgwFoAUfxdgpJ5x\n\
+9iPpco6w0HzyVsFtzkQwDwYDVR0TAQH/BAUwAwEB/zAKBggqhkjOPQQDAgNHADBE\n\
+AiAbVXt+luyMcEprIvU5eNqTA9IBuzHD7Ho1D0eXqM1yeAIgTdIC/am2YOOJhbtZ\n\
+W0iG8bIEcq/SVKbNu+yJLNvxDDc=\n\
+-----END CERTIFICATE-----\n\
+";
+
+    #[test]
+    fn root_certificates() -> Result<(), PgEmbedError> {
+        let pem = TEST_ROOT_CERTIFICATE.as_bytes().to_vec();
+        assert_eq!(pem, PgCertificate::Pem(pem.clone()).pem()?);
+        let path = std::env::temp_dir().join(format!("pg_embed_root_{}.pem", std::process::id()));
+        std::fs::write(&path, &pem).unwrap();
+        let from_path = PgCertificate::Path(path.clone()).pem();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(pem, from_path?);
+        assert!(matches!(
+            PgCertificate::Path(path).pem(),
+            Err(PgEmbedError::ReadFileError { .. })
+        ));
+
+        let settings = PgFetchSettings::builder()
+            .root_certificate(PgCertificate::Pem(pem.clone()))
+            .root_certificate(PgCertificate::Pem([pem.clone(), pem].concat()))
+            .build()?;
+        assert_eq!(2, settings.root_certificates.len());
+        #[cfg(any(feature = "fetch-native-tls", feature = "fetch-rustls"))]
+        settings.client()?;
+        Ok(())
+    }
+
+    #[cfg(feature = "verify-signatures")]
+    #[tokio::test]
+    async fn signature_keyring_required() -> Result<(), PgEmbedError> {
+        let settings = PgFetchSettings::default();
+        let client = settings.client()?;
+        let result = settings
+            .verify_signature(&client, &settings.version, b"")
+            .await;
+        assert!(matches!(
+            result,
+            Err(PgEmbedError::SignatureVerificationFailed { .. })
+        ));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn download_timeout() {
+        // accepts the connection, but never responds
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let settings = PgFetchSettings {
+            host: format!("http://{}", listener.local_addr().unwrap()),
+            timeouts: Timeouts {
+                download: Some(std::time::Duration::from_millis(200)),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        assert!(matches!(
+            settings.fetch_postgres().await,
+            Err(PgEmbedError::DownloadTimeout { .. })
+        ));
+    }
+
     #[tokio::test]
     async fn fetch_postgres() -> Result<(), PgEmbedError> {
         let pg_settings = PgFetchSettings::default();
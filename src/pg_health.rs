@@ -0,0 +1,225 @@
+//!
+//! Server health
+//!
+//! Check if the server accepts connections with `pg_isready` or by sending a startup packet
+//!
+use std::io;
+use std::path::Path;
+use std::process::Stdio;
+use std::time::Duration;
+
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio::process::Command;
+
+use crate::pg_errors::PgEmbedError;
+use crate::pg_types::PgResult;
+
+/// Protocol version 3.0 of the startup packet
+const PROTOCOL_VERSION: i32 = 196_608;
+/// Error code sent while the server is starting up, shutting down or in recovery
+const CANNOT_CONNECT_NOW: &str = "57P03";
+/// Interval between the connection attempts while waiting for the server
+pub(crate) const READY_POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+///
+/// Connection state of the server
+///
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum PgHealth {
+    /// the server accepts connections
+    Accepting,
+    /// the server is running but rejects connections, e.g. while starting up
+    Rejecting,
+    /// the server could not be reached
+    NoResponse,
+}
+
+impl PgHealth {
+    ///
+    /// Map a `pg_isready` exit code, `None` if no connection was attempted (*exit code 3*)
+    ///
+    fn from_pg_isready_exit_code(code: i32) -> Option<PgHealth> {
+        match code {
+            0 => Some(PgHealth::Accepting),
+            1 => Some(PgHealth::Rejecting),
+            2 => Some(PgHealth::NoResponse),
+            _ => None,
+        }
+    }
+}
+
+///
+/// Check the server with the `pg_isready` executable
+///
+/// Returns `None` if `pg_isready` is not available or did not attempt to connect.
+///
+pub(crate) async fn pg_isready(
+    pg_isready_exe: &Path,
+    host: &str,
+    port: u16,
+    user: &str,
+) -> Option<PgHealth> {
+    if !pg_isready_exe.exists() {
+        return None;
+    }
+    let status = Command::new(pg_isready_exe)
+        .args(
+            [
+                "-h",
+                host,
+                "-p",
+                &port.to_string(),
+                "-U",
+                user,
+                "-d",
+                "postgres",
+            ]
+            .iter(),
+        )
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .kill_on_drop(true)
+        .status()
+        .await
+        .ok()?;
+    status.code().and_then(PgHealth::from_pg_isready_exit_code)
+}
+
+///
+/// Ping the server by sending a startup packet
+///
+/// Any response besides a *cannot connect now* error means the server accepts connections,
+/// including authentication requests and errors about unknown users or databases.
+///
+pub(crate) async fn ping(host: &str, port: u16, user: &str) -> PgHealth {
+    match startup_response(host, port, user).await {
+        Ok(Some(code)) if code == CANNOT_CONNECT_NOW => PgHealth::Rejecting,
+        Ok(_) => PgHealth::Accepting,
+        Err(_) => PgHealth::NoResponse,
+    }
+}
+
+///
+/// Ping the server until it accepts connections
+///
+/// If timeout is set to None the server is awaited without a time limit.
+///
+pub(crate) async fn wait_until_accepting(
+    host: &str,
+    port: u16,
+    user: &str,
+    timeout: Option<Duration>,
+) -> PgResult<()> {
+    let poll = async {
+        while ping(host, port, user).await != PgHealth::Accepting {
+            tokio::time::sleep(READY_POLL_INTERVAL).await;
+        }
+    };
+    match timeout {
+        Some(timeout) => {
+            tokio::time::timeout(timeout, poll)
+                .await
+                .map_err(|e| PgEmbedError::PgError {
+                    message: "postgresql is not accepting connections".to_string(),
+                    source: Box::new(e),
+                })
+        }
+        None => {
+            poll.await;
+            Ok(())
+        }
+    }
+}
+
+///
+/// Send a startup packet and read the response
+///
+/// Returns the error code if the server responded with an error.
+///
+async fn startup_response(host: &str, port: u16, user: &str) -> io::Result<Option<String>> {
+    let mut stream = TcpStream::connect((host, port)).await?;
+    stream.write_all(&startup_packet(user)).await?;
+    let tag = stream.read_u8().await?;
+    if tag != b'E' {
+        return Ok(None);
+    }
+    let length = stream.read_i32().await?;
+    let mut body = vec![0u8; (length.max(4) - 4) as usize];
+    stream.read_exact(&mut body).await?;
+    Ok(Some(error_code(&body).unwrap_or_default()))
+}
+
+///
+/// Startup packet of protocol version 3.0 for `user`
+///
+fn startup_packet(user: &str) -> Vec<u8> {
+    let mut parameters = Vec::new();
+    for value in ["user", user, "database", "postgres"].iter() {
+        parameters.extend_from_slice(value.as_bytes());
+        parameters.push(0);
+    }
+    parameters.push(0);
+    let length = (8 + parameters.len()) as i32;
+    let mut packet = Vec::with_capacity(length as usize);
+    packet.extend_from_slice(&length.to_be_bytes());
+    packet.extend_from_slice(&PROTOCOL_VERSION.to_be_bytes());
+    packet.extend_from_slice(&parameters);
+    packet
+}
+
+///
+/// Error code (*field `C`*) of an error response body
+///
+fn error_code(body: &[u8]) -> Option<String> {
+    body.split(|b| *b == 0)
+        .find(|field| field.first() == Some(&b'C'))
+        .map(|field| String::from_utf8_lossy(&field[1..]).into_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn startup_packet_layout() {
+        let packet = startup_packet("postgres");
+        let length = i32::from_be_bytes([packet[0], packet[1], packet[2], packet[3]]);
+        assert_eq!(packet.len() as i32, length);
+        assert!(packet.ends_with(b"database\0postgres\0\0"));
+        assert_eq!(
+            Some("57P03".to_string()),
+            error_code(b"SFATAL\0C57P03\0Mthe database system is starting up\0\0")
+        );
+    }
+
+    #[test]
+    fn pg_isready_exit_codes() {
+        assert_eq!(
+            Some(PgHealth::Accepting),
+            PgHealth::from_pg_isready_exit_code(0)
+        );
+        assert_eq!(
+            Some(PgHealth::Rejecting),
+            PgHealth::from_pg_isready_exit_code(1)
+        );
+        assert_eq!(
+            Some(PgHealth::NoResponse),
+            PgHealth::from_pg_isready_exit_code(2)
+        );
+        assert_eq!(None, PgHealth::from_pg_isready_exit_code(3));
+    }
+
+    #[tokio::test]
+    async fn ping_closed_port() {
+        let port = std::net::TcpListener::bind(("127.0.0.1", 0))
+            .and_then(|listener| listener.local_addr())
+            .unwrap()
+            .port();
+        assert_eq!(
+            PgHealth::NoResponse,
+            ping("127.0.0.1", port, "postgres").await
+        );
+    }
+}
@@ -0,0 +1,114 @@
+//!
+//! Database maintenance
+//!
+//! Long-lived databases of a persistent data directory need the same periodic maintenance as
+//! any other postgresql server. [crate::postgres::PgEmbed::vacuum],
+//! [crate::postgres::PgEmbed::analyze] and [crate::postgres::PgEmbed::reindex] run the bundled
+//! `vacuumdb` and `reindexdb`:
+//!
+//! ```rust, ignore
+//! let options = PgVacuumOptions {
+//!     analyze: true,
+//!     tables: vec!["orders".to_string()],
+//!     ..Default::default()
+//! };
+//! pg.vacuum("app", &options).await?;
+//! pg.reindex("app").await?;
+//! ```
+//!
+use std::ffi::OsString;
+
+///
+/// vacuumdb options
+///
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PgVacuumOptions {
+    /// rewrite the tables to return the free space to the operating system, `VACUUM FULL`
+    /// locks each table exclusively while it is processed
+    pub full: bool,
+    /// freeze the row transaction ids, `VACUUM FREEZE`
+    pub freeze: bool,
+    /// update the planner statistics afterwards, `VACUUM ANALYZE`
+    pub analyze: bool,
+    /// tables to vacuum, optionally schema qualified like `sales.orders`
+    /// if empty all tables of the database are vacuumed
+    pub tables: Vec<String>,
+    /// number of concurrent connections vacuuming different tables
+    /// if set to None the tables are vacuumed one after another
+    pub jobs: Option<u32>,
+}
+
+impl PgVacuumOptions {
+    ///
+    /// vacuumdb arguments of the vacuum of `database`
+    ///
+    pub(crate) fn args(&self, database: &str) -> Vec<OsString> {
+        let mut args: Vec<OsString> = vec![format!("--dbname={}", database).into()];
+        if self.full {
+            args.push("--full".into());
+        }
+        if self.freeze {
+            args.push("--freeze".into());
+        }
+        if self.analyze {
+            args.push("--analyze".into());
+        }
+        if let Some(jobs) = self.jobs {
+            args.push(format!("--jobs={}", jobs).into());
+        }
+        args.extend(
+            self.tables
+                .iter()
+                .map(|table| format!("--table={}", table).into()),
+        );
+        args
+    }
+}
+
+///
+/// vacuumdb arguments updating the planner statistics of `database`
+///
+pub(crate) fn analyze_args(database: &str) -> Vec<OsString> {
+    vec![
+        format!("--dbname={}", database).into(),
+        "--analyze-only".into(),
+    ]
+}
+
+///
+/// reindexdb arguments rebuilding the indexes of `database`
+///
+pub(crate) fn reindex_args(database: &str) -> Vec<OsString> {
+    vec![format!("--dbname={}", database).into()]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn maintenance_arguments() {
+        assert_eq!(vec!["--dbname=app"], PgVacuumOptions::default().args("app"));
+        let options = PgVacuumOptions {
+            full: true,
+            freeze: true,
+            analyze: true,
+            tables: vec!["orders".to_string(), "sales.items".to_string()],
+            jobs: Some(2),
+        };
+        assert_eq!(
+            vec![
+                "--dbname=app",
+                "--full",
+                "--freeze",
+                "--analyze",
+                "--jobs=2",
+                "--table=orders",
+                "--table=sales.items"
+            ],
+            options.args("app")
+        );
+        assert_eq!(vec!["--dbname=app", "--analyze-only"], analyze_args("app"));
+        assert_eq!(vec!["--dbname=app"], reindex_args("app"));
+    }
+}
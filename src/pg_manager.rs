@@ -0,0 +1,251 @@
+//!
+//! Multiple instances
+//!
+//! Run several servers side by side, e.g. to test sharding or replication between databases
+//!
+use std::path::{Component, Path, PathBuf};
+
+use futures::future::try_join_all;
+
+use crate::pg_errors::PgEmbedError;
+use crate::pg_fetch::PgFetchSettings;
+use crate::pg_types::PgResult;
+use crate::postgres::{self, PgEmbed, PgSettings, PgSettingsBuilder};
+
+///
+/// Owner of named PgEmbed instances
+///
+/// Each instance gets a database directory below the base directory named after it and a port
+/// distinct from the other instances. The instances are started and stopped together, the
+/// individual instances are handed out by name.
+///
+pub struct PgEmbedManager {
+    base_dir: PathBuf,
+    fetch_settings: PgFetchSettings,
+    /// instances in the order they were added
+    instances: Vec<(String, PgEmbed)>,
+}
+
+impl PgEmbedManager {
+    ///
+    /// Create a manager of instances below `base_dir` using the binaries of `fetch_settings`
+    ///
+    pub fn new(base_dir: impl Into<PathBuf>, fetch_settings: PgFetchSettings) -> Self {
+        PgEmbedManager {
+            base_dir: base_dir.into(),
+            fetch_settings,
+            instances: Vec::new(),
+        }
+    }
+
+    ///
+    /// Add an instance named `name` with the default settings, see [PgEmbedManager::add_with]
+    ///
+    pub async fn add(&mut self, name: &str) -> PgResult<&mut PgEmbed> {
+        self.add_with(name, |settings| settings).await
+    }
+
+    ///
+    /// Add an instance named `name`
+    ///
+    /// `configure` receives a settings builder with the database directory `<base_dir>/<name>`
+    /// and a free port not used by another instance, both may be overridden.
+    ///
+    /// Returns the instance, or an error if the name is not a plain directory name or is
+    /// already taken.
+    ///
+    pub async fn add_with<F>(&mut self, name: &str, configure: F) -> PgResult<&mut PgEmbed>
+    where
+        F: FnOnce(PgSettingsBuilder) -> PgSettingsBuilder,
+    {
+        validate_name(name)?;
+        if self.get(name).is_some() {
+            return Err(PgEmbedError::InvalidInstanceName {
+                name: name.to_string(),
+                reason: "an instance of this name exists".to_string(),
+            });
+        }
+        let settings = PgSettings::builder()
+            .database_dir(self.base_dir.join(name))
+            .port(self.free_port()?);
+        let pg = PgEmbed::new(configure(settings).build()?, self.fetch_settings.clone()).await?;
+        self.instances.push((name.to_string(), pg));
+        Ok(&mut self.instances.last_mut().expect("instance was added").1)
+    }
+
+    ///
+    /// Instance named `name`
+    ///
+    pub fn get(&self, name: &str) -> Option<&PgEmbed> {
+        self.instances
+            .iter()
+            .find(|(instance_name, _)| instance_name == name)
+            .map(|(_, pg)| pg)
+    }
+
+    ///
+    /// Mutable instance named `name`
+    ///
+    pub fn get_mut(&mut self, name: &str) -> Option<&mut PgEmbed> {
+        self.instances
+            .iter_mut()
+            .find(|(instance_name, _)| instance_name == name)
+            .map(|(_, pg)| pg)
+    }
+
+    ///
+    /// Remove the instance named `name`, handing over its ownership
+    ///
+    pub fn remove(&mut self, name: &str) -> Option<PgEmbed> {
+        let index = self
+            .instances
+            .iter()
+            .position(|(instance_name, _)| instance_name == name)?;
+        Some(self.instances.remove(index).1)
+    }
+
+    ///
+    /// Names of the instances in the order they were added
+    ///
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.instances.iter().map(|(name, _)| name.as_str())
+    }
+
+    ///
+    /// Setup all instances concurrently, see [PgEmbed::setup]
+    ///
+    /// The binaries are acquired only once.
+    ///
+    pub async fn setup_all(&mut self) -> PgResult<()> {
+        try_join_all(self.instances.iter_mut().map(|(_, pg)| pg.setup())).await?;
+        Ok(())
+    }
+
+    ///
+    /// Start all instances concurrently, see [PgEmbed::start_db]
+    ///
+    pub async fn start_all(&mut self) -> PgResult<()> {
+        try_join_all(self.instances.iter_mut().map(|(_, pg)| pg.start_db())).await?;
+        Ok(())
+    }
+
+    ///
+    /// Stop all running instances concurrently, see [PgEmbed::stop_db]
+    ///
+    /// Every instance is stopped even if stopping another one failed.
+    ///
+    /// Returns the first error.
+    ///
+    pub async fn stop_all(&mut self) -> PgResult<()> {
+        let stops = self.instances.iter_mut().map(|(_, pg)| async move {
+            if pg
+                .server_process()?
+                .is_some_and(|process| process.is_running())
+            {
+                pg.stop_db().await?;
+            }
+            Ok::<_, PgEmbedError>(())
+        });
+        let results = futures::future::join_all(stops).await;
+        results.into_iter().collect()
+    }
+
+    ///
+    /// Stop and clean up all instances, see [PgEmbed::shutdown]
+    ///
+    /// The base directory is removed if it is empty afterwards.
+    ///
+    /// Returns the first error.
+    ///
+    pub async fn shutdown(self) -> PgResult<()> {
+        let instances = self.instances.into_iter().map(|(_, pg)| pg.shutdown());
+        let results = futures::future::join_all(instances).await;
+        // fails if other files are left in it
+        let _ = std::fs::remove_dir(&self.base_dir);
+        results.into_iter().collect()
+    }
+
+    ///
+    /// Free port not used by an instance
+    ///
+    fn free_port(&self) -> PgResult<u16> {
+        loop {
+            // the port is released before returning, it may be handed out again
+            let port = postgres::free_port()?;
+            if self
+                .instances
+                .iter()
+                .all(|(_, pg)| pg.pg_settings.port != port)
+            {
+                return Ok(port);
+            }
+        }
+    }
+}
+
+///
+/// Check that `name` can be used as the database directory name
+///
+fn validate_name(name: &str) -> PgResult<()> {
+    let mut components = Path::new(name).components();
+    match (components.next(), components.next()) {
+        (Some(Component::Normal(component)), None) if component == name => Ok(()),
+        _ => Err(PgEmbedError::InvalidInstanceName {
+            name: name.to_string(),
+            reason: "must be a plain directory name".to_string(),
+        }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn manage_instances() -> Result<(), PgEmbedError> {
+        let base = std::env::temp_dir().join(format!("pg_embed_manager_{}", std::process::id()));
+        let cache_dir = base.join("cache");
+        let mut manager = PgEmbedManager::new(&base, PgFetchSettings::default());
+        for name in ["", "..", "a/b"].iter() {
+            let result = manager.add(name).await;
+            assert!(matches!(
+                result,
+                Err(PgEmbedError::InvalidInstanceName { .. })
+            ));
+        }
+        manager
+            .add_with("shard_1", |settings| settings.cache_dir(&cache_dir))
+            .await?;
+        manager
+            .add_with("shard_2", |settings| {
+                settings.cache_dir(&cache_dir).user("app")
+            })
+            .await?;
+        let result = manager.add("shard_1").await;
+        assert!(matches!(
+            result,
+            Err(PgEmbedError::InvalidInstanceName { .. })
+        ));
+
+        assert_eq!(
+            vec!["shard_1", "shard_2"],
+            manager.names().collect::<Vec<_>>()
+        );
+        let shard_1 = manager.get("shard_1").unwrap();
+        let shard_2 = manager.get("shard_2").unwrap();
+        assert_eq!(base.join("shard_1"), shard_1.pg_settings.database_dir);
+        assert_eq!("app", shard_2.pg_settings.user);
+        assert_ne!(shard_1.pg_settings.port, shard_2.pg_settings.port);
+        assert!(manager.get("shard_3").is_none());
+
+        let removed = manager.remove("shard_1").unwrap();
+        assert_eq!(vec!["shard_2"], manager.names().collect::<Vec<_>>());
+        removed.shutdown().await?;
+        manager.stop_all().await?;
+        manager.shutdown().await?;
+        assert!(!base.join("shard_1").exists());
+        assert!(!base.join("shard_2").exists());
+        std::fs::remove_dir_all(&base).unwrap();
+        Ok(())
+    }
+}
@@ -0,0 +1,247 @@
+//!
+//! Cache manifest
+//!
+//! File list with sizes and sha256 hashes of the cached postgresql binaries
+//!
+use std::fs::File;
+use std::io::{BufReader, Read};
+use std::path::{Path, PathBuf};
+
+use sha2::{Digest, Sha256};
+
+use crate::pg_errors::PgEmbedError;
+use crate::pg_types::PgResult;
+
+///
+/// Manifest entry of a single file
+///
+#[derive(Debug, Clone, PartialEq)]
+pub struct ManifestEntry {
+    /// path relative to the cache directory
+    pub path: PathBuf,
+    /// file size in bytes
+    pub size: u64,
+    /// hex encoded sha256 hash of the file content
+    pub sha256: String,
+}
+
+///
+/// Manifest of the files of a cache directory
+///
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct CacheManifest {
+    /// manifest entries sorted by path
+    pub entries: Vec<ManifestEntry>,
+}
+
+impl CacheManifest {
+    ///
+    /// Create the manifest of the files in the `dirs` of `cache_dir`
+    ///
+    /// Symbolic links are not followed and not recorded.
+    ///
+    pub fn create(cache_dir: &Path, dirs: &[&str]) -> PgResult<Self> {
+        let mut files = Vec::new();
+        for dir in dirs {
+            let dir = cache_dir.join(dir);
+            if dir.is_dir() {
+                collect_files(&dir, &mut files)?;
+            }
+        }
+        let mut entries = files
+            .into_iter()
+            .map(|file| {
+                let (size, sha256) = hash_file(&file)?;
+                Ok(ManifestEntry {
+                    path: file.strip_prefix(cache_dir).unwrap_or(&file).to_path_buf(),
+                    size,
+                    sha256,
+                })
+            })
+            .collect::<PgResult<Vec<_>>>()?;
+        entries.sort_by(|a, b| a.path.cmp(&b.path));
+        Ok(CacheManifest { entries })
+    }
+
+    ///
+    /// Read a manifest written by [CacheManifest::write]
+    ///
+    /// Returns `Ok(None)` if the manifest file does not exist.
+    ///
+    pub fn read(manifest_path: &Path) -> PgResult<Option<Self>> {
+        if !manifest_path.exists() {
+            return Ok(None);
+        }
+        let content =
+            std::fs::read_to_string(manifest_path).map_err(|e| PgEmbedError::ReadFileError {
+                path: manifest_path.to_path_buf(),
+                e,
+            })?;
+        let entries = content
+            .lines()
+            .filter(|line| !line.trim().is_empty())
+            .map(|line| {
+                let mut parts = line.splitn(3, ' ');
+                let sha256 = parts.next().unwrap_or_default();
+                let size = parts.next().and_then(|size| size.parse().ok());
+                let path = parts.next();
+                match (size, path) {
+                    (Some(size), Some(path)) if !sha256.is_empty() => Ok(ManifestEntry {
+                        path: PathBuf::from(path),
+                        size,
+                        sha256: sha256.to_string(),
+                    }),
+                    _ => Err(PgEmbedError::ReadFileError {
+                        path: manifest_path.to_path_buf(),
+                        e: std::io::Error::new(
+                            std::io::ErrorKind::InvalidData,
+                            format!("malformed manifest line: {}", line),
+                        ),
+                    }),
+                }
+            })
+            .collect::<PgResult<Vec<_>>>()?;
+        Ok(Some(CacheManifest { entries }))
+    }
+
+    ///
+    /// Write the manifest, one `<sha256> <size> <path>` line per file
+    ///
+    pub fn write(&self, manifest_path: &Path) -> PgResult<()> {
+        let content: String = self
+            .entries
+            .iter()
+            .map(|entry| {
+                format!(
+                    "{} {} {}\n",
+                    entry.sha256,
+                    entry.size,
+                    entry.path.to_string_lossy().replace('\\', "/")
+                )
+            })
+            .collect();
+        std::fs::write(manifest_path, content).map_err(|e| PgEmbedError::WriteFileError {
+            path: manifest_path.to_path_buf(),
+            e,
+        })
+    }
+
+    ///
+    /// Files of `cache_dir` that are missing or have a different size than recorded
+    ///
+    pub fn missing_files(&self, cache_dir: &Path) -> Vec<PathBuf> {
+        self.entries
+            .iter()
+            .map(|entry| (entry, cache_dir.join(&entry.path)))
+            .filter(|(entry, path)| {
+                std::fs::metadata(path).map_or(true, |metadata| metadata.len() != entry.size)
+            })
+            .map(|(_, path)| path)
+            .collect()
+    }
+
+    ///
+    /// Files of `cache_dir` that are missing or whose content differs from the recorded hash
+    ///
+    pub fn corrupted_files(&self, cache_dir: &Path) -> Vec<PathBuf> {
+        self.entries
+            .iter()
+            .map(|entry| (entry, cache_dir.join(&entry.path)))
+            .filter(|(entry, path)| {
+                hash_file(path).map_or(true, |(size, sha256)| {
+                    size != entry.size || sha256 != entry.sha256
+                })
+            })
+            .map(|(_, path)| path)
+            .collect()
+    }
+}
+
+///
+/// Collect the regular files below `dir` recursively
+///
+fn collect_files(dir: &Path, files: &mut Vec<PathBuf>) -> PgResult<()> {
+    let read_error = |e| PgEmbedError::ReadFileError {
+        path: dir.to_path_buf(),
+        e,
+    };
+    for entry in std::fs::read_dir(dir).map_err(read_error)? {
+        let entry = entry.map_err(read_error)?;
+        let file_type = entry.file_type().map_err(read_error)?;
+        if file_type.is_dir() {
+            collect_files(&entry.path(), files)?;
+        } else if file_type.is_file() {
+            files.push(entry.path());
+        }
+    }
+    Ok(())
+}
+
+///
+/// Size and hex encoded sha256 hash of a file
+///
+fn hash_file(path: &Path) -> PgResult<(u64, String)> {
+    let read_error = |e| PgEmbedError::ReadFileError {
+        path: path.to_path_buf(),
+        e,
+    };
+    let mut reader = BufReader::new(File::open(path).map_err(read_error)?);
+    let mut hasher = Sha256::new();
+    let mut buffer = [0u8; 64 * 1024];
+    let mut size = 0u64;
+    loop {
+        let read = reader.read(&mut buffer).map_err(read_error)?;
+        if read == 0 {
+            break;
+        }
+        hasher.update(&buffer[..read]);
+        size += read as u64;
+    }
+    let sha256 = hasher
+        .finalize()
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect();
+    Ok((size, sha256))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn manifest_detects_changes() -> Result<(), PgEmbedError> {
+        let dir = std::env::temp_dir().join(format!("pg_embed_manifest_{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("bin")).unwrap();
+        std::fs::create_dir_all(dir.join("lib").join("postgresql")).unwrap();
+        std::fs::write(dir.join("bin").join("postgres"), b"postgres").unwrap();
+        std::fs::write(
+            dir.join("lib").join("postgresql").join("plpgsql.so"),
+            b"plpgsql",
+        )
+        .unwrap();
+        std::fs::write(dir.join("notes.txt"), b"not recorded").unwrap();
+
+        let manifest = CacheManifest::create(&dir, &["bin", "lib", "share"])?;
+        assert_eq!(2, manifest.entries.len());
+        let manifest_path = dir.join(".manifest");
+        manifest.write(&manifest_path)?;
+        assert_eq!(Some(manifest.clone()), CacheManifest::read(&manifest_path)?);
+        assert!(manifest.corrupted_files(&dir).is_empty());
+
+        std::fs::write(dir.join("bin").join("postgres"), b"postgreS").unwrap();
+        assert!(manifest.missing_files(&dir).is_empty());
+        assert_eq!(
+            vec![dir.join("bin").join("postgres")],
+            manifest.corrupted_files(&dir)
+        );
+
+        std::fs::remove_file(dir.join("lib").join("postgresql").join("plpgsql.so")).unwrap();
+        assert_eq!(
+            vec![dir.join("lib").join("postgresql").join("plpgsql.so")],
+            manifest.missing_files(&dir)
+        );
+        std::fs::remove_dir_all(&dir).unwrap();
+        Ok(())
+    }
+}
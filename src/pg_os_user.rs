@@ -0,0 +1,204 @@
+//!
+//! Operating system user
+//!
+//! Run postgresql processes as a separate, less privileged operating system user
+//!
+use std::path::Path;
+
+use crate::pg_errors::PgEmbedError;
+use crate::pg_types::PgResult;
+
+///
+/// Operating system user the postgresql processes are executed as
+///
+/// Only supported on unix systems, the managing process needs the privileges
+/// to switch users (*usually root*). On windows starting a process as another user requires
+/// the credentials of that user, [PgOsUser::lookup] fails with
+/// [PgEmbedError::UnsupportedPlatform] there. Run the whole managing process as the
+/// unprivileged user instead.
+///
+#[derive(Debug, Clone, PartialEq)]
+pub struct PgOsUser {
+    /// user name
+    pub name: String,
+    /// user id
+    pub uid: u32,
+    /// primary group id
+    pub gid: u32,
+}
+
+impl PgOsUser {
+    ///
+    /// Look up an operating system user by name
+    ///
+    #[cfg(unix)]
+    pub fn lookup(name: &str) -> PgResult<Self> {
+        let c_name = std::ffi::CString::new(name)
+            .map_err(|_| PgEmbedError::OsUserNotFound(name.to_string()))?;
+        let mut passwd: libc::passwd = unsafe { std::mem::zeroed() };
+        let mut result: *mut libc::passwd = std::ptr::null_mut();
+        let mut buffer = vec![0 as libc::c_char; 4096];
+        loop {
+            let code = unsafe {
+                libc::getpwnam_r(
+                    c_name.as_ptr(),
+                    &mut passwd,
+                    buffer.as_mut_ptr(),
+                    buffer.len(),
+                    &mut result,
+                )
+            };
+            if code == libc::ERANGE {
+                let len = buffer.len() * 2;
+                buffer.resize(len, 0);
+                continue;
+            }
+            if code != 0 {
+                return Err(PgEmbedError::PgError {
+                    source: Box::new(std::io::Error::from_raw_os_error(code)),
+                    message: format!("look up os user {}", name),
+                });
+            }
+            break;
+        }
+        if result.is_null() {
+            return Err(PgEmbedError::OsUserNotFound(name.to_string()));
+        }
+        Ok(PgOsUser {
+            name: name.to_string(),
+            uid: passwd.pw_uid,
+            gid: passwd.pw_gid,
+        })
+    }
+
+    ///
+    /// Look up an operating system user by name
+    ///
+    /// Not supported on this platform, see [PgOsUser]
+    ///
+    #[cfg(not(unix))]
+    pub fn lookup(_name: &str) -> PgResult<Self> {
+        Err(PgEmbedError::UnsupportedPlatform(
+            "running postgresql as a separate os user".to_string(),
+        ))
+    }
+
+    ///
+    /// Change the ownership of `path` (*recursively for directories*) to this user
+    ///
+    #[cfg(unix)]
+    pub fn chown(&self, path: &Path) -> PgResult<()> {
+        std::os::unix::fs::lchown(path, Some(self.uid), Some(self.gid)).map_err(|e| {
+            PgEmbedError::WriteFileError {
+                path: path.to_path_buf(),
+                e,
+            }
+        })?;
+        if path.is_dir() && !path.is_symlink() {
+            let entries = std::fs::read_dir(path).map_err(|e| PgEmbedError::ReadFileError {
+                path: path.to_path_buf(),
+                e,
+            })?;
+            for entry in entries {
+                let entry = entry.map_err(|e| PgEmbedError::ReadFileError {
+                    path: path.to_path_buf(),
+                    e,
+                })?;
+                self.chown(&entry.path())?;
+            }
+        }
+        Ok(())
+    }
+
+    ///
+    /// Change the ownership of `path` to this user
+    ///
+    /// Not supported on this platform, see [PgOsUser]
+    ///
+    #[cfg(not(unix))]
+    pub fn chown(&self, _path: &Path) -> PgResult<()> {
+        Err(PgEmbedError::UnsupportedPlatform(
+            "running postgresql as a separate os user".to_string(),
+        ))
+    }
+
+    ///
+    /// Configure an async command to be executed as this user
+    ///
+    pub fn apply(&self, command: &mut tokio::process::Command) {
+        #[cfg(unix)]
+        {
+            command.uid(self.uid).gid(self.gid);
+        }
+        #[cfg(not(unix))]
+        let _ = command;
+    }
+
+    ///
+    /// Configure a synchronous command to be executed as this user
+    ///
+    pub fn apply_sync(&self, command: &mut std::process::Command) {
+        #[cfg(unix)]
+        {
+            use std::os::unix::process::CommandExt;
+            command.uid(self.uid).gid(self.gid);
+        }
+        #[cfg(not(unix))]
+        let _ = command;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(unix)]
+    #[test]
+    fn lookup_os_user() -> Result<(), PgEmbedError> {
+        let root = PgOsUser::lookup("root")?;
+        assert_eq!("root", root.name);
+        assert_eq!(0, root.uid);
+        assert!(matches!(
+            PgOsUser::lookup("pg_embed_no_such_user"),
+            Err(PgEmbedError::OsUserNotFound(_))
+        ));
+        assert!(matches!(
+            PgOsUser::lookup("invalid\0name"),
+            Err(PgEmbedError::OsUserNotFound(_))
+        ));
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn chown_recursively() -> Result<(), PgEmbedError> {
+        use std::os::unix::fs::MetadataExt;
+
+        let dir = std::env::temp_dir().join(format!("pg_embed_chown_{}", std::process::id()));
+        let nested = dir.join("nested");
+        std::fs::create_dir_all(&nested).unwrap();
+        std::fs::write(nested.join("file"), "content").unwrap();
+        // changing the ownership to the current owner needs no privileges
+        let metadata = std::fs::metadata(&dir).unwrap();
+        let user = PgOsUser {
+            name: "current".to_string(),
+            uid: metadata.uid(),
+            gid: metadata.gid(),
+        };
+        let result = user.chown(&dir);
+        let owner = std::fs::metadata(nested.join("file")).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+        result?;
+        assert_eq!((user.uid, user.gid), (owner.uid(), owner.gid()));
+        Ok(())
+    }
+
+    #[cfg(not(unix))]
+    #[test]
+    fn lookup_os_user_unsupported() {
+        assert!(matches!(
+            PgOsUser::lookup("postgres"),
+            Err(PgEmbedError::UnsupportedPlatform(_))
+        ));
+    }
+}
@@ -0,0 +1,509 @@
+//!
+//! Server process
+//!
+//! Handle to the postmaster started by `pg_ctl`, identified by the process id recorded in
+//! `postmaster.pid`, and a guard terminating it if this process exits without stopping it
+//!
+use std::path::{Path, PathBuf};
+
+use crate::pg_errors::PgEmbedError;
+use crate::pg_types::PgResult;
+
+/// Process id file of a running server
+const POSTMASTER_PID_FILE_NAME: &str = "postmaster.pid";
+
+///
+/// Handle to the postmaster process
+///
+/// `pg_ctl` detaches the server, so it is not a child of this process and cannot be awaited.
+/// The handle only holds the process id, it may be stale once the server exited.
+///
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct PgServerProcess {
+    pid: u32,
+}
+
+impl PgServerProcess {
+    ///
+    /// Read the postmaster process id of `database_dir`
+    ///
+    /// Returns `Ok(None)` if the server is not running or did not write its process id yet.
+    ///
+    pub fn from_database_dir(database_dir: &Path) -> PgResult<Option<Self>> {
+        let pid_file = database_dir.join(POSTMASTER_PID_FILE_NAME);
+        match std::fs::read_to_string(&pid_file) {
+            Ok(content) => Ok(postmaster_pid(&content).map(|pid| PgServerProcess { pid })),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(PgEmbedError::ReadFileError { path: pid_file, e }),
+        }
+    }
+
+    ///
+    /// Remove the `postmaster.pid` file of `database_dir`
+    ///
+    pub(crate) fn remove_pid_file(database_dir: &Path) -> PgResult<()> {
+        let pid_file = database_dir.join(POSTMASTER_PID_FILE_NAME);
+        match std::fs::remove_file(&pid_file) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => {
+                Err(PgEmbedError::PgCleanUpFailure { path: pid_file, e })
+            }
+            _ => Ok(()),
+        }
+    }
+
+    ///
+    /// Process id of the postmaster
+    ///
+    pub fn pid(&self) -> u32 {
+        self.pid
+    }
+
+    ///
+    /// Check if the process still exists
+    ///
+    #[cfg(unix)]
+    pub fn is_running(&self) -> bool {
+        // signal 0 only checks for existence, EPERM means it exists but belongs to another user
+        let result = unsafe { libc::kill(self.pid as libc::pid_t, 0) };
+        result == 0 || std::io::Error::last_os_error().raw_os_error() == Some(libc::EPERM)
+    }
+
+    ///
+    /// Check if the process still exists
+    ///
+    #[cfg(windows)]
+    pub fn is_running(&self) -> bool {
+        std::process::Command::new("tasklist")
+            .args(["/FI", &format!("PID eq {}", self.pid), "/NH"].iter())
+            .stderr(std::process::Stdio::null())
+            .output()
+            .map(|output| {
+                String::from_utf8_lossy(&output.stdout)
+                    .split_whitespace()
+                    .any(|field| field == self.pid.to_string())
+            })
+            .unwrap_or(false)
+    }
+
+    ///
+    /// Check if the process still exists and is a postgresql server
+    ///
+    /// A process id left in `postmaster.pid` by a killed server may have been reused by an
+    /// unrelated process.
+    ///
+    pub fn is_postgres(&self) -> bool {
+        process_name(self.pid).is_some_and(|name| {
+            let name = name.trim().to_lowercase();
+            let name = Path::new(&name)
+                .file_stem()
+                .map(|stem| stem.to_string_lossy().into_owned())
+                .unwrap_or_default();
+            name == "postgres" || name == "postmaster"
+        })
+    }
+
+    ///
+    /// Check if the process is the postgresql server of `database_dir`
+    ///
+    /// The postmaster changes its working directory to the database directory, which is
+    /// compared if it can be determined (*linux and other unix systems with `lsof`*).
+    /// Returns `None` for a postgresql server whose database directory is unknown, it may be
+    /// an unrelated server which reused the process id.
+    ///
+    pub fn is_postmaster_of(&self, database_dir: &Path) -> Option<bool> {
+        if !self.is_postgres() {
+            return Some(false);
+        }
+        let working_dir = process_working_dir(self.pid)?;
+        let database_dir = std::fs::canonicalize(database_dir).ok()?;
+        Some(working_dir == database_dir)
+    }
+
+    ///
+    /// Send a signal to the postmaster, e.g. `libc::SIGHUP` to reload the configuration
+    ///
+    #[cfg(unix)]
+    pub fn signal(&self, signal: i32) -> std::io::Result<()> {
+        if unsafe { libc::kill(self.pid as libc::pid_t, signal) } == 0 {
+            Ok(())
+        } else {
+            Err(std::io::Error::last_os_error())
+        }
+    }
+
+    ///
+    /// Kill the postmaster and its child processes
+    ///
+    pub fn kill(&self) -> std::io::Result<()> {
+        kill_process_tree(self.pid)
+    }
+}
+
+///
+/// Terminates the server if this process dies without stopping it, e.g. when an aborted test
+/// run is killed
+///
+/// On unix `pg_ctl` starts the postmaster in a session of its own, which a dying parent does
+/// not take down. A watchdog shell in its own process group waits for its stdin pipe to be
+/// closed, which happens on any exit of this process, and then shuts down the server of the
+/// database directory (*`SIGQUIT`, after ten seconds `SIGKILL` to its process group*).
+///
+/// On windows the server is assigned to a job object which kills its processes once the last
+/// handle to it is closed.
+///
+#[derive(Debug)]
+pub(crate) struct PgOrphanGuard {
+    #[cfg(unix)]
+    watchdog: std::process::Child,
+    #[cfg(windows)]
+    job: JobHandle,
+}
+
+/// Watchdog script, `$0` is the database directory
+#[cfg(unix)]
+const WATCHDOG_SCRIPT: &str = r#"while read -r _; do :; done
+pid=$(head -n 1 "$0/postmaster.pid" 2>/dev/null)
+[ -n "$pid" ] && kill -QUIT "$pid" 2>/dev/null || exit 0
+for _ in 1 2 3 4 5 6 7 8 9 10; do kill -0 "$pid" 2>/dev/null || exit 0; sleep 1; done
+kill -KILL -- "-$pid" 2>/dev/null || kill -KILL "$pid" 2>/dev/null"#;
+
+impl PgOrphanGuard {
+    ///
+    /// Spawn the watchdog of `database_dir`
+    ///
+    #[cfg(unix)]
+    pub(crate) fn new(database_dir: &Path) -> std::io::Result<Self> {
+        use std::os::unix::process::CommandExt;
+        use std::process::Stdio;
+
+        let watchdog = std::process::Command::new("sh")
+            .arg("-c")
+            .arg(WATCHDOG_SCRIPT)
+            .arg(database_dir)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            // not interrupted together with this process, e.g. by ctrl-c in a terminal
+            .process_group(0)
+            .spawn()?;
+        Ok(PgOrphanGuard { watchdog })
+    }
+
+    ///
+    /// Create the job object
+    ///
+    #[cfg(windows)]
+    pub(crate) fn new(_database_dir: &Path) -> std::io::Result<Self> {
+        Ok(PgOrphanGuard {
+            job: JobHandle::new()?,
+        })
+    }
+
+    ///
+    /// Guard a started server
+    ///
+    /// The watchdog reads the process id when this process exits, so restarted servers are
+    /// covered as well.
+    ///
+    #[cfg(unix)]
+    pub(crate) fn watch(&self, _process: &PgServerProcess) -> std::io::Result<()> {
+        Ok(())
+    }
+
+    ///
+    /// Guard a started server
+    ///
+    /// Processes the postmaster starts afterwards inherit the job.
+    ///
+    #[cfg(windows)]
+    pub(crate) fn watch(&self, process: &PgServerProcess) -> std::io::Result<()> {
+        self.job.assign(process.pid)
+    }
+}
+
+#[cfg(unix)]
+impl Drop for PgOrphanGuard {
+    fn drop(&mut self) {
+        // killed before its stdin is closed, the server is stopped by its owner
+        let _ = self.watchdog.kill();
+        let _ = self.watchdog.wait();
+    }
+}
+
+///
+/// Job object killing its processes when closed
+///
+#[cfg(windows)]
+#[derive(Debug)]
+struct JobHandle(windows_sys::Win32::Foundation::HANDLE);
+
+// the handle is only passed to thread safe kernel calls
+#[cfg(windows)]
+unsafe impl Send for JobHandle {}
+#[cfg(windows)]
+unsafe impl Sync for JobHandle {}
+
+#[cfg(windows)]
+impl JobHandle {
+    fn new() -> std::io::Result<Self> {
+        use windows_sys::Win32::System::JobObjects::{
+            CreateJobObjectW, JobObjectExtendedLimitInformation, SetInformationJobObject,
+            JOBOBJECT_EXTENDED_LIMIT_INFORMATION, JOB_OBJECT_LIMIT_KILL_ON_JOB_CLOSE,
+        };
+
+        let handle = unsafe { CreateJobObjectW(std::ptr::null(), std::ptr::null()) };
+        if handle.is_null() {
+            return Err(std::io::Error::last_os_error());
+        }
+        let job = JobHandle(handle);
+        let mut limits = JOBOBJECT_EXTENDED_LIMIT_INFORMATION::default();
+        limits.BasicLimitInformation.LimitFlags = JOB_OBJECT_LIMIT_KILL_ON_JOB_CLOSE;
+        let result = unsafe {
+            SetInformationJobObject(
+                job.0,
+                JobObjectExtendedLimitInformation,
+                &limits as *const JOBOBJECT_EXTENDED_LIMIT_INFORMATION as *const _,
+                std::mem::size_of::<JOBOBJECT_EXTENDED_LIMIT_INFORMATION>() as u32,
+            )
+        };
+        if result == 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+        Ok(job)
+    }
+
+    fn assign(&self, pid: u32) -> std::io::Result<()> {
+        use windows_sys::Win32::Foundation::CloseHandle;
+        use windows_sys::Win32::System::JobObjects::AssignProcessToJobObject;
+        use windows_sys::Win32::System::Threading::{
+            OpenProcess, PROCESS_SET_QUOTA, PROCESS_TERMINATE,
+        };
+
+        let process = unsafe { OpenProcess(PROCESS_SET_QUOTA | PROCESS_TERMINATE, 0, pid) };
+        if process.is_null() {
+            return Err(std::io::Error::last_os_error());
+        }
+        let result = unsafe { AssignProcessToJobObject(self.0, process) };
+        let error = std::io::Error::last_os_error();
+        unsafe { CloseHandle(process) };
+        if result == 0 {
+            return Err(error);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(windows)]
+impl Drop for JobHandle {
+    fn drop(&mut self) {
+        unsafe { windows_sys::Win32::Foundation::CloseHandle(self.0) };
+    }
+}
+
+///
+/// Process id of the postmaster, the first line of `postmaster.pid`
+///
+fn postmaster_pid(content: &str) -> Option<u32> {
+    content
+        .lines()
+        .next()?
+        .trim()
+        .parse()
+        .ok()
+        .filter(|pid| *pid > 0)
+}
+
+///
+/// Executable name of a running process
+///
+#[cfg(target_os = "linux")]
+fn process_name(pid: u32) -> Option<String> {
+    std::fs::read_to_string(format!("/proc/{}/comm", pid)).ok()
+}
+
+///
+/// Executable name of a running process
+///
+#[cfg(all(unix, not(target_os = "linux")))]
+fn process_name(pid: u32) -> Option<String> {
+    let output = std::process::Command::new("ps")
+        .args(["-o", "comm=", "-p", &pid.to_string()].iter())
+        .stderr(std::process::Stdio::null())
+        .output()
+        .ok()?;
+    let name = String::from_utf8_lossy(&output.stdout).trim().to_string();
+    Some(name).filter(|name| output.status.success() && !name.is_empty())
+}
+
+///
+/// Executable name of a running process
+///
+#[cfg(windows)]
+fn process_name(pid: u32) -> Option<String> {
+    let output = std::process::Command::new("tasklist")
+        .args(["/FI", &format!("PID eq {}", pid), "/FO", "CSV", "/NH"].iter())
+        .stderr(std::process::Stdio::null())
+        .output()
+        .ok()?;
+    // "postgres.exe","4242",...
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let mut fields = stdout
+        .split(',')
+        .map(|field| field.trim().trim_matches('"'));
+    let name = fields.next()?.to_string();
+    Some(name).filter(|_| fields.next() == Some(pid.to_string().as_str()))
+}
+
+///
+/// Working directory of a running process
+///
+#[cfg(target_os = "linux")]
+fn process_working_dir(pid: u32) -> Option<PathBuf> {
+    std::fs::read_link(format!("/proc/{}/cwd", pid)).ok()
+}
+
+///
+/// Working directory of a running process
+///
+#[cfg(all(unix, not(target_os = "linux")))]
+fn process_working_dir(pid: u32) -> Option<PathBuf> {
+    let output = std::process::Command::new("lsof")
+        .args(["-a", "-p", &pid.to_string(), "-d", "cwd", "-Fn"].iter())
+        .stderr(std::process::Stdio::null())
+        .output()
+        .ok()?;
+    // p4242
+    // n/var/lib/postgresql/data
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let dir = stdout.lines().find_map(|line| line.strip_prefix('n'))?;
+    Some(PathBuf::from(dir)).filter(|_| output.status.success())
+}
+
+///
+/// Working directory of a running process, not determinable without elevated rights
+///
+#[cfg(windows)]
+fn process_working_dir(_pid: u32) -> Option<PathBuf> {
+    None
+}
+
+///
+/// Kill a process, together with its process group if it leads one
+///
+#[cfg(unix)]
+fn kill_process_tree(pid: u32) -> std::io::Result<()> {
+    let pid = pid as libc::pid_t;
+    // the children of the postmaster exit on their own once it is gone
+    let target = if unsafe { libc::getpgid(pid) } == pid {
+        -pid
+    } else {
+        pid
+    };
+    if unsafe { libc::kill(target, libc::SIGKILL) } == 0 {
+        return Ok(());
+    }
+    match std::io::Error::last_os_error() {
+        // already exited
+        e if e.raw_os_error() == Some(libc::ESRCH) => Ok(()),
+        e => Err(e),
+    }
+}
+
+///
+/// Kill a process and its child processes
+///
+#[cfg(windows)]
+fn kill_process_tree(pid: u32) -> std::io::Result<()> {
+    std::process::Command::new("taskkill")
+        .args(["/F", "/T", "/PID", &pid.to_string()].iter())
+        .stdout(std::process::Stdio::null())
+        .stderr(std::process::Stdio::null())
+        .status()
+        .map(|_| ())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_postmaster_pid() {
+        assert_eq!(
+            Some(4242),
+            postmaster_pid("4242\n/var/lib/postgresql/data\n1700000000\n")
+        );
+        assert_eq!(None, postmaster_pid(""));
+        assert_eq!(None, postmaster_pid("0\n"));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn kill_server_process() {
+        let dir = std::env::temp_dir().join(format!("pg_embed_process_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        assert_eq!(None, PgServerProcess::from_database_dir(&dir).unwrap());
+
+        let mut child = std::process::Command::new("sleep")
+            .arg("30")
+            .spawn()
+            .unwrap();
+        std::fs::write(
+            dir.join(POSTMASTER_PID_FILE_NAME),
+            format!("{}\n", child.id()),
+        )
+        .unwrap();
+        let process = PgServerProcess::from_database_dir(&dir).unwrap().unwrap();
+        assert_eq!(child.id(), process.pid());
+        assert!(process.is_running());
+        assert!(!process.is_postgres());
+        assert_eq!(Some(false), process.is_postmaster_of(&dir));
+        process.kill().unwrap();
+        child.wait().unwrap();
+        assert!(!process.is_running());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn working_dir_of_process() {
+        let dir = std::env::temp_dir().join(format!("pg_embed_cwd_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let mut child = std::process::Command::new("sleep")
+            .arg("30")
+            .current_dir(&dir)
+            .spawn()
+            .unwrap();
+        assert_eq!(
+            Some(std::fs::canonicalize(&dir).unwrap()),
+            process_working_dir(child.id())
+        );
+        child.kill().unwrap();
+        child.wait().unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn orphaned_server_is_terminated() {
+        use std::os::unix::process::{CommandExt, ExitStatusExt};
+
+        let dir = std::env::temp_dir().join(format!("pg_embed_orphan_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let mut server = std::process::Command::new("sleep")
+            .arg("30")
+            .process_group(0)
+            .spawn()
+            .unwrap();
+        std::fs::write(
+            dir.join(POSTMASTER_PID_FILE_NAME),
+            format!("{}\n", server.id()),
+        )
+        .unwrap();
+        let mut guard = PgOrphanGuard::new(&dir).unwrap();
+        // the pipe is closed the same way when this process exits
+        drop(guard.watchdog.stdin.take());
+        assert_eq!(Some(libc::SIGQUIT), server.wait().unwrap().signal());
+        guard.watchdog.wait().unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
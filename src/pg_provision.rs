@@ -0,0 +1,500 @@
+//!
+//! Databases, roles, privileges, extensions and logical replication
+//!
+//! Roles and databases listed in [crate::postgres::PgSettings::roles] and
+//! [crate::postgres::PgSettings::databases] are created on start if they don't exist yet,
+//! privileges are granted with [crate::postgres::PgEmbed::grant], extensions are created with
+//! [crate::postgres::PgEmbed::create_extension] and publications are subscribed to with
+//! [crate::postgres::PgEmbed::create_subscription]
+//!
+use crate::pg_types::SecretString;
+use crate::postgres::{quote_identifier, quote_literal};
+
+///
+/// Role attribute
+///
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
+pub enum PgRoleAttribute {
+    /// the role can log in
+    Login,
+    /// the role bypasses all permission checks
+    Superuser,
+    /// the role can create databases
+    CreateDb,
+    /// the role can create roles
+    CreateRole,
+    /// the role can start streaming replication
+    Replication,
+    /// the role bypasses row level security policies
+    BypassRls,
+}
+
+impl std::fmt::Display for PgRoleAttribute {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PgRoleAttribute::Login => write!(f, "LOGIN"),
+            PgRoleAttribute::Superuser => write!(f, "SUPERUSER"),
+            PgRoleAttribute::CreateDb => write!(f, "CREATEDB"),
+            PgRoleAttribute::CreateRole => write!(f, "CREATEROLE"),
+            PgRoleAttribute::Replication => write!(f, "REPLICATION"),
+            PgRoleAttribute::BypassRls => write!(f, "BYPASSRLS"),
+        }
+    }
+}
+
+///
+/// Role created on start
+///
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct PgRoleSpec {
+    /// role name
+    pub name: String,
+    /// password of the role
+    /// if set to None the role has no password
+    pub password: Option<SecretString>,
+    /// role attributes, e.g. [PgRoleAttribute::Login]
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub attributes: Vec<PgRoleAttribute>,
+}
+
+impl PgRoleSpec {
+    ///
+    /// Role `name` without password and attributes
+    ///
+    pub fn new(name: impl Into<String>) -> Self {
+        PgRoleSpec {
+            name: name.into(),
+            password: None,
+            attributes: Vec::new(),
+        }
+    }
+
+    ///
+    /// Role able to log in with `password`
+    ///
+    pub fn login(name: impl Into<String>, password: impl Into<SecretString>) -> Self {
+        PgRoleSpec::new(name)
+            .password(password)
+            .attribute(PgRoleAttribute::Login)
+    }
+
+    /// Set the password
+    pub fn password(mut self, password: impl Into<SecretString>) -> Self {
+        self.password = Some(password.into());
+        self
+    }
+
+    /// Add a role attribute
+    pub fn attribute(mut self, attribute: PgRoleAttribute) -> Self {
+        self.attributes.push(attribute);
+        self
+    }
+
+    ///
+    /// `CREATE ROLE` statement of the role
+    ///
+    pub(crate) fn create_statement(&self) -> String {
+        let mut statement = format!("CREATE ROLE {}", quote_identifier(&self.name));
+        if !self.attributes.is_empty() || self.password.is_some() {
+            statement.push_str(" WITH");
+        }
+        for attribute in &self.attributes {
+            statement.push_str(&format!(" {}", attribute));
+        }
+        if let Some(password) = &self.password {
+            statement.push_str(&format!(
+                " PASSWORD {}",
+                quote_literal(password.expose_secret())
+            ));
+        }
+        statement
+    }
+}
+
+///
+/// Database created on start
+///
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct PgDatabaseSpec {
+    /// database name
+    pub name: String,
+    /// owning role, declared roles are created before the databases
+    /// if set to None the database is owned by [crate::postgres::PgSettings::user]
+    pub owner: Option<String>,
+    /// character set encoding, e.g. `UTF8`
+    /// if set to None the encoding of the database cluster is used
+    pub encoding: Option<String>,
+}
+
+impl PgDatabaseSpec {
+    ///
+    /// Database `name` with the default owner and encoding
+    ///
+    pub fn new(name: impl Into<String>) -> Self {
+        PgDatabaseSpec {
+            name: name.into(),
+            owner: None,
+            encoding: None,
+        }
+    }
+
+    /// Set the owning role
+    pub fn owner(mut self, owner: impl Into<String>) -> Self {
+        self.owner = Some(owner.into());
+        self
+    }
+
+    /// Set the character set encoding
+    pub fn encoding(mut self, encoding: impl Into<String>) -> Self {
+        self.encoding = Some(encoding.into());
+        self
+    }
+
+    ///
+    /// `CREATE DATABASE` statement of the database
+    ///
+    /// A database with an explicit encoding is created from `template0`, `template1` only
+    /// allows the encoding of the database cluster.
+    ///
+    pub(crate) fn create_statement(&self) -> String {
+        let mut statement = format!("CREATE DATABASE {}", quote_identifier(&self.name));
+        if let Some(owner) = &self.owner {
+            statement.push_str(&format!(" OWNER {}", quote_identifier(owner)));
+        }
+        if let Some(encoding) = &self.encoding {
+            statement.push_str(&format!(
+                " ENCODING {} TEMPLATE template0",
+                quote_literal(encoding)
+            ));
+        }
+        statement
+    }
+}
+
+///
+/// Database of the cluster, see [crate::postgres::PgEmbed::list_databases]
+///
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PgDatabaseInfo {
+    /// database name
+    pub name: String,
+    /// owning role
+    pub owner: String,
+    /// character set encoding, e.g. `UTF8`
+    pub encoding: String,
+    /// disk space used in bytes
+    pub size: u64,
+    /// the database can be copied by `CREATE DATABASE .. TEMPLATE`
+    pub is_template: bool,
+}
+
+///
+/// Extension shipped with the postgresql binaries, see
+/// [crate::postgres::PgEmbed::available_extensions]
+///
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PgExtensionInfo {
+    /// extension name, e.g. `pgcrypto`
+    pub name: String,
+    /// version installed by `CREATE EXTENSION` without a version
+    pub default_version: Option<String>,
+    /// version installed in the database
+    /// if set to None the extension is not installed
+    pub installed_version: Option<String>,
+    /// description of the extension
+    pub comment: Option<String>,
+}
+
+///
+/// Privilege granted on a database object
+///
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
+pub enum PgPrivilege {
+    /// all privileges of the object type
+    All,
+    /// read rows of a table
+    Select,
+    /// insert rows into a table
+    Insert,
+    /// update rows of a table
+    Update,
+    /// delete rows of a table
+    Delete,
+    /// truncate a table
+    Truncate,
+    /// reference a table in a foreign key
+    References,
+    /// create triggers on a table
+    Trigger,
+    /// create schemas in a database or objects in a schema
+    Create,
+    /// connect to a database
+    Connect,
+    /// create temporary tables in a database
+    Temporary,
+    /// look up objects in a schema
+    Usage,
+}
+
+impl std::fmt::Display for PgPrivilege {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PgPrivilege::All => write!(f, "ALL PRIVILEGES"),
+            PgPrivilege::Select => write!(f, "SELECT"),
+            PgPrivilege::Insert => write!(f, "INSERT"),
+            PgPrivilege::Update => write!(f, "UPDATE"),
+            PgPrivilege::Delete => write!(f, "DELETE"),
+            PgPrivilege::Truncate => write!(f, "TRUNCATE"),
+            PgPrivilege::References => write!(f, "REFERENCES"),
+            PgPrivilege::Trigger => write!(f, "TRIGGER"),
+            PgPrivilege::Create => write!(f, "CREATE"),
+            PgPrivilege::Connect => write!(f, "CONNECT"),
+            PgPrivilege::Temporary => write!(f, "TEMPORARY"),
+            PgPrivilege::Usage => write!(f, "USAGE"),
+        }
+    }
+}
+
+///
+/// Database object privileges are granted on
+///
+/// Schemas and tables are looked up in `database`, tables through its `search_path`.
+///
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PgGrantObject {
+    /// a database
+    Database(String),
+    /// a schema of a database
+    Schema { database: String, schema: String },
+    /// a table of a database
+    Table { database: String, table: String },
+    /// all existing tables of a schema
+    AllTablesInSchema { database: String, schema: String },
+}
+
+impl PgGrantObject {
+    ///
+    /// Database to connect to for the grant, `None` for the maintenance database
+    ///
+    pub(crate) fn database(&self) -> Option<&str> {
+        match self {
+            PgGrantObject::Database(_) => None,
+            PgGrantObject::Schema { database, .. }
+            | PgGrantObject::Table { database, .. }
+            | PgGrantObject::AllTablesInSchema { database, .. } => Some(database),
+        }
+    }
+}
+
+impl std::fmt::Display for PgGrantObject {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PgGrantObject::Database(database) => {
+                write!(f, "DATABASE {}", quote_identifier(database))
+            }
+            PgGrantObject::Schema { schema, .. } => {
+                write!(f, "SCHEMA {}", quote_identifier(schema))
+            }
+            PgGrantObject::Table { table, .. } => write!(f, "TABLE {}", quote_identifier(table)),
+            PgGrantObject::AllTablesInSchema { schema, .. } => {
+                write!(f, "ALL TABLES IN SCHEMA {}", quote_identifier(schema))
+            }
+        }
+    }
+}
+
+///
+/// `GRANT` statement of `privileges` on `object` to `role`
+///
+pub(crate) fn grant_statement(
+    privileges: &[PgPrivilege],
+    object: &PgGrantObject,
+    role: &str,
+) -> String {
+    format!(
+        "GRANT {} ON {} TO {}",
+        privilege_list(privileges),
+        object,
+        quote_identifier(role)
+    )
+}
+
+///
+/// `REVOKE` statement of `privileges` on `object` from `role`
+///
+pub(crate) fn revoke_statement(
+    privileges: &[PgPrivilege],
+    object: &PgGrantObject,
+    role: &str,
+) -> String {
+    format!(
+        "REVOKE {} ON {} FROM {}",
+        privilege_list(privileges),
+        object,
+        quote_identifier(role)
+    )
+}
+
+///
+/// Tables published by a publication
+///
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PgPublicationTables {
+    /// all tables of the database, including tables created later
+    All,
+    /// the listed tables, looked up through the `search_path`
+    Tables(Vec<String>),
+}
+
+impl std::fmt::Display for PgPublicationTables {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PgPublicationTables::All => write!(f, "FOR ALL TABLES"),
+            PgPublicationTables::Tables(tables) if tables.is_empty() => Ok(()),
+            PgPublicationTables::Tables(tables) => {
+                let tables = tables
+                    .iter()
+                    .map(|table| quote_identifier(table))
+                    .collect::<Vec<_>>()
+                    .join(", ");
+                write!(f, "FOR TABLE {}", tables)
+            }
+        }
+    }
+}
+
+///
+/// Replication slot of the cluster, see [crate::postgres::PgEmbed::replication_slots]
+///
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PgReplicationSlotInfo {
+    /// slot name
+    pub name: String,
+    /// output plugin of a logical slot, e.g. `pgoutput`
+    pub plugin: Option<String>,
+    /// database of a logical slot, physical slots belong to the cluster
+    pub database: Option<String>,
+    /// a client is streaming from the slot
+    pub active: bool,
+}
+
+///
+/// `CREATE PUBLICATION` statement of the publication `name` of `tables`
+///
+pub(crate) fn publication_statement(name: &str, tables: &PgPublicationTables) -> String {
+    format!("CREATE PUBLICATION {} {}", quote_identifier(name), tables)
+        .trim_end()
+        .to_string()
+}
+
+///
+/// `CREATE SUBSCRIPTION` statement of the subscription `name` to `publications` of the
+/// server at `conninfo`
+///
+pub(crate) fn subscription_statement(name: &str, conninfo: &str, publications: &[&str]) -> String {
+    let publications = publications
+        .iter()
+        .map(|publication| quote_identifier(publication))
+        .collect::<Vec<_>>()
+        .join(", ");
+    format!(
+        "CREATE SUBSCRIPTION {} CONNECTION {} PUBLICATION {}",
+        quote_identifier(name),
+        quote_literal(conninfo),
+        publications
+    )
+}
+
+fn privilege_list(privileges: &[PgPrivilege]) -> String {
+    privileges
+        .iter()
+        .map(|privilege| privilege.to_string())
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn create_statements() {
+        assert_eq!(
+            r#"CREATE ROLE "reader""#,
+            PgRoleSpec::new("reader").create_statement()
+        );
+        let role = PgRoleSpec::login("app", "it's").attribute(PgRoleAttribute::CreateDb);
+        assert_eq!(
+            r#"CREATE ROLE "app" WITH LOGIN CREATEDB PASSWORD 'it''s'"#,
+            role.create_statement()
+        );
+        assert_eq!(
+            r#"CREATE DATABASE "app""#,
+            PgDatabaseSpec::new("app").create_statement()
+        );
+        let database = PgDatabaseSpec::new("app").owner("app").encoding("UTF8");
+        assert_eq!(
+            r#"CREATE DATABASE "app" OWNER "app" ENCODING 'UTF8' TEMPLATE template0"#,
+            database.create_statement()
+        );
+    }
+
+    #[test]
+    fn grant_statements() {
+        let table = PgGrantObject::Table {
+            database: "app".to_string(),
+            table: "users".to_string(),
+        };
+        assert_eq!(Some("app"), table.database());
+        assert_eq!(
+            r#"GRANT SELECT, INSERT ON TABLE "users" TO "reader""#,
+            grant_statement(
+                &[PgPrivilege::Select, PgPrivilege::Insert],
+                &table,
+                "reader"
+            )
+        );
+        let database = PgGrantObject::Database("app".to_string());
+        assert_eq!(None, database.database());
+        assert_eq!(
+            r#"REVOKE ALL PRIVILEGES ON DATABASE "app" FROM "reader""#,
+            revoke_statement(&[PgPrivilege::All], &database, "reader")
+        );
+        let schema = PgGrantObject::AllTablesInSchema {
+            database: "app".to_string(),
+            schema: "public".to_string(),
+        };
+        assert_eq!(
+            r#"GRANT UPDATE ON ALL TABLES IN SCHEMA "public" TO "app""#,
+            grant_statement(&[PgPrivilege::Update], &schema, "app")
+        );
+    }
+
+    #[test]
+    fn replication_statements() {
+        assert_eq!(
+            r#"CREATE PUBLICATION "changes" FOR ALL TABLES"#,
+            publication_statement("changes", &PgPublicationTables::All)
+        );
+        let tables = PgPublicationTables::Tables(vec!["users".to_string(), "orders".to_string()]);
+        assert_eq!(
+            r#"CREATE PUBLICATION "changes" FOR TABLE "users", "orders""#,
+            publication_statement("changes", &tables)
+        );
+        let empty = PgPublicationTables::Tables(Vec::new());
+        assert_eq!(
+            r#"CREATE PUBLICATION "changes""#,
+            publication_statement("changes", &empty)
+        );
+        assert_eq!(
+            r#"CREATE SUBSCRIPTION "sync" CONNECTION 'user=it''s' PUBLICATION "a", "b""#,
+            subscription_statement("sync", "user=it's", &["a", "b"])
+        );
+    }
+}
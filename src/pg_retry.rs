@@ -0,0 +1,183 @@
+//!
+//! Retry file operations
+//!
+//! On Windows freshly written executables and libraries are briefly locked by virus scanners
+//! (*e.g. Defender*), operations failing with a sharing violation are retried with a delay.
+//!
+use std::io;
+use std::time::Duration;
+
+use crate::pg_errors::PgEmbedError;
+
+/// Number of attempts before giving up
+const MAX_ATTEMPTS: u32 = 8;
+/// Delay before the first retry, doubled after each attempt
+const INITIAL_DELAY: Duration = Duration::from_millis(50);
+/// Upper bound of the delay between two attempts
+const MAX_DELAY: Duration = Duration::from_secs(1);
+
+///
+/// Errors which may be caused by a file being locked temporarily
+///
+pub(crate) trait TransientError: Sized {
+    /// Check if the operation might succeed when retried
+    fn is_transient(&self) -> bool;
+    /// Error returned after the retries have been exhausted
+    fn exhausted(self, operation: &str, attempts: u32) -> Self;
+}
+
+impl TransientError for io::Error {
+    fn is_transient(&self) -> bool {
+        is_sharing_violation(self)
+    }
+
+    fn exhausted(self, operation: &str, attempts: u32) -> Self {
+        io::Error::new(
+            self.kind(),
+            format!(
+                "{} failed {} times, the file is locked by another process: {}",
+                operation, attempts, self
+            ),
+        )
+    }
+}
+
+impl TransientError for PgEmbedError {
+    fn is_transient(&self) -> bool {
+        match self {
+            PgEmbedError::UnpackFailure(e)
+            | PgEmbedError::ReadFileError { e, .. }
+            | PgEmbedError::WriteFileError { e, .. }
+            | PgEmbedError::PgCleanUpFailure { e, .. } => is_sharing_violation(e),
+            _ => false,
+        }
+    }
+
+    fn exhausted(self, operation: &str, attempts: u32) -> Self {
+        PgEmbedError::PgError {
+            message: format!(
+                "{} failed {} times, a file is locked by another process",
+                operation, attempts
+            ),
+            source: Box::new(self),
+        }
+    }
+}
+
+///
+/// Run `f`, retrying it with an increasing delay while it fails with a transient error
+///
+/// Waits without blocking the async runtime. Errors are only retried on Windows.
+///
+pub(crate) async fn retry<T, E: TransientError>(
+    operation: &str,
+    mut f: impl FnMut() -> Result<T, E>,
+) -> Result<T, E> {
+    let mut delay = INITIAL_DELAY;
+    let mut attempts = 1;
+    loop {
+        match f() {
+            Err(e) if e.is_transient() && attempts < MAX_ATTEMPTS => {
+                log::debug!("{} failed on attempt {}, retrying", operation, attempts);
+                tokio::time::sleep(delay).await;
+                delay = (delay * 2).min(MAX_DELAY);
+                attempts += 1;
+            }
+            Err(e) if e.is_transient() => return Err(e.exhausted(operation, attempts)),
+            result => return result,
+        }
+    }
+}
+
+///
+/// Run `f` like [retry], blocking the current thread while waiting
+///
+/// Only for synchronous code, e.g. on drop or on a blocking thread of the runtime.
+///
+pub(crate) fn retry_blocking<T, E: TransientError>(
+    operation: &str,
+    mut f: impl FnMut() -> Result<T, E>,
+) -> Result<T, E> {
+    let mut delay = INITIAL_DELAY;
+    let mut attempts = 1;
+    loop {
+        match f() {
+            Err(e) if e.is_transient() && attempts < MAX_ATTEMPTS => {
+                log::debug!("{} failed on attempt {}, retrying", operation, attempts);
+                std::thread::sleep(delay);
+                delay = (delay * 2).min(MAX_DELAY);
+                attempts += 1;
+            }
+            Err(e) if e.is_transient() => return Err(e.exhausted(operation, attempts)),
+            result => return result,
+        }
+    }
+}
+
+#[cfg(windows)]
+fn is_sharing_violation(e: &io::Error) -> bool {
+    // ERROR_SHARING_VIOLATION, ERROR_LOCK_VIOLATION, access denied errors are permanent
+    matches!(e.raw_os_error(), Some(32) | Some(33))
+}
+
+#[cfg(not(windows))]
+fn is_sharing_violation(_e: &io::Error) -> bool {
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Debug, PartialEq)]
+    struct Locked(u32);
+
+    impl TransientError for Locked {
+        fn is_transient(&self) -> bool {
+            true
+        }
+
+        fn exhausted(self, _operation: &str, attempts: u32) -> Self {
+            Locked(attempts)
+        }
+    }
+
+    #[tokio::test]
+    async fn retries_transient_errors() {
+        let mut calls = 0;
+        let result = retry("test", || {
+            calls += 1;
+            if calls < 3 {
+                Err(Locked(0))
+            } else {
+                Ok(calls)
+            }
+        })
+        .await;
+        assert_eq!(Ok(3), result);
+
+        let result: Result<(), _> = retry("test", || Err(Locked(0))).await;
+        assert_eq!(Err(Locked(MAX_ATTEMPTS)), result);
+    }
+
+    #[test]
+    fn retries_transient_errors_blocking() {
+        let mut calls = 0;
+        let result = retry_blocking("test", || {
+            calls += 1;
+            if calls < 2 {
+                Err(Locked(0))
+            } else {
+                Ok(calls)
+            }
+        });
+        assert_eq!(Ok(2), result);
+    }
+
+    #[cfg(windows)]
+    #[test]
+    fn access_denied_is_permanent() {
+        assert!(!io::Error::from_raw_os_error(5).is_transient());
+        assert!(io::Error::from_raw_os_error(32).is_transient());
+    }
+}
@@ -0,0 +1,247 @@
+//!
+//! Termination signals
+//!
+//! Stop the servers and remove their files when the process is interrupted (*ctrl-c*) or
+//! terminated, see [crate::postgres::PgEmbed::install_signal_handlers]
+//!
+use std::collections::HashMap;
+use std::future::Future;
+use std::path::PathBuf;
+use std::process::Stdio;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+
+use crate::pg_commands;
+use crate::pg_enums::PgShutdownMode;
+use crate::pg_errors::PgEmbedError;
+use crate::pg_os_user::PgOsUser;
+use crate::pg_types::PgResult;
+
+/// Exit code after SIGINT, 128 + signal number like a shell
+const SIGINT_EXIT_CODE: i32 = 130;
+/// Exit code after SIGTERM
+#[cfg(unix)]
+const SIGTERM_EXIT_CODE: i32 = 143;
+
+lazy_static! {
+    ///
+    /// Cleanups of the instances with installed signal handlers
+    ///
+    static ref CLEANUPS: Mutex<HashMap<u64, PgSignalCleanup>> = Mutex::new(HashMap::new());
+    ///
+    /// Set once the signal handling thread listens
+    ///
+    static ref INSTALLED: Mutex<bool> = Mutex::new(false);
+}
+
+/// Id of the next registration
+static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+
+///
+/// Stops a server and removes its files on a termination signal
+///
+#[derive(Debug)]
+pub(crate) struct PgSignalCleanup {
+    pub(crate) pg_ctl_exe: PathBuf,
+    pub(crate) database_dir: PathBuf,
+    pub(crate) os_user: Option<PgOsUser>,
+    /// files and directories removed after the server stopped
+    pub(crate) remove: Vec<PathBuf>,
+}
+
+impl PgSignalCleanup {
+    pub(crate) fn run(&self) {
+        let mut command = std::process::Command::new(&self.pg_ctl_exe);
+        command
+            .args(pg_commands::stop_db_args(
+                &self.database_dir,
+                PgShutdownMode::Fast,
+            ))
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null());
+        if let Some(os_user) = &self.os_user {
+            os_user.apply_sync(&mut command);
+        }
+        if let Err(e) = command.status() {
+            log::error!("Failed to stop the postgresql server: {}", e);
+        }
+        for path in &self.remove {
+            let result = if path.is_dir() {
+                std::fs::remove_dir_all(path)
+            } else {
+                std::fs::remove_file(path)
+            };
+            match result {
+                Err(e) if e.kind() != std::io::ErrorKind::NotFound => {
+                    log::error!("Failed to remove {}: {}", path.display(), e)
+                }
+                _ => {}
+            }
+        }
+    }
+}
+
+///
+/// Registered cleanup, unregistered when dropped
+///
+#[derive(Debug)]
+pub(crate) struct PgSignalRegistration {
+    id: u64,
+}
+
+impl Drop for PgSignalRegistration {
+    fn drop(&mut self) {
+        cleanups().remove(&self.id);
+    }
+}
+
+///
+/// Register a cleanup, the signal handlers are installed on first use
+///
+/// Returns the registration on success, otherwise returns an error.
+///
+pub(crate) fn register(cleanup: PgSignalCleanup) -> PgResult<PgSignalRegistration> {
+    install()?;
+    Ok(add(cleanup))
+}
+
+///
+/// Add a cleanup run by the signal handling thread
+///
+fn add(cleanup: PgSignalCleanup) -> PgSignalRegistration {
+    let id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+    cleanups().insert(id, cleanup);
+    PgSignalRegistration { id }
+}
+
+fn cleanups() -> std::sync::MutexGuard<'static, HashMap<u64, PgSignalCleanup>> {
+    CLEANUPS.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+///
+/// Start the signal handling thread
+///
+/// It has a runtime of its own, so it keeps listening after the runtime of the caller (*e.g.
+/// of a single `#[tokio::test]`*) shut down.
+///
+fn install() -> PgResult<()> {
+    let mut installed = INSTALLED.lock().unwrap_or_else(|e| e.into_inner());
+    if *installed {
+        return Ok(());
+    }
+    let install_error = |e| PgEmbedError::PgError {
+        message: "failed to install the signal handlers".to_string(),
+        source: Box::new(e),
+    };
+    let (listening_sender, listening_receiver) = std::sync::mpsc::channel();
+    std::thread::Builder::new()
+        .name("pg-embed-signals".to_string())
+        .spawn(move || {
+            let runtime = tokio::runtime::Builder::new_current_thread()
+                .enable_all()
+                .build();
+            let signal = runtime.and_then(|runtime| {
+                let signal = {
+                    let _context = runtime.enter();
+                    listen()?
+                };
+                Ok((runtime, signal))
+            });
+            let (runtime, signal) = match signal {
+                Ok(listening) => {
+                    let _ = listening_sender.send(Ok(()));
+                    listening
+                }
+                Err(e) => {
+                    let _ = listening_sender.send(Err(e));
+                    return;
+                }
+            };
+            let exit_code = runtime.block_on(signal);
+            log::warn!("Termination signal received, stopping the postgresql servers");
+            let pending: Vec<PgSignalCleanup> = cleanups().drain().map(|(_, c)| c).collect();
+            for cleanup in pending {
+                cleanup.run();
+            }
+            std::process::exit(exit_code);
+        })
+        .map_err(install_error)?;
+    listening_receiver
+        .recv()
+        .unwrap_or_else(|_| Err(std::io::Error::other("signal handling thread exited")))
+        .map_err(install_error)?;
+    *installed = true;
+    Ok(())
+}
+
+///
+/// Listen for SIGINT and SIGTERM
+///
+/// Returns a future resolving to the exit code of the received signal.
+///
+#[cfg(unix)]
+fn listen() -> std::io::Result<impl Future<Output = i32>> {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let mut interrupt = signal(SignalKind::interrupt())?;
+    let mut terminate = signal(SignalKind::terminate())?;
+    Ok(async move {
+        tokio::select! {
+            _ = interrupt.recv() => SIGINT_EXIT_CODE,
+            _ = terminate.recv() => SIGTERM_EXIT_CODE,
+        }
+    })
+}
+
+///
+/// Listen for ctrl-c
+///
+/// Returns a future resolving to the exit code of the received signal.
+///
+#[cfg(windows)]
+fn listen() -> std::io::Result<impl Future<Output = i32>> {
+    let mut ctrl_c = tokio::signal::windows::ctrl_c()?;
+    Ok(async move {
+        ctrl_c.recv().await;
+        SIGINT_EXIT_CODE
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cleanup_is_registered_until_dropped() {
+        // the handlers are not installed, they would exit the test process on ctrl-c
+        let registration = add(PgSignalCleanup {
+            pg_ctl_exe: PathBuf::from("pg_ctl"),
+            database_dir: PathBuf::from("db"),
+            os_user: None,
+            remove: Vec::new(),
+        });
+        let id = registration.id;
+        assert!(cleanups().contains_key(&id));
+        drop(registration);
+        assert!(!cleanups().contains_key(&id));
+    }
+
+    #[test]
+    fn cleanup_removes_files() {
+        let dir = std::env::temp_dir().join(format!("pg_embed_signal_{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("db")).unwrap();
+        std::fs::write(dir.join("pwfile"), "password").unwrap();
+        // the missing pg_ctl does not keep the files from being removed
+        PgSignalCleanup {
+            pg_ctl_exe: dir.join("pg_ctl"),
+            database_dir: dir.join("db"),
+            os_user: None,
+            remove: vec![dir.join("db"), dir.join("pwfile"), dir.join("missing")],
+        }
+        .run();
+        assert!(!dir.join("db").exists());
+        assert!(!dir.join("pwfile").exists());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,117 @@
+//!
+//! Statement statistics
+//!
+//! [crate::postgres::PgEmbed::enable_pg_stat_statements] preloads `pg_stat_statements`, the
+//! statistics of the statements executed since are read with
+//! [crate::postgres::PgEmbed::statement_stats], e.g. to assert on query counts in tests:
+//!
+//! ```rust, ignore
+//! pg.enable_pg_stat_statements().await?;
+//! pg.reset_statement_stats().await?;
+//! run_request().await?;
+//! let stats = pg.statement_stats("app").await?;
+//! assert!(stats.iter().all(|statement| statement.calls < 10));
+//! ```
+//!
+use std::time::Duration;
+
+use crate::pg_fetch::PostgresVersion;
+
+/// Library and extension name of the statement statistics
+pub(crate) const PG_STAT_STATEMENTS: &str = "pg_stat_statements";
+
+///
+/// Execution statistics of a normalized statement, see
+/// [crate::postgres::PgEmbed::statement_stats]
+///
+#[derive(Debug, Clone, PartialEq)]
+pub struct PgStatementStats {
+    /// statement text with constants replaced by parameters, e.g. `SELECT * FROM t WHERE id = $1`
+    pub query: String,
+    /// role executing the statement
+    pub role: String,
+    /// number of executions
+    pub calls: u64,
+    /// total execution time
+    pub total_time: Duration,
+    /// mean execution time
+    pub mean_time: Duration,
+    /// total number of rows retrieved or affected
+    pub rows: u64,
+    /// shared buffer blocks found in the buffer cache
+    pub shared_blocks_hit: u64,
+    /// shared buffer blocks read from disk
+    pub shared_blocks_read: u64,
+}
+
+///
+/// Query of the statement statistics of the database `$1`, ordered by total execution time
+///
+/// Postgresql 13 renamed the execution time columns, e.g. `total_time` to `total_exec_time`.
+///
+pub(crate) fn statement_stats_query(version: &PostgresVersion) -> String {
+    let (total_time, mean_time) = if version.major() >= 13 {
+        ("total_exec_time", "mean_exec_time")
+    } else {
+        ("total_time", "mean_time")
+    };
+    format!(
+        "SELECT s.query, pg_get_userbyid(s.userid)::text, s.calls, s.{total}, s.{mean}, \
+         s.rows, s.shared_blks_hit, s.shared_blks_read \
+         FROM pg_stat_statements s JOIN pg_database d ON d.oid = s.dbid \
+         WHERE d.datname = $1 ORDER BY s.{total} DESC",
+        total = total_time,
+        mean = mean_time
+    )
+}
+
+///
+/// Duration of `milliseconds` as reported by `pg_stat_statements`
+///
+pub(crate) fn duration_from_millis(milliseconds: f64) -> Duration {
+    Duration::from_secs_f64(milliseconds.max(0.0) / 1000.0)
+}
+
+///
+/// `shared_preload_libraries` server parameter `libraries` with `library` added
+///
+/// Returns `None` if `library` is already preloaded.
+///
+pub(crate) fn add_preload_library(libraries: Option<&str>, library: &str) -> Option<String> {
+    let mut libraries = libraries
+        .unwrap_or_default()
+        .split(',')
+        .map(str::trim)
+        .filter(|library| !library.is_empty())
+        .collect::<Vec<_>>();
+    if libraries.contains(&library) {
+        return None;
+    }
+    libraries.push(library);
+    Some(libraries.join(","))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pg_fetch::{PG_V12, PG_V16};
+
+    #[test]
+    fn statement_stats() {
+        assert!(statement_stats_query(&PG_V16).contains("s.total_exec_time, s.mean_exec_time"));
+        assert!(statement_stats_query(&PG_V12).contains("ORDER BY s.total_time DESC"));
+        assert_eq!(Duration::from_micros(1500), duration_from_millis(1.5));
+        assert_eq!(
+            Some("pg_stat_statements".to_string()),
+            add_preload_library(None, PG_STAT_STATEMENTS)
+        );
+        assert_eq!(
+            Some("auto_explain,pg_stat_statements".to_string()),
+            add_preload_library(Some("auto_explain, "), PG_STAT_STATEMENTS)
+        );
+        assert_eq!(
+            None,
+            add_preload_library(Some("pg_stat_statements"), PG_STAT_STATEMENTS)
+        );
+    }
+}
@@ -0,0 +1,116 @@
+//!
+//! Status reporting
+//!
+//! Push based reporting of the setup, start and stop phases, e.g. to render progress bars
+//!
+use std::sync::Arc;
+
+use tokio::sync::mpsc::{self, UnboundedReceiver};
+
+///
+/// Lifecycle phase
+///
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum PgPhase {
+    /// Downloading postgresql binaries
+    Downloading,
+    /// Unpacking postgresql binaries
+    Unpacking,
+    /// Creating the database cluster
+    Initializing,
+    /// Starting the postgresql server
+    Starting,
+    /// Postgresql server is running
+    Running,
+    /// Stopping the postgresql server
+    Stopping,
+    /// Postgresql server is stopped
+    Stopped,
+    /// Postgresql server processes exited unexpectedly
+    Crashed,
+}
+
+impl std::fmt::Display for PgPhase {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let s = match self {
+            PgPhase::Downloading => "downloading",
+            PgPhase::Unpacking => "unpacking",
+            PgPhase::Initializing => "initializing",
+            PgPhase::Starting => "starting",
+            PgPhase::Running => "running",
+            PgPhase::Stopping => "stopping",
+            PgPhase::Stopped => "stopped",
+            PgPhase::Crashed => "crashed",
+        };
+        write!(f, "{s}")
+    }
+}
+
+///
+/// Status report
+///
+#[derive(Debug, Clone, PartialEq)]
+pub struct PgStatusReport {
+    /// current phase
+    pub phase: PgPhase,
+    /// progress of the phase in percent, if known
+    pub percent: Option<f32>,
+    /// human readable message
+    pub message: String,
+}
+
+///
+/// Receiver of status reports
+///
+/// Implemented for closures taking a [PgStatusReport].
+///
+pub trait StatusSink: Send + Sync {
+    /// receive a status report
+    fn report(&self, report: PgStatusReport);
+}
+
+impl<F> StatusSink for F
+where
+    F: Fn(PgStatusReport) + Send + Sync,
+{
+    fn report(&self, report: PgStatusReport) {
+        self(report)
+    }
+}
+
+///
+/// Shared status sink
+///
+pub type PgStatusSink = Arc<dyn StatusSink>;
+
+///
+/// Create a status sink forwarding the reports into a channel
+///
+/// Lets the reports be consumed as a stream, e.g. to forward them to a UI event loop.
+/// Reports are dropped once the receiver is closed.
+///
+pub fn channel() -> (impl StatusSink, UnboundedReceiver<PgStatusReport>) {
+    let (sender, receiver) = mpsc::unbounded_channel();
+    let sink = move |report: PgStatusReport| {
+        let _ = sender.send(report);
+    };
+    (sink, receiver)
+}
+
+///
+/// Report to an optional status sink
+///
+pub(crate) fn report(
+    sink: Option<&PgStatusSink>,
+    phase: PgPhase,
+    percent: Option<f32>,
+    message: impl Into<String>,
+) {
+    if let Some(sink) = sink {
+        sink.report(PgStatusReport {
+            phase,
+            percent,
+            message: message.into(),
+        });
+    }
+}
@@ -0,0 +1,337 @@
+//!
+//! Process supervision
+//!
+//! Monitor a started server and restart it if its processes died (*e.g. killed by the OOM
+//! killer*)
+//!
+use std::path::PathBuf;
+use std::process::Stdio;
+use std::sync::Arc;
+use std::time::Duration;
+
+use log::{error, warn};
+use tokio::sync::{watch, Mutex};
+use tokio::task::{AbortHandle, JoinHandle};
+
+use crate::command_executor::AsyncCommand;
+use crate::pg_commands::PgCommand;
+use crate::pg_enums::PgServerStatus;
+use crate::pg_health;
+use crate::pg_hooks::{PgHookContext, PgHooks, PgLifecycleEvent};
+use crate::pg_os_user::PgOsUser;
+use crate::pg_status::{self, PgPhase, PgStatusSink};
+use crate::pg_types::Timeouts;
+
+/// Exit code of `pg_ctl status` if the server is not running
+const PG_CTL_STATUS_NOT_RUNNING: i32 = 3;
+
+///
+/// Supervisor settings
+///
+#[derive(Debug, Clone, PartialEq)]
+pub struct PgSupervisorSettings {
+    /// interval between two checks of the server processes
+    pub check_interval: Duration,
+    /// restart the server after it crashed
+    pub auto_restart: bool,
+    /// maximum number of restarts
+    /// if set to None the server is restarted without a limit
+    pub max_restarts: Option<u32>,
+    /// delay before the first restart attempt, doubled after each failed attempt
+    pub initial_backoff: Duration,
+    /// upper bound of the delay between two restart attempts
+    pub max_backoff: Duration,
+}
+
+impl Default for PgSupervisorSettings {
+    fn default() -> Self {
+        PgSupervisorSettings {
+            check_interval: Duration::from_secs(1),
+            auto_restart: true,
+            max_restarts: Some(5),
+            initial_backoff: Duration::from_millis(500),
+            max_backoff: Duration::from_secs(30),
+        }
+    }
+}
+
+///
+/// Running supervisor, stops supervising when dropped
+///
+#[derive(Debug)]
+pub struct PgSupervisor {
+    task: JoinHandle<()>,
+}
+
+impl PgSupervisor {
+    ///
+    /// Stop supervising the server
+    ///
+    pub fn stop(self) {
+        self.task.abort();
+    }
+
+    ///
+    /// Handle stopping the supervisor independent of this value
+    ///
+    pub(crate) fn abort_handle(&self) -> AbortHandle {
+        self.task.abort_handle()
+    }
+}
+
+impl Drop for PgSupervisor {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+///
+/// Server being supervised
+///
+pub(crate) struct Supervised {
+    pub(crate) pg_ctl_exe: PathBuf,
+    pub(crate) database_dir: PathBuf,
+    pub(crate) host: String,
+    pub(crate) port: u16,
+    pub(crate) user: String,
+    pub(crate) timeouts: Timeouts,
+    pub(crate) os_user: Option<PgOsUser>,
+    pub(crate) server_status: Arc<Mutex<PgServerStatus>>,
+    pub(crate) status_sender: Arc<watch::Sender<PgServerStatus>>,
+    pub(crate) status_sink: Option<PgStatusSink>,
+    pub(crate) hooks: PgHooks,
+    pub(crate) hook_context: PgHookContext,
+}
+
+impl Supervised {
+    ///
+    /// Start supervising the server
+    ///
+    /// Only a server with the status [PgServerStatus::Started] is checked, stopping it through
+    /// [crate::postgres::PgEmbed] is not mistaken for a crash.
+    ///
+    pub(crate) fn spawn(self, settings: PgSupervisorSettings) -> PgSupervisor {
+        PgSupervisor {
+            task: tokio::spawn(self.supervise(settings)),
+        }
+    }
+
+    async fn supervise(self, settings: PgSupervisorSettings) {
+        let mut restarts = 0;
+        let mut backoff = settings.initial_backoff;
+        let mut restart_pending = false;
+        loop {
+            tokio::time::sleep(settings.check_interval).await;
+            if !restart_pending {
+                if *self.server_status.lock().await != PgServerStatus::Started
+                    || self.is_running().await
+                {
+                    continue;
+                }
+                {
+                    let mut server_status = self.server_status.lock().await;
+                    // stopped while checking
+                    if *server_status != PgServerStatus::Started {
+                        continue;
+                    }
+                    *server_status = PgServerStatus::Failure;
+                    self.status_sender.send_replace(PgServerStatus::Failure);
+                }
+                error!("Postgresql server processes exited unexpectedly");
+                self.report(PgPhase::Crashed, "Postgresql server crashed");
+                let context = PgHookContext {
+                    error: Some("postgresql server processes exited unexpectedly".to_string()),
+                    ..self.hook_context.clone()
+                };
+                self.hooks.invoke(PgLifecycleEvent::Failure, context).await;
+                restart_pending = settings.auto_restart;
+                if !restart_pending {
+                    continue;
+                }
+            }
+            if settings
+                .max_restarts
+                .is_some_and(|max_restarts| restarts >= max_restarts)
+            {
+                error!(
+                    "Postgresql server was restarted {} times, giving up",
+                    restarts
+                );
+                restart_pending = false;
+                continue;
+            }
+            tokio::time::sleep(backoff).await;
+            {
+                let mut server_status = self.server_status.lock().await;
+                // started or stopped in the meantime
+                if *server_status != PgServerStatus::Failure {
+                    restart_pending = false;
+                    continue;
+                }
+                *server_status = PgServerStatus::Starting;
+                self.status_sender.send_replace(PgServerStatus::Starting);
+            }
+            restarts += 1;
+            self.report(PgPhase::Starting, "Restarting postgresql server");
+            let result = self.start().await;
+            {
+                let mut server_status = self.server_status.lock().await;
+                match &result {
+                    Ok(status) => {
+                        *server_status = *status;
+                        self.status_sender.send_replace(*status);
+                        restart_pending = false;
+                        backoff = settings.initial_backoff;
+                        self.report(PgPhase::Running, "Postgresql server restarted");
+                    }
+                    Err(e) => {
+                        *server_status = PgServerStatus::Failure;
+                        self.status_sender.send_replace(PgServerStatus::Failure);
+                        backoff = (backoff * 2).min(settings.max_backoff);
+                        warn!("Failed to restart the postgresql server: {}", e);
+                    }
+                }
+            }
+            self.hooks
+                .invoke_for(PgLifecycleEvent::Start, self.hook_context.clone(), &result)
+                .await;
+        }
+    }
+
+    ///
+    /// Check if the server processes are running with `pg_ctl status`
+    ///
+    /// Errors running pg_ctl are not taken as a crash.
+    ///
+    async fn is_running(&self) -> bool {
+        let mut command = PgCommand::status_db_command(
+            &self.pg_ctl_exe,
+            &self.database_dir,
+            self.os_user.as_ref(),
+        );
+        let status = command
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .kill_on_drop(true)
+            .status()
+            .await;
+        !matches!(
+            status.map(|s| s.code()),
+            Ok(Some(PG_CTL_STATUS_NOT_RUNNING))
+        )
+    }
+
+    async fn start(&self) -> crate::pg_types::PgResult<PgServerStatus> {
+        let mut executor = PgCommand::start_db_executor(
+            &self.pg_ctl_exe,
+            &self.database_dir,
+            &self.port,
+            self.timeouts.ready,
+            self.os_user.as_ref(),
+        )?;
+        let status = executor.execute(self.timeouts.start_timeout()).await?;
+        let timeout = self
+            .timeouts
+            .ready
+            .or_else(|| self.timeouts.start_timeout());
+        pg_health::wait_until_accepting(&self.host, self.port, &self.user, timeout).await?;
+        Ok(status)
+    }
+
+    fn report(&self, phase: PgPhase, message: &str) {
+        pg_status::report(self.status_sink.as_ref(), phase, None, message);
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use crate::pg_status::PgStatusReport;
+
+    #[tokio::test]
+    async fn restart_crashed_server() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = std::env::temp_dir().join(format!("pg_embed_supervisor_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        // pg_ctl stand-in, the server is never running but always starts
+        let pg_ctl_exe = dir.join("pg_ctl");
+        std::fs::write(
+            &pg_ctl_exe,
+            "#!/bin/sh\ncase \"$*\" in *status*) exit 3;; esac\n",
+        )
+        .unwrap();
+        std::fs::set_permissions(&pg_ctl_exe, std::fs::Permissions::from_mode(0o755)).unwrap();
+        // server stand-in answering startup packets with an authentication request
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let accepted = Arc::new(std::sync::atomic::AtomicBool::new(false));
+        let server = tokio::spawn({
+            let accepted = accepted.clone();
+            async move {
+                use tokio::io::AsyncWriteExt;
+                while let Ok((mut stream, _)) = listener.accept().await {
+                    accepted.store(true, std::sync::atomic::Ordering::SeqCst);
+                    let _ = stream.write_all(b"R").await;
+                }
+            }
+        });
+
+        let (sender, mut receiver) = tokio::sync::mpsc::unbounded_channel();
+        let sink = move |report: PgStatusReport| {
+            let _ = sender.send(report);
+        };
+        let server_status = Arc::new(Mutex::new(PgServerStatus::Started));
+        let (status_sender, status_receiver) = watch::channel(PgServerStatus::Started);
+        let supervisor = Supervised {
+            pg_ctl_exe,
+            database_dir: dir.clone(),
+            host: "127.0.0.1".to_string(),
+            port,
+            user: "postgres".to_string(),
+            timeouts: Timeouts::default(),
+            os_user: None,
+            server_status: server_status.clone(),
+            status_sender: Arc::new(status_sender),
+            status_sink: Some(Arc::new(sink)),
+            hooks: PgHooks::default(),
+            hook_context: PgHookContext {
+                event: PgLifecycleEvent::Start,
+                db_uri: String::new(),
+                port: 5432,
+                error: None,
+            },
+        }
+        .spawn(PgSupervisorSettings {
+            check_interval: Duration::from_millis(10),
+            max_restarts: Some(1),
+            initial_backoff: Duration::from_millis(10),
+            ..Default::default()
+        });
+
+        let mut phases = Vec::new();
+        while phases.len() < 4 {
+            let phase = receiver.recv().await.unwrap().phase;
+            if phase == PgPhase::Running {
+                // restarted only once the server accepts connections
+                assert!(accepted.load(std::sync::atomic::Ordering::SeqCst));
+            }
+            phases.push(phase);
+        }
+        assert_eq!(
+            vec![
+                PgPhase::Crashed,
+                PgPhase::Starting,
+                PgPhase::Running,
+                PgPhase::Crashed
+            ],
+            phases
+        );
+        assert_eq!(PgServerStatus::Failure, *server_status.lock().await);
+        assert_eq!(PgServerStatus::Failure, *status_receiver.borrow());
+        supervisor.stop();
+        server.abort();
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
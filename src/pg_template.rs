@@ -0,0 +1,310 @@
+//!
+//! Database cluster templates
+//!
+//! initdb runs once per postgresql version and cluster settings into the cache directory, new
+//! database directories are clones of that template, see
+//! [crate::postgres::PgSettings::initdb_template]
+//!
+//! Snapshots of database directories are clones kept in the cache directory as well, see
+//! [crate::postgres::PgEmbed::snapshot]
+//!
+//! Directories are cloned copy-on-write where the filesystem supports it, otherwise copied.
+//! Hard links are never used, postgresql modifies its files in place, which would write through
+//! to the source directory.
+//!
+use std::path::{Path, PathBuf};
+
+use sha2::{Digest, Sha256};
+
+use crate::pg_commands::PgInitDbOptions;
+use crate::pg_enums::{PgAuthMethod, PgCloneMethod};
+use crate::pg_errors::PgEmbedError;
+use crate::pg_fetch::PostgresVersion;
+use crate::pg_types::PgResult;
+
+/// Directory of the templates inside the cache directory
+const TEMPLATES_DIR_NAME: &str = "templates";
+/// Directory of the snapshots inside the cache directory
+const SNAPSHOTS_DIR_NAME: &str = "snapshots";
+
+///
+/// Settings the database cluster created by initdb depends on
+///
+pub(crate) struct PgTemplateKey<'a> {
+    pub(crate) version: PostgresVersion,
+    pub(crate) user: &'a str,
+    pub(crate) auth_method: PgAuthMethod,
+    /// stored in the cluster, `None` if the authentication method needs no password
+    pub(crate) password: Option<&'a str>,
+    pub(crate) initdb_options: &'a PgInitDbOptions,
+}
+
+impl PgTemplateKey<'_> {
+    ///
+    /// Template directory of the settings inside `cache_dir`
+    ///
+    /// The directory name is a hash of the settings, so the password is not revealed.
+    ///
+    pub(crate) fn template_dir(&self, cache_dir: &Path) -> PathBuf {
+        let mut hasher = Sha256::new();
+        hasher.update(self.version.to_string());
+        for value in [
+            self.user,
+            self.auth_method.hba_method(),
+            self.password.unwrap_or_default(),
+        ]
+        .iter()
+        {
+            hasher.update([0]);
+            hasher.update(value);
+        }
+        for arg in self.initdb_options.args() {
+            hasher.update([0]);
+            hasher.update(arg.to_string_lossy().as_bytes());
+        }
+        let hash: String = hasher.finalize()[..16]
+            .iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect();
+        cache_dir.join(TEMPLATES_DIR_NAME).join(hash)
+    }
+}
+
+///
+/// Snapshots directory of the database directory `database_dir` inside `cache_dir`
+///
+/// Named after a hash of the absolute database directory path, so each database directory
+/// has snapshots of its own.
+///
+pub(crate) fn snapshots_dir(cache_dir: &Path, database_dir: &Path) -> PathBuf {
+    let database_dir = std::path::absolute(database_dir).unwrap_or_else(|_| database_dir.into());
+    let hash: String = Sha256::digest(database_dir.to_string_lossy().as_bytes())[..16]
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect();
+    cache_dir.join(SNAPSHOTS_DIR_NAME).join(hash)
+}
+
+///
+/// Move a created template from `staging_dir` to `template_dir`
+///
+/// If another process created the template in the meantime its template is kept.
+///
+pub(crate) fn commit(staging_dir: &Path, template_dir: &Path) -> PgResult<()> {
+    match std::fs::rename(staging_dir, template_dir) {
+        Ok(()) => Ok(()),
+        Err(_) if template_dir.join("PG_VERSION").exists() => std::fs::remove_dir_all(staging_dir)
+            .map_err(|e| PgEmbedError::PgCleanUpFailure {
+                path: staging_dir.to_path_buf(),
+                e,
+            }),
+        Err(e) => Err(PgEmbedError::WriteFileError {
+            path: template_dir.to_path_buf(),
+            e,
+        }),
+    }
+}
+
+///
+/// Clone the content of `source` into `target` recursively, keeping the permissions
+///
+/// postgresql refuses to start on a database directory accessible by other users.
+///
+/// Returns [PgCloneMethod::Reflink] if all files were cloned copy-on-write.
+///
+pub(crate) fn clone_dir(source: &Path, target: &Path) -> PgResult<PgCloneMethod> {
+    #[cfg(target_os = "macos")]
+    {
+        if clone_tree(source, target) {
+            return Ok(PgCloneMethod::Reflink);
+        }
+    }
+    let mut reflink = cfg!(target_os = "linux");
+    copy_dir(source, target, &mut reflink)?;
+    Ok(if reflink {
+        PgCloneMethod::Reflink
+    } else {
+        PgCloneMethod::Copy
+    })
+}
+
+///
+/// Clone a whole directory tree with `clonefile`, which creates `target` itself
+///
+/// Returns `false` if the filesystem does not support it or `target` is not empty.
+///
+#[cfg(target_os = "macos")]
+fn clone_tree(source: &Path, target: &Path) -> bool {
+    use std::ffi::CString;
+    use std::os::unix::ffi::OsStrExt;
+
+    match std::fs::remove_dir(target) {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => return false,
+        _ => {}
+    }
+    let source = CString::new(source.as_os_str().as_bytes());
+    let target = CString::new(target.as_os_str().as_bytes());
+    match (source, target) {
+        (Ok(source), Ok(target)) => unsafe {
+            libc::clonefile(source.as_ptr(), target.as_ptr(), 0) == 0
+        },
+        _ => false,
+    }
+}
+
+///
+/// Copy the content of `source` into `target` recursively
+///
+/// Files are reflinked while `reflink` is set, it is cleared once the filesystem does not
+/// support it.
+///
+fn copy_dir(source: &Path, target: &Path, reflink: &mut bool) -> PgResult<()> {
+    let read_error = |e| PgEmbedError::ReadFileError {
+        path: source.to_path_buf(),
+        e,
+    };
+    std::fs::create_dir_all(target).map_err(|e| PgEmbedError::DirCreationError {
+        dir: target.to_path_buf(),
+        e,
+    })?;
+    for entry in std::fs::read_dir(source).map_err(read_error)? {
+        let entry = entry.map_err(read_error)?;
+        let target_path = target.join(entry.file_name());
+        if entry.file_type().map_err(read_error)?.is_dir() {
+            copy_dir(&entry.path(), &target_path, reflink)?;
+        } else {
+            copy_file(&entry.path(), &target_path, reflink).map_err(|e| {
+                PgEmbedError::WriteFileError {
+                    path: target_path.clone(),
+                    e,
+                }
+            })?;
+        }
+    }
+    let permissions = std::fs::metadata(source).map_err(read_error)?.permissions();
+    std::fs::set_permissions(target, permissions).map_err(|e| PgEmbedError::WriteFileError {
+        path: target.to_path_buf(),
+        e,
+    })
+}
+
+///
+/// Copy a file, as a reflink while `reflink` is set
+///
+fn copy_file(source: &Path, target: &Path, reflink: &mut bool) -> std::io::Result<()> {
+    if *reflink {
+        match reflink_file(source, target) {
+            Ok(()) => return Ok(()),
+            Err(e) if reflink_unsupported(&e) => {
+                log::debug!("Reflinks are not supported for {}: {}", target.display(), e);
+                *reflink = false;
+            }
+            Err(e) => return Err(e),
+        }
+    }
+    std::fs::copy(source, target).map(|_| ())
+}
+
+///
+/// Clone a file copy-on-write with the `FICLONE` ioctl
+///
+#[cfg(target_os = "linux")]
+fn reflink_file(source: &Path, target: &Path) -> std::io::Result<()> {
+    use std::os::unix::io::AsRawFd;
+
+    let source_file = std::fs::File::open(source)?;
+    let permissions = source_file.metadata()?.permissions();
+    let target_file = std::fs::File::create(target)?;
+    let result = unsafe {
+        libc::ioctl(
+            target_file.as_raw_fd(),
+            libc::FICLONE,
+            source_file.as_raw_fd(),
+        )
+    };
+    if result == 0 {
+        return target_file.set_permissions(permissions);
+    }
+    let error = std::io::Error::last_os_error();
+    drop(target_file);
+    let _ = std::fs::remove_file(target);
+    Err(error)
+}
+
+///
+/// Clone a file copy-on-write, not available on this platform
+///
+#[cfg(not(target_os = "linux"))]
+fn reflink_file(_source: &Path, _target: &Path) -> std::io::Result<()> {
+    Err(std::io::ErrorKind::Unsupported.into())
+}
+
+///
+/// Check if a reflink failed because the filesystem does not support it
+///
+#[cfg(target_os = "linux")]
+fn reflink_unsupported(error: &std::io::Error) -> bool {
+    // EXDEV: source and target on different filesystems, EINVAL/ENOTTY: not implemented
+    [libc::EOPNOTSUPP, libc::EXDEV, libc::EINVAL, libc::ENOTTY]
+        .iter()
+        .any(|code| error.raw_os_error() == Some(*code))
+}
+
+///
+/// Check if a reflink failed because the filesystem does not support it
+///
+#[cfg(not(target_os = "linux"))]
+fn reflink_unsupported(error: &std::io::Error) -> bool {
+    error.kind() == std::io::ErrorKind::Unsupported
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pg_fetch::{PG_V15, PG_V16};
+
+    #[test]
+    fn template_dirs() -> Result<(), PgEmbedError> {
+        let dir = std::env::temp_dir().join(format!("pg_embed_template_{}", std::process::id()));
+        let options = PgInitDbOptions::default();
+        let key = PgTemplateKey {
+            version: PG_V16,
+            user: "postgres",
+            auth_method: PgAuthMethod::MD5,
+            password: Some("password"),
+            initdb_options: &options,
+        };
+        let template_dir = key.template_dir(&dir);
+        assert_eq!(dir.join(TEMPLATES_DIR_NAME), template_dir.parent().unwrap());
+        assert_eq!(template_dir, key.template_dir(&dir));
+        let other_password = PgTemplateKey {
+            password: Some("other"),
+            ..key
+        };
+        assert_ne!(template_dir, other_password.template_dir(&dir));
+        let other_version = PgTemplateKey {
+            version: PG_V15,
+            ..key
+        };
+        assert_ne!(template_dir, other_version.template_dir(&dir));
+
+        let staging_dir = dir.join("staging");
+        std::fs::create_dir_all(staging_dir.join("global")).unwrap();
+        std::fs::write(staging_dir.join("PG_VERSION"), "16\n").unwrap();
+        std::fs::write(staging_dir.join("global").join("pg_control"), "control").unwrap();
+        std::fs::create_dir_all(template_dir.parent().unwrap()).unwrap();
+        commit(&staging_dir, &template_dir)?;
+        assert!(!staging_dir.exists());
+
+        let database_dir = dir.join("db");
+        let snapshots = snapshots_dir(&dir, &database_dir);
+        assert_eq!(dir.join(SNAPSHOTS_DIR_NAME), snapshots.parent().unwrap());
+        assert_eq!(snapshots, snapshots_dir(&dir, &dir.join(".").join("db")));
+        assert_ne!(snapshots, snapshots_dir(&dir, &dir.join("other")));
+        clone_dir(&template_dir, &database_dir)?;
+        let pg_control = std::fs::read_to_string(database_dir.join("global").join("pg_control"));
+        assert_eq!("control", pg_control.unwrap());
+        std::fs::remove_dir_all(&dir).unwrap();
+        Ok(())
+    }
+}
@@ -0,0 +1,225 @@
+//!
+//! TLS server mode
+//!
+//! Install the server certificate into the database cluster and enable `ssl`
+//!
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+use crate::pg_errors::PgEmbedError;
+use crate::pg_types::PgResult;
+
+/// Server certificate file inside the database directory
+const SERVER_CERT_FILE_NAME: &str = "server.crt";
+/// Server private key file inside the database directory
+const SERVER_KEY_FILE_NAME: &str = "server.key";
+
+///
+/// Source of the server certificate
+///
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
+pub enum PgTlsCertificate {
+    /// PEM encoded certificate (*chain*) and private key files
+    Files {
+        /// certificate file
+        cert_file: PathBuf,
+        /// private key file
+        key_file: PathBuf,
+    },
+    /// Self-signed certificate for `localhost`, `127.0.0.1` and `::1`
+    ///
+    /// Generated once when the database cluster is set up.
+    #[cfg(feature = "tls-self-signed")]
+    SelfSigned,
+}
+
+///
+/// TLS server settings
+///
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct PgTlsSettings {
+    /// the server certificate
+    pub certificate: PgTlsCertificate,
+    /// `sslmode` parameter added to the database uris
+    /// if set to None `require` is used
+    pub ssl_mode: Option<String>,
+}
+
+impl PgTlsSettings {
+    ///
+    /// `sslmode` of the database uris
+    ///
+    pub fn ssl_mode(&self) -> &str {
+        self.ssl_mode.as_deref().unwrap_or("require")
+    }
+
+    ///
+    /// Server parameters enabling TLS
+    ///
+    pub(crate) fn server_config(&self) -> HashMap<String, String> {
+        [
+            ("ssl", "on"),
+            ("ssl_cert_file", SERVER_CERT_FILE_NAME),
+            ("ssl_key_file", SERVER_KEY_FILE_NAME),
+        ]
+        .iter()
+        .map(|(name, value)| (name.to_string(), value.to_string()))
+        .collect()
+    }
+
+    ///
+    /// Install the certificate and private key into the database directory
+    ///
+    /// Provided files are copied on every call, a self-signed certificate is only generated
+    /// if the database directory has no certificate yet.
+    ///
+    /// Returns `Ok(())` on success, otherwise returns an error.
+    ///
+    pub(crate) fn install(&self, database_dir: &Path) -> PgResult<()> {
+        let cert_path = database_dir.join(SERVER_CERT_FILE_NAME);
+        let key_path = database_dir.join(SERVER_KEY_FILE_NAME);
+        let (cert, key) = match &self.certificate {
+            PgTlsCertificate::Files {
+                cert_file,
+                key_file,
+            } => (read_file(cert_file)?, read_file(key_file)?),
+            #[cfg(feature = "tls-self-signed")]
+            PgTlsCertificate::SelfSigned => {
+                if cert_path.exists() && key_path.exists() {
+                    return Ok(());
+                }
+                self_signed::generate()?
+            }
+        };
+        write_file(&cert_path, &cert, false)?;
+        write_file(&key_path, &key, true)
+    }
+}
+
+fn read_file(path: &Path) -> PgResult<Vec<u8>> {
+    std::fs::read(path).map_err(|e| PgEmbedError::ReadFileError {
+        path: path.to_path_buf(),
+        e,
+    })
+}
+
+///
+/// Write a file, private files are only accessible by the owner
+///
+/// Postgresql refuses to start if the private key is readable by other users.
+///
+fn write_file(path: &Path, content: &[u8], private: bool) -> PgResult<()> {
+    let write_error = |e| PgEmbedError::WriteFileError {
+        path: path.to_path_buf(),
+        e,
+    };
+    std::fs::write(path, content).map_err(write_error)?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        let mode = if private { 0o600 } else { 0o644 };
+        std::fs::set_permissions(path, std::fs::Permissions::from_mode(mode))
+            .map_err(write_error)?;
+    }
+    #[cfg(not(unix))]
+    let _ = private;
+    Ok(())
+}
+
+#[cfg(feature = "tls-self-signed")]
+mod self_signed {
+    use rcgen::{CertificateParams, DistinguishedName, DnType, KeyPair};
+
+    use crate::pg_errors::PgEmbedError;
+    use crate::pg_types::PgResult;
+
+    ///
+    /// Generate a self-signed certificate and its private key, both PEM encoded
+    ///
+    pub(super) fn generate() -> PgResult<(Vec<u8>, Vec<u8>)> {
+        build().map_err(|e| PgEmbedError::PgError {
+            message: "failed to generate the self-signed server certificate".to_string(),
+            source: Box::new(e),
+        })
+    }
+
+    fn build() -> Result<(Vec<u8>, Vec<u8>), rcgen::Error> {
+        // ip addresses are added as ip subject alternative names
+        let mut params = CertificateParams::new(vec![
+            "localhost".to_string(),
+            "127.0.0.1".to_string(),
+            "::1".to_string(),
+        ])?;
+        let mut name = DistinguishedName::new();
+        name.push(DnType::CommonName, "localhost");
+        params.distinguished_name = name;
+        let key = KeyPair::generate()?;
+        let certificate = params.self_signed(&key)?;
+        Ok((
+            certificate.pem().into_bytes(),
+            key.serialize_pem().into_bytes(),
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn install_certificate_files() -> Result<(), PgEmbedError> {
+        let base = std::env::temp_dir().join(format!("pg_embed_tls_{}", std::process::id()));
+        let database_dir = base.join("db");
+        std::fs::create_dir_all(&database_dir).unwrap();
+        std::fs::write(base.join("cert.pem"), "certificate").unwrap();
+        std::fs::write(base.join("key.pem"), "key").unwrap();
+
+        let settings = PgTlsSettings {
+            certificate: PgTlsCertificate::Files {
+                cert_file: base.join("cert.pem"),
+                key_file: base.join("key.pem"),
+            },
+            ssl_mode: None,
+        };
+        settings.install(&database_dir)?;
+        assert_eq!(
+            "certificate",
+            std::fs::read_to_string(database_dir.join(SERVER_CERT_FILE_NAME)).unwrap()
+        );
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let metadata = std::fs::metadata(database_dir.join(SERVER_KEY_FILE_NAME)).unwrap();
+            assert_eq!(0o600, metadata.permissions().mode() & 0o777);
+        }
+        assert_eq!("require", settings.ssl_mode());
+        assert_eq!("on", settings.server_config()["ssl"]);
+        std::fs::remove_dir_all(&base).unwrap();
+        Ok(())
+    }
+
+    #[cfg(feature = "tls-self-signed")]
+    #[test]
+    fn install_self_signed_certificate() -> Result<(), PgEmbedError> {
+        let database_dir =
+            std::env::temp_dir().join(format!("pg_embed_tls_self_signed_{}", std::process::id()));
+        std::fs::create_dir_all(&database_dir).unwrap();
+        let settings = PgTlsSettings {
+            certificate: PgTlsCertificate::SelfSigned,
+            ssl_mode: None,
+        };
+        settings.install(&database_dir)?;
+        let cert = std::fs::read(database_dir.join(SERVER_CERT_FILE_NAME)).unwrap();
+        assert!(cert.starts_with(b"-----BEGIN CERTIFICATE-----"));
+        settings.install(&database_dir)?;
+        assert_eq!(
+            cert,
+            std::fs::read(database_dir.join(SERVER_CERT_FILE_NAME)).unwrap()
+        );
+        std::fs::remove_dir_all(&database_dir).unwrap();
+        Ok(())
+    }
+}
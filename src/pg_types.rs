@@ -1,5 +1,184 @@
 use crate::pg_errors::PgEmbedError;
 use std::cell::Cell;
+use std::fmt;
+use std::time::Duration;
+use zeroize::Zeroize;
 
 pub type PgResult<T> = Result<T, PgEmbedError>;
 pub type PgCommandSync = Box<Cell<std::process::Command>>;
+
+///
+/// Timeouts
+///
+/// Every duration is optional, if set to `None` the related operation will not be timed out.
+/// The initdb, start and stop timeouts fall back to the process timeout if not set. The
+/// download timeouts apply to fetching the postgresql binaries, see
+/// [crate::pg_fetch::PgFetchSettings::timeouts].
+///
+#[derive(Debug, Copy, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(default))]
+pub struct Timeouts {
+    /// duration to wait before terminating process execution
+    /// pg_ctl start/stop, initdb, pg_dump and pg_restore timeout
+    pub process: Option<Duration>,
+    /// duration pg_ctl waits for the started server to accept connections
+    pub ready: Option<Duration>,
+    /// initdb timeout, a cold cache may need longer than the other processes
+    pub init: Option<Duration>,
+    /// pg_ctl start timeout
+    pub start: Option<Duration>,
+    /// pg_ctl stop timeout
+    pub stop: Option<Duration>,
+    /// duration to wait for the connection to the repository host
+    pub download_connect: Option<Duration>,
+    /// duration to wait for the whole download (*connect, request and response body*)
+    pub download: Option<Duration>,
+}
+
+impl Timeouts {
+    /// The initdb timeout
+    pub fn init_timeout(&self) -> Option<Duration> {
+        self.init.or(self.process)
+    }
+
+    /// The pg_ctl start timeout
+    pub fn start_timeout(&self) -> Option<Duration> {
+        self.start.or(self.process)
+    }
+
+    /// The pg_ctl stop timeout
+    pub fn stop_timeout(&self) -> Option<Duration> {
+        self.stop.or(self.process)
+    }
+}
+
+impl Default for Timeouts {
+    fn default() -> Self {
+        Timeouts {
+            process: Some(Duration::from_secs(15)),
+            ready: None,
+            init: None,
+            start: None,
+            stop: None,
+            download_connect: Some(Duration::from_secs(30)),
+            download: Some(Duration::from_secs(600)),
+        }
+    }
+}
+
+///
+/// Secret string, e.g. a password
+///
+/// The value is redacted from `Debug` and `Display` output and overwritten when dropped.
+/// Use [SecretString::expose_secret] to access it. Serialization writes the plain value.
+///
+#[derive(Clone, PartialEq, Eq, Default)]
+pub struct SecretString(String);
+
+impl SecretString {
+    ///
+    /// Create a new secret
+    ///
+    pub fn new(secret: impl Into<String>) -> Self {
+        SecretString(secret.into())
+    }
+
+    ///
+    /// The secret value
+    ///
+    pub fn expose_secret(&self) -> &str {
+        &self.0
+    }
+}
+
+impl From<String> for SecretString {
+    fn from(secret: String) -> Self {
+        SecretString(secret)
+    }
+}
+
+impl From<&str> for SecretString {
+    fn from(secret: &str) -> Self {
+        SecretString(secret.to_string())
+    }
+}
+
+impl fmt::Debug for SecretString {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("SecretString(***)")
+    }
+}
+
+impl fmt::Display for SecretString {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("***")
+    }
+}
+
+#[cfg(feature = "serde")]
+impl serde::Serialize for SecretString {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.expose_secret())
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for SecretString {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        String::deserialize(deserializer).map(SecretString)
+    }
+}
+
+///
+/// Implement serde (de)serialization of a type as its `Display` and `FromStr` string
+///
+#[cfg(feature = "serde")]
+macro_rules! serde_as_string {
+    ($type:ty) => {
+        impl serde::Serialize for $type {
+            fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+                serializer.collect_str(self)
+            }
+        }
+
+        impl<'de> serde::Deserialize<'de> for $type {
+            fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+                let s = String::deserialize(deserializer)?;
+                s.parse().map_err(serde::de::Error::custom)
+            }
+        }
+    };
+}
+#[cfg(feature = "serde")]
+pub(crate) use serde_as_string;
+
+impl Drop for SecretString {
+    fn drop(&mut self) {
+        self.0.zeroize();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn phase_timeouts() {
+        let timeouts = Timeouts {
+            init: Some(Duration::from_secs(60)),
+            ..Default::default()
+        };
+        assert_eq!(Some(Duration::from_secs(60)), timeouts.init_timeout());
+        assert_eq!(Some(Duration::from_secs(15)), timeouts.start_timeout());
+        assert_eq!(Some(Duration::from_secs(15)), timeouts.stop_timeout());
+    }
+
+    #[test]
+    fn secret_is_redacted() {
+        let secret = SecretString::from("hunter2");
+        assert_eq!("hunter2", secret.expose_secret());
+        assert_eq!("SecretString(***)", format!("{:?}", secret));
+        assert_eq!("***", secret.to_string());
+    }
+}
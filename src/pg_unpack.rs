@@ -2,115 +2,490 @@
 //! Unpack postgresql binaries
 //!
 use std::fs::File;
-use std::io::{BufReader, BufWriter};
+use std::io::{BufReader, Read};
 use std::path::{Path, PathBuf};
 use tar::Archive;
 use xz2::read::XzDecoder;
 use zip::ZipArchive;
 
 use crate::pg_errors::PgEmbedError;
+use crate::pg_retry;
+use crate::pg_status::{self, PgPhase, PgStatusSink};
 use crate::pg_types::PgResult;
 
 ///
-/// Unzip the postgresql txz file
+/// Top level directories extracted by a minimal install
 ///
-/// Returns `Ok(PathBuf(txz_file_path))` file path of the txz archive on success, otherwise returns an error.
+const MINIMAL_INSTALL_DIRS: [&str; 3] = ["bin", "lib", "share"];
+
 ///
-fn unzip_txz(zip_file_path: &PathBuf, cache_dir: &Path) -> Result<PathBuf, PgEmbedError> {
-    let zip_file = File::open(zip_file_path).map_err(|e| PgEmbedError::ReadFileError {
-        path: zip_file_path.clone(),
-        e,
-    })?;
-    let mut zip_archive = ZipArchive::new(zip_file).map_err(|e| PgEmbedError::UnzipFileError {
-        path: zip_file_path.clone(),
-        e,
-    })?;
-
-    for i in 0..zip_archive.len() {
-        let mut file = zip_archive
-            .by_index(i)
-            .map_err(|e| PgEmbedError::UnzipFileError {
-                path: zip_file_path.clone(),
-                e,
-            })?;
-        if file.name().ends_with(".txz") {
-            let txz_path = cache_dir.join(file.name());
-            let txz_file = File::create(&txz_path).map_err(|e| PgEmbedError::WriteFileError {
-                path: txz_path.clone(),
-                e,
-            })?;
-            std::io::copy(&mut file, &mut BufWriter::new(&txz_file)).map_err(|e| {
-                PgEmbedError::ReadFileError {
-                    path: zip_file_path.clone(),
-                    e,
-                }
-            })?;
-            return Ok(txz_path);
-        }
-    }
-    Err(PgEmbedError::InvalidPgPackage)
+/// Check if an archive entry is part of a minimal install
+///
+/// Only the executables, shared libraries and runtime data are needed,
+/// headers, documentation and static libraries are skipped.
+///
+fn included_in_minimal_install(path: &Path) -> bool {
+    // ignore leading `./` components
+    let path: PathBuf = path
+        .components()
+        .filter(|component| matches!(component, std::path::Component::Normal(_)))
+        .collect();
+    let top_level = path.iter().next().and_then(|name| name.to_str());
+    let static_lib = path.extension().is_some_and(|extension| extension == "a");
+    let docs = path.starts_with("share/doc");
+    top_level.is_some_and(|dir| MINIMAL_INSTALL_DIRS.contains(&dir)) && !static_lib && !docs
 }
 
 ///
-/// Decompress the postgresql txz file
+/// Tar archive restoring the mode bits, modification times and symbolic links of the entries
 ///
-/// Returns `Ok(PathBuf(tar_file_path))` (*the file path to the postgresql tar file*) on success, otherwise returns an error.
+/// The shared libraries are linked by versioned symbolic links (*e.g. `libpq.so.5` to
+/// `libpq.so.5.16`*) and the executables need their mode bits, otherwise `initdb` fails.
 ///
-fn decompress_xz(zip_file_path: &PathBuf) -> Result<PathBuf, PgEmbedError> {
-    let xz_file = File::open(zip_file_path).map_err(|e| PgEmbedError::ReadFileError {
-        path: zip_file_path.clone(),
-        e,
-    })?;
-    let xz_decoder = XzDecoder::new(xz_file);
-    let target_path = zip_file_path.with_extension("tar");
-    let tar_file = File::create(&target_path).map_err(|e| PgEmbedError::WriteFileError {
-        path: target_path.clone(),
-        e,
-    })?;
-    std::io::copy(
-        &mut BufReader::new(xz_decoder),
-        &mut BufWriter::new(&tar_file),
-    )
-    .map_err(|e| PgEmbedError::WriteFileError {
-        path: target_path.clone(),
-        e,
-    })?;
-    Ok(target_path)
+fn tar_archive<R: Read>(reader: R) -> Archive<R> {
+    let mut archive = Archive::new(reader);
+    archive.set_preserve_permissions(true);
+    archive.set_preserve_mtime(true);
+    archive.set_unpack_xattrs(false);
+    archive.set_overwrite(true);
+    archive
 }
 
 ///
-/// Unpack the postgresql tar file
+/// Unpack the postgresql tar archive read from `reader`
+///
+/// With `minimal_install` only the entries needed to run postgresql are unpacked.
 ///
 /// Returns `Ok(())` on success, otherwise returns an error.
 ///
-fn decompress_tar(file_path: &PathBuf, cache_dir: &PathBuf) -> Result<(), PgEmbedError> {
-    let tar_file = File::open(file_path).map_err(|e| PgEmbedError::ReadFileError {
-        path: file_path.clone(),
-        e,
-    })?;
-    let mut archive = Archive::new(tar_file);
-    archive
-        .unpack(cache_dir)
-        .map_err(PgEmbedError::UnpackFailure)?;
+fn unpack_tar<R: Read>(reader: R, cache_dir: &Path, minimal_install: bool) -> PgResult<()> {
+    let mut archive = tar_archive(reader);
+    if !minimal_install {
+        archive
+            .unpack(cache_dir)
+            .map_err(PgEmbedError::UnpackFailure)?;
+        return Ok(());
+    }
+    for entry in archive.entries().map_err(PgEmbedError::UnpackFailure)? {
+        let mut entry = entry.map_err(PgEmbedError::UnpackFailure)?;
+        let path = entry.path().map_err(PgEmbedError::UnpackFailure)?;
+        if included_in_minimal_install(&path) {
+            entry
+                .unpack_in(cache_dir)
+                .map_err(PgEmbedError::UnpackFailure)?;
+        }
+    }
     Ok(())
 }
 
+///
+/// Package formats of the postgresql binaries
+///
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum PgPackageFormat {
+    /// zip file containing a txz archive (*zonky layout*)
+    Zip,
+    /// xz compressed tar archive
+    TarXz,
+    /// gzip compressed tar archive
+    TarGz,
+    /// zstd compressed tar archive
+    TarZst,
+    /// plain tar archive
+    Tar,
+}
+
+impl PgPackageFormat {
+    ///
+    /// Number of leading bytes needed by [PgPackageFormat::detect]
+    ///
+    pub const HEADER_LEN: usize = 512;
+
+    ///
+    /// Detect the package format by the magic bytes of its header
+    ///
+    /// Returns `None` if the format is unknown.
+    ///
+    pub fn detect(header: &[u8]) -> Option<Self> {
+        if header.starts_with(b"PK\x03\x04") {
+            Some(PgPackageFormat::Zip)
+        } else if header.starts_with(&[0xfd, b'7', b'z', b'X', b'Z', 0x00]) {
+            Some(PgPackageFormat::TarXz)
+        } else if header.starts_with(&[0x1f, 0x8b]) {
+            Some(PgPackageFormat::TarGz)
+        } else if header.starts_with(&[0x28, 0xb5, 0x2f, 0xfd]) {
+            Some(PgPackageFormat::TarZst)
+        } else if header.get(257..262) == Some(b"ustar".as_ref()) {
+            Some(PgPackageFormat::Tar)
+        } else {
+            None
+        }
+    }
+}
+
 ///
 /// Unpack the postgresql executables
 ///
+/// The package format is detected by its content, see [PgPackageFormat].
+/// The package is decompressed and unpacked in a single pass, without intermediate files.
+/// With `minimal_install` headers, documentation and static libraries are skipped,
+/// reducing the size of the cache.
+///
 /// Returns `Ok(())` on success, otherwise returns an error.
 ///
-pub async fn unpack_postgres(zip_file_path: &PathBuf, cache_dir: &PathBuf) -> PgResult<()> {
-    let txz_file_path = unzip_txz(zip_file_path, cache_dir)?;
-    let tar_file_path = decompress_xz(&txz_file_path)?;
-    std::fs::remove_file(&txz_file_path).map_err(|e| PgEmbedError::PgCleanUpFailure {
-        path: txz_file_path,
+pub async fn unpack_postgres(
+    zip_file_path: &Path,
+    cache_dir: &Path,
+    minimal_install: bool,
+) -> PgResult<()> {
+    unpack_postgres_reporting(zip_file_path, cache_dir, minimal_install, None).await
+}
+
+///
+/// Unpack the postgresql executables, reporting the progress to `status_sink`
+///
+/// The progress is reported in whole percent of the package bytes read.
+///
+pub(crate) async fn unpack_postgres_reporting(
+    zip_file_path: &Path,
+    cache_dir: &Path,
+    minimal_install: bool,
+    status_sink: Option<PgStatusSink>,
+) -> PgResult<()> {
+    let zip_file_path = zip_file_path.to_path_buf();
+    let cache_dir = cache_dir.to_path_buf();
+    // decompression is cpu heavy, keep it off the async executor threads
+    tokio::task::spawn_blocking(move || {
+        pg_retry::retry_blocking("unpacking postgresql binaries", || {
+            unpack_package(
+                &zip_file_path,
+                &cache_dir,
+                minimal_install,
+                status_sink.as_ref(),
+            )
+        })
+    })
+    .await
+    .map_err(|e| PgEmbedError::PgError {
+        source: Box::new(e),
+        message: "unpack task failed".to_string(),
+    })?
+}
+
+///
+/// Unpack a package of any supported format, blocking the current thread
+///
+fn unpack_package(
+    package_path: &Path,
+    cache_dir: &Path,
+    minimal_install: bool,
+    status_sink: Option<&PgStatusSink>,
+) -> PgResult<()> {
+    let read_error = |e| PgEmbedError::ReadFileError {
+        path: package_path.to_path_buf(),
         e,
-    })?;
-    decompress_tar(&tar_file_path, cache_dir)?;
-    std::fs::remove_file(&tar_file_path).map_err(|e| PgEmbedError::PgCleanUpFailure {
-        path: tar_file_path,
+    };
+    let mut header = Vec::with_capacity(PgPackageFormat::HEADER_LEN);
+    File::open(package_path)
+        .and_then(|file| {
+            file.take(PgPackageFormat::HEADER_LEN as u64)
+                .read_to_end(&mut header)
+        })
+        .map_err(read_error)?;
+    let format = PgPackageFormat::detect(&header).ok_or(PgEmbedError::InvalidPgPackage)?;
+    log::debug!("Unpacking {} as {:?}", package_path.display(), format);
+    let file = File::open(package_path).map_err(read_error)?;
+    let total = file.metadata().map_err(read_error)?.len();
+    let file = BufReader::new(ProgressReader::new(file, total, status_sink));
+    match format {
+        PgPackageFormat::Zip => unpack_zip(file, package_path, cache_dir, minimal_install),
+        PgPackageFormat::TarXz => unpack_tar(XzDecoder::new(file), cache_dir, minimal_install),
+        PgPackageFormat::TarGz => unpack_tar(
+            flate2::read::GzDecoder::new(file),
+            cache_dir,
+            minimal_install,
+        ),
+        PgPackageFormat::TarZst => unpack_tar(
+            zstd::stream::read::Decoder::with_buffer(file).map_err(read_error)?,
+            cache_dir,
+            minimal_install,
+        ),
+        PgPackageFormat::Tar => unpack_tar(file, cache_dir, minimal_install),
+    }
+}
+
+///
+/// Unpack the txz archive contained in a zip file
+///
+fn unpack_zip<R: Read + std::io::Seek>(
+    zip_file: R,
+    zip_file_path: &Path,
+    cache_dir: &Path,
+    minimal_install: bool,
+) -> PgResult<()> {
+    let unzip_error = |e| PgEmbedError::UnzipFileError {
+        path: zip_file_path.to_path_buf(),
         e,
-    })?;
-    Ok(())
+    };
+    let mut zip_archive = ZipArchive::new(zip_file).map_err(unzip_error)?;
+    let txz_index = (0..zip_archive.len())
+        .find(|i| {
+            zip_archive
+                .name_for_index(*i)
+                .is_some_and(|name| name.ends_with(".txz"))
+        })
+        .ok_or(PgEmbedError::InvalidPgPackage)?;
+    let txz_file = zip_archive.by_index(txz_index).map_err(unzip_error)?;
+    unpack_tar(
+        XzDecoder::new(BufReader::new(txz_file)),
+        cache_dir,
+        minimal_install,
+    )
+}
+
+///
+/// Reader reporting the share of the package read so far
+///
+struct ProgressReader<'a, R> {
+    inner: R,
+    read: u64,
+    total: u64,
+    percent: Option<u32>,
+    status_sink: Option<&'a PgStatusSink>,
+}
+
+impl<'a, R: Read> ProgressReader<'a, R> {
+    fn new(inner: R, total: u64, status_sink: Option<&'a PgStatusSink>) -> Self {
+        ProgressReader {
+            inner,
+            read: 0,
+            total,
+            percent: None,
+            status_sink,
+        }
+    }
+}
+
+impl<R: Read> Read for ProgressReader<'_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.read += n as u64;
+        if self.status_sink.is_some() && self.total > 0 {
+            let percent = (self.read.min(self.total) * 100 / self.total) as u32;
+            if self.percent != Some(percent) {
+                self.percent = Some(percent);
+                pg_status::report(
+                    self.status_sink,
+                    PgPhase::Unpacking,
+                    Some(percent as f32),
+                    "Unpacking postgresql binaries",
+                );
+            }
+        }
+        Ok(n)
+    }
+}
+
+impl<R: std::io::Seek> std::io::Seek for ProgressReader<'_, R> {
+    fn seek(&mut self, pos: std::io::SeekFrom) -> std::io::Result<u64> {
+        self.inner.seek(pos)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    ///
+    /// Tar archive of a minimal installation
+    ///
+    #[cfg(unix)]
+    fn package_tar() -> Vec<u8> {
+        let mut tar = tar::Builder::new(Vec::new());
+        let mut append = |path: &str,
+                          entry_type: tar::EntryType,
+                          mode: u32,
+                          content: &[u8],
+                          link: Option<&str>| {
+            let mut header = tar::Header::new_gnu();
+            header.set_entry_type(entry_type);
+            header.set_mode(mode);
+            header.set_mtime(1_600_000_000);
+            header.set_size(content.len() as u64);
+            if let Some(link) = link {
+                header.set_link_name(link).unwrap();
+            }
+            header.set_cksum();
+            tar.append_data(&mut header, path, content).unwrap();
+        };
+        append(
+            "bin/initdb",
+            tar::EntryType::Regular,
+            0o755,
+            b"initdb",
+            None,
+        );
+        append(
+            "lib/libpq.so.5.16",
+            tar::EntryType::Regular,
+            0o644,
+            b"libpq",
+            None,
+        );
+        append(
+            "lib/libpq.so.5",
+            tar::EntryType::Symlink,
+            0o777,
+            b"",
+            Some("libpq.so.5.16"),
+        );
+        append(
+            "include/libpq-fe.h",
+            tar::EntryType::Regular,
+            0o644,
+            b"header",
+            None,
+        );
+        tar.into_inner().unwrap()
+    }
+
+    ///
+    /// Create a package in the zonky layout: a jar containing a txz with the installation
+    ///
+    #[cfg(unix)]
+    fn create_package(zip_path: &Path) {
+        let tar = package_tar();
+        let mut txz = Vec::new();
+        std::io::copy(&mut xz2::read::XzEncoder::new(tar.as_slice(), 1), &mut txz).unwrap();
+
+        let mut zip = zip::ZipWriter::new(File::create(zip_path).unwrap());
+        let options = zip::write::SimpleFileOptions::default()
+            .compression_method(zip::CompressionMethod::Stored);
+        zip.start_file("postgres-linux-x86_64.txz", options)
+            .unwrap();
+        std::io::Write::write_all(&mut zip, &txz).unwrap();
+        zip.finish().unwrap();
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn unpack_preserves_links_modes_and_mtimes() -> Result<(), PgEmbedError> {
+        use std::os::unix::fs::PermissionsExt;
+
+        let base = std::env::temp_dir().join(format!("pg_embed_unpack_{}", std::process::id()));
+        for minimal_install in [false, true] {
+            let cache_dir = base.join(format!("cache_{}", minimal_install));
+            std::fs::create_dir_all(&cache_dir).unwrap();
+            let zip_path = cache_dir.join("postgres.zip");
+            create_package(&zip_path);
+
+            unpack_postgres(&zip_path, &cache_dir, minimal_install).await?;
+
+            let initdb = std::fs::metadata(cache_dir.join("bin/initdb")).unwrap();
+            assert_eq!(0o755, initdb.permissions().mode() & 0o777);
+            let mtime = initdb.modified().unwrap();
+            assert_eq!(
+                1_600_000_000,
+                mtime
+                    .duration_since(std::time::UNIX_EPOCH)
+                    .unwrap()
+                    .as_secs()
+            );
+            let link = cache_dir.join("lib/libpq.so.5");
+            assert!(std::fs::symlink_metadata(&link)
+                .unwrap()
+                .file_type()
+                .is_symlink());
+            assert_eq!(
+                PathBuf::from("libpq.so.5.16"),
+                std::fs::read_link(&link).unwrap()
+            );
+            assert_eq!("libpq", std::fs::read_to_string(&link).unwrap());
+            assert_eq!(
+                !minimal_install,
+                cache_dir.join("include/libpq-fe.h").exists()
+            );
+        }
+        std::fs::remove_dir_all(&base).unwrap();
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn unpack_tar_zst_package() -> Result<(), PgEmbedError> {
+        let base = std::env::temp_dir().join(format!("pg_embed_unpack_zst_{}", std::process::id()));
+        std::fs::create_dir_all(&base).unwrap();
+        let package_path = base.join("postgres.tar.zst");
+        std::fs::write(
+            &package_path,
+            zstd::encode_all(package_tar().as_slice(), 1).unwrap(),
+        )
+        .unwrap();
+
+        let reports = std::sync::Arc::new(std::sync::Mutex::new(Vec::new()));
+        let sink_reports = reports.clone();
+        let sink: PgStatusSink = std::sync::Arc::new(move |report: pg_status::PgStatusReport| {
+            sink_reports.lock().unwrap().push(report)
+        });
+        unpack_postgres_reporting(&package_path, &base, false, Some(sink)).await?;
+        assert_eq!(
+            "initdb",
+            std::fs::read_to_string(base.join("bin/initdb")).unwrap()
+        );
+        let reports = reports.lock().unwrap();
+        assert!(reports
+            .iter()
+            .all(|report| report.phase == PgPhase::Unpacking));
+        assert_eq!(
+            Some(100.0),
+            reports.last().and_then(|report| report.percent)
+        );
+        std::fs::remove_dir_all(&base).unwrap();
+        Ok(())
+    }
+
+    #[test]
+    fn detect_package_format() {
+        let mut tar = vec![0u8; 512];
+        tar[257..262].copy_from_slice(b"ustar");
+        assert_eq!(
+            Some(PgPackageFormat::Zip),
+            PgPackageFormat::detect(b"PK\x03\x04rest")
+        );
+        assert_eq!(
+            Some(PgPackageFormat::TarXz),
+            PgPackageFormat::detect(&[0xfd, b'7', b'z', b'X', b'Z', 0x00, 0x00])
+        );
+        assert_eq!(
+            Some(PgPackageFormat::TarGz),
+            PgPackageFormat::detect(&[0x1f, 0x8b, 0x08])
+        );
+        assert_eq!(
+            Some(PgPackageFormat::TarZst),
+            PgPackageFormat::detect(&[0x28, 0xb5, 0x2f, 0xfd, 0x00])
+        );
+        assert_eq!(Some(PgPackageFormat::Tar), PgPackageFormat::detect(&tar));
+        assert_eq!(None, PgPackageFormat::detect(b"<html>"));
+    }
+
+    #[test]
+    fn minimal_install_entries() {
+        let included = [
+            "bin/initdb",
+            "./bin/pg_ctl",
+            "lib/libpq.so.5",
+            "share/postgresql/postgres.bki",
+        ];
+        for path in included {
+            assert!(included_in_minimal_install(Path::new(path)), "{}", path);
+        }
+        for path in [
+            "include/libpq-fe.h",
+            "lib/libpq.a",
+            "./share/doc/README",
+            "README",
+        ] {
+            assert!(!included_in_minimal_install(Path::new(path)), "{}", path);
+        }
+    }
 }
@@ -0,0 +1,332 @@
+//!
+//! pgvector
+//!
+//! [crate::postgres::PgEmbed::enable_pgvector] installs the pgvector extension into the cached
+//! binaries and creates it in a database:
+//!
+//! ```rust, ignore
+//! pg.enable_pgvector("app", &PgVectorSettings::default()).await?;
+//! pg.run_sql("app", "CREATE TABLE items (embedding vector(3))").await?;
+//! ```
+//!
+//! By default the source release is downloaded, checked against the sha256 hash pinned for
+//! [PGVECTOR_VERSION] and compiled with the flags of the bundled `pg_config`, which requires a
+//! c compiler (*`cc` or the `CC` environment variable*) and binaries acquired without
+//! [crate::pg_fetch::PgFetchSettings::minimal_install]. Set [PgVectorSettings::archive_url] to
+//! install a prebuilt archive instead.
+//!
+use std::path::{Path, PathBuf};
+
+use sha2::{Digest, Sha256};
+
+use crate::pg_errors::PgEmbedError;
+use crate::pg_fetch::PostgresVersion;
+use crate::pg_types::PgResult;
+
+/// pgvector release installed by default
+pub const PGVECTOR_VERSION: &str = "0.8.0";
+/// Hex encoded sha256 hashes of the source releases, by pgvector version
+const PGVECTOR_SOURCE_SHA256: &[(&str, &str)] = &[(
+    "0.8.0",
+    "867a2c328d4928a5a9d6f052cd3bc78c7d60228a9b914ad32aa3db88e9de27b0",
+)];
+/// Environment variable selecting the c compiler building pgvector
+const CC_ENV: &str = "CC";
+
+///
+/// Settings of the pgvector installation
+///
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PgVectorSettings {
+    /// pgvector release, e.g. `0.8.0`
+    pub version: String,
+    /// url of a prebuilt tar archive of pgvector, see [crate::pg_extension::PgExtensionInstaller]
+    /// supports the placeholders `{version}`, `{pg_major}` and `{platform}`
+    /// if set to None the source release is compiled
+    pub archive_url: Option<String>,
+    /// hex encoded sha256 hash of the downloaded archive
+    /// if set to None the hash pinned for the source release of `version` is checked
+    pub sha256: Option<String>,
+}
+
+impl Default for PgVectorSettings {
+    fn default() -> Self {
+        PgVectorSettings {
+            version: PGVECTOR_VERSION.to_string(),
+            archive_url: None,
+            sha256: None,
+        }
+    }
+}
+
+impl PgVectorSettings {
+    ///
+    /// Download url of the source release
+    ///
+    pub fn source_url(&self) -> String {
+        format!(
+            "https://github.com/pgvector/pgvector/archive/refs/tags/v{}.tar.gz",
+            self.version
+        )
+    }
+
+    ///
+    /// Download url of the prebuilt archive for postgresql `pg_version` on `platform`
+    ///
+    pub fn prebuilt_url(&self, pg_version: &PostgresVersion, platform: &str) -> Option<String> {
+        self.archive_url.as_ref().map(|url| {
+            url.replace("{version}", &self.version)
+                .replace("{pg_major}", &pg_version.major().to_string())
+                .replace("{platform}", platform)
+        })
+    }
+
+    ///
+    /// Hex encoded sha256 hash expected of the downloaded archive
+    ///
+    /// Falls back to the hash pinned for the source release unless a prebuilt archive is
+    /// installed.
+    ///
+    pub fn expected_sha256(&self) -> Option<String> {
+        self.sha256.clone().or_else(|| {
+            if self.archive_url.is_some() {
+                return None;
+            }
+            PGVECTOR_SOURCE_SHA256
+                .iter()
+                .find(|(version, _)| *version == self.version)
+                .map(|(_, sha256)| sha256.to_string())
+        })
+    }
+}
+
+///
+/// Check the downloaded archive `content` of `url` against the hex encoded sha256 hash
+/// `expected`
+///
+pub(crate) fn verify_sha256(url: &str, content: &[u8], expected: &str) -> PgResult<()> {
+    let actual: String = Sha256::digest(content)
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect();
+    if !actual.eq_ignore_ascii_case(expected) {
+        return Err(PgEmbedError::ChecksumMismatch {
+            url: url.to_string(),
+            expected: expected.to_string(),
+            actual,
+        });
+    }
+    Ok(())
+}
+
+///
+/// Compile the pgvector sources of the release archive unpacked into `source_dir` and lay
+/// out the extension files in `package_dir`
+///
+/// The compiler and preprocessor flags and the server headers are taken from the bundled
+/// `pg_config` of `cache_dir`.
+///
+pub(crate) fn build_package(
+    settings: &PgVectorSettings,
+    pg_version: &PostgresVersion,
+    cache_dir: &Path,
+    source_dir: &Path,
+    package_dir: &Path,
+) -> PgResult<()> {
+    let build_failure = |reason: String| PgEmbedError::ExtensionBuildFailure {
+        name: "vector".to_string(),
+        reason,
+    };
+    if cfg!(target_os = "windows") {
+        return Err(PgEmbedError::UnsupportedPlatform(
+            "building pgvector, set an archive url of a prebuilt pgvector".to_string(),
+        ));
+    }
+    let pg_config = cache_dir.join("bin").join("pg_config");
+    if !pg_config.is_file() {
+        return Err(build_failure(
+            "pg_config is skipped by a minimal install".to_string(),
+        ));
+    }
+    let include_dir = PathBuf::from(pg_config_value(&pg_config, "--includedir-server")?);
+    if !include_dir.join("postgres.h").is_file() {
+        return Err(build_failure(
+            "the postgresql headers are skipped by a minimal install".to_string(),
+        ));
+    }
+    let mut flags = Vec::new();
+    for option in ["--cppflags", "--cflags", "--cflags_sl"] {
+        flags.extend(
+            pg_config_value(&pg_config, option)?
+                .split_whitespace()
+                .map(str::to_string),
+        );
+    }
+    // the release archive contains the single directory `pgvector-{version}`
+    let source_dir = read_dir_paths(source_dir)?
+        .into_iter()
+        .find(|path| path.is_dir())
+        .ok_or_else(|| build_failure("the source archive is empty".to_string()))?;
+    let mut sources = read_dir_paths(&source_dir.join("src"))?;
+    sources.retain(|path| path.extension().is_some_and(|extension| extension == "c"));
+
+    std::fs::create_dir_all(package_dir).map_err(|e| PgEmbedError::DirCreationError {
+        dir: package_dir.to_path_buf(),
+        e,
+    })?;
+    let library = package_dir.join(format!("vector{}", library_suffix(pg_version)));
+    let compiler = std::env::var(CC_ENV).unwrap_or_else(|_| "cc".to_string());
+    let mut command = std::process::Command::new(&compiler);
+    command.args(&flags).arg("-I").arg(&include_dir);
+    // linking a loadable module is not covered by the flags of pg_config
+    if cfg!(target_os = "macos") {
+        command.args(["-bundle", "-undefined", "dynamic_lookup"]);
+    } else {
+        command.arg("-shared");
+    }
+    command.args(&sources).arg("-o").arg(&library);
+    if cfg!(not(target_os = "macos")) {
+        command.arg("-lm");
+    }
+    let output = command
+        .output()
+        .map_err(|e| build_failure(format!("failed to run {}: {}", compiler, e)))?;
+    if !output.status.success() {
+        return Err(build_failure(
+            String::from_utf8_lossy(&output.stderr).trim().to_string(),
+        ));
+    }
+
+    // the install script of the release is generated from `sql/vector.sql`
+    let copy = |from: PathBuf, to: PathBuf| {
+        std::fs::copy(&from, &to).map_err(|e| PgEmbedError::WriteFileError { path: to, e })
+    };
+    copy(
+        source_dir.join("vector.control"),
+        package_dir.join("vector.control"),
+    )?;
+    copy(
+        source_dir.join("sql").join("vector.sql"),
+        package_dir.join(format!("vector--{}.sql", settings.version)),
+    )?;
+    for script in read_dir_paths(&source_dir.join("sql"))? {
+        let update_script = script
+            .file_name()
+            .and_then(|name| name.to_str())
+            .is_some_and(|name| name.starts_with("vector--") && name.ends_with(".sql"));
+        if update_script {
+            copy(
+                script.clone(),
+                package_dir.join(script.file_name().unwrap_or_default()),
+            )?;
+        }
+    }
+    Ok(())
+}
+
+///
+/// Value of the `option` of the bundled `pg_config`, e.g. `--cflags`
+///
+fn pg_config_value(pg_config: &Path, option: &str) -> PgResult<String> {
+    let build_failure = |reason: String| PgEmbedError::ExtensionBuildFailure {
+        name: "vector".to_string(),
+        reason,
+    };
+    let output = std::process::Command::new(pg_config)
+        .arg(option)
+        .output()
+        .map_err(|e| build_failure(format!("failed to run {}: {}", pg_config.display(), e)))?;
+    if !output.status.success() {
+        return Err(build_failure(format!(
+            "pg_config {} failed: {}",
+            option,
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
+
+///
+/// File suffix of loadable modules of postgresql `pg_version`
+///
+/// Postgresql 16 changed the suffix on macos from `.so` to `.dylib`.
+///
+fn library_suffix(pg_version: &PostgresVersion) -> &'static str {
+    if cfg!(target_os = "macos") && pg_version.major() >= 16 {
+        ".dylib"
+    } else {
+        ".so"
+    }
+}
+
+///
+/// Paths of the entries of `dir` sorted by name
+///
+fn read_dir_paths(dir: &Path) -> PgResult<Vec<PathBuf>> {
+    let read_error = |e| PgEmbedError::ReadFileError {
+        path: dir.to_path_buf(),
+        e,
+    };
+    let mut paths = std::fs::read_dir(dir)
+        .map_err(read_error)?
+        .map(|entry| entry.map(|entry| entry.path()))
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(read_error)?;
+    paths.sort();
+    Ok(paths)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pg_fetch::PG_V16;
+
+    #[test]
+    fn pgvector_urls() {
+        let settings = PgVectorSettings::default();
+        assert_eq!(
+            format!(
+                "https://github.com/pgvector/pgvector/archive/refs/tags/v{}.tar.gz",
+                PGVECTOR_VERSION
+            ),
+            settings.source_url()
+        );
+        assert_eq!(None, settings.prebuilt_url(&PG_V16, "linux-amd64"));
+        let url = "https://mirror/pgvector-{version}-pg{pg_major}-{platform}.tgz";
+        let settings = PgVectorSettings {
+            version: "0.7.4".to_string(),
+            archive_url: Some(url.to_string()),
+            sha256: None,
+        };
+        assert_eq!(
+            Some("https://mirror/pgvector-0.7.4-pg16-linux-amd64.tgz".to_string()),
+            settings.prebuilt_url(&PG_V16, "linux-amd64")
+        );
+    }
+
+    #[test]
+    fn pgvector_sha256() -> Result<(), PgEmbedError> {
+        let settings = PgVectorSettings::default();
+        assert!(settings.expected_sha256().is_some());
+        let settings = PgVectorSettings {
+            version: "0.0.1".to_string(),
+            ..PgVectorSettings::default()
+        };
+        assert_eq!(None, settings.expected_sha256());
+        let settings = PgVectorSettings {
+            archive_url: Some("https://mirror/pgvector.tgz".to_string()),
+            ..PgVectorSettings::default()
+        };
+        assert_eq!(None, settings.expected_sha256());
+
+        // sha256 of "abc"
+        let sha256 = "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad";
+        verify_sha256("url", b"abc", sha256)?;
+        verify_sha256("url", b"abc", &sha256.to_uppercase())?;
+        assert!(matches!(
+            verify_sha256("url", b"abd", sha256),
+            Err(PgEmbedError::ChecksumMismatch { .. })
+        ));
+        Ok(())
+    }
+}
@@ -0,0 +1,397 @@
+//!
+//! Test support
+//!
+//! One server shared by all tests of a test process, each test gets a database of its own, so
+//! the tests can run in parallel:
+//!
+//! ```rust, ignore
+//! #[tokio::test]
+//! async fn insert_user() -> Result<(), PgEmbedError> {
+//!     let database = pg_embed::test_support::shared_instance().await?;
+//!     let pool = PgPoolOptions::new().connect(database.uri()).await?;
+//!     ...
+//! }
+//! ```
+//!
+//! With the `macros` feature the `#[pg_embed::test]` attribute creates the database of a test,
+//! applies migrations and removes it afterwards:
+//!
+//! ```rust, ignore
+//! #[pg_embed::test(migrations = "migrations")]
+//! async fn insert_user(db: PgTestDatabase) -> Result<(), PgEmbedError> {
+//!     let pool = PgPoolOptions::new().connect(db.uri()).await?;
+//!     ...
+//! }
+//! ```
+//!
+//! The server is started on first use with the binaries of [PgFetchSettings::default], which
+//! can be changed with the `PG_EMBED_VERSION` environment variable. It is stopped and its files
+//! are removed when the test process exits, on windows it is killed and the files stay in the
+//! temporary directory.
+//!
+use std::future::Future;
+use std::ops::Deref;
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use futures::TryFutureExt;
+use sqlx_tokio::migrate::Migrator;
+use sqlx_tokio::{Connection, PgConnection};
+use tokio::sync::{OnceCell, OwnedSemaphorePermit, Semaphore};
+
+use crate::pg_errors::PgEmbedError;
+use crate::pg_fetch::PgFetchSettings;
+use crate::pg_signal::PgSignalCleanup;
+use crate::pg_types::PgResult;
+use crate::postgres::PgEmbed;
+
+/// Database the test databases are created from
+const TEMPLATE_DATABASE: &str = "pg_embed_template";
+
+/// Shared server, never dropped
+static SHARED_INSTANCE: OnceCell<PgEmbed> = OnceCell::const_new();
+
+/// Number of the next test database
+static NEXT_DATABASE: AtomicU64 = AtomicU64::new(0);
+
+lazy_static! {
+    ///
+    /// Stops the shared server when the process exits
+    ///
+    static ref EXIT_CLEANUP: Mutex<Option<PgSignalCleanup>> = Mutex::new(None);
+}
+
+///
+/// Database of a single test on the shared server
+///
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PgTestDatabase {
+    name: String,
+    uri: String,
+}
+
+impl PgTestDatabase {
+    ///
+    /// Database name
+    ///
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    ///
+    /// Database uri `postgres://{username}:{password}@{host}:{port}/{name}`
+    ///
+    pub fn uri(&self) -> &str {
+        &self.uri
+    }
+
+    ///
+    /// Apply the sql migration scripts of `migration_dir`
+    ///
+    pub async fn migrate(&self, migration_dir: &Path) -> PgResult<()> {
+        let migrator = Migrator::new(migration_dir).await?;
+        let mut conn = PgConnection::connect(&self.uri)
+            .map_err(PgEmbedError::SqlxError)
+            .await?;
+        migrator.run(&mut conn).await?;
+        conn.close().map_err(PgEmbedError::SqlxError).await
+    }
+
+    ///
+    /// Drop the database, all connections to it must be closed
+    ///
+    pub async fn remove(self) -> PgResult<()> {
+        match SHARED_INSTANCE.get() {
+            Some(pg) => pg.drop_database(&self.name).await,
+            None => Ok(()),
+        }
+    }
+}
+
+///
+/// Create a database for a test on the shared server, starting the server on first use
+///
+/// The database is a copy of an empty template database and is not dropped, it is removed
+/// together with the server.
+///
+/// Returns the database, or an error if the server could not be started or the database could
+/// not be created.
+///
+pub async fn shared_instance() -> PgResult<PgTestDatabase> {
+    let pg = shared_server().await?;
+    let name = format!(
+        "test_{}_{}",
+        std::process::id(),
+        NEXT_DATABASE.fetch_add(1, Ordering::Relaxed)
+    );
+    pg.create_database_from_template(&name, TEMPLATE_DATABASE)
+        .await?;
+    Ok(PgTestDatabase {
+        uri: pg.full_db_uri(&name),
+        name,
+    })
+}
+
+///
+/// Pool of test databases on the shared server, recycled after use
+///
+/// The databases are copies of a template prepared once, e.g. by applying the migrations.
+/// Each test leases a database, which is dropped and copied from the template again before it
+/// is leased out the next time. Leases wait while all databases are leased.
+///
+/// ```rust, ignore
+/// lazy_static! {
+///     static ref POOL: OnceCell<PgTestDatabasePool> = OnceCell::new();
+/// }
+///
+/// let pool = POOL
+///     .get_or_try_init(|| PgTestDatabasePool::new("app", 8, |template| async move {
+///         template.migrate(Path::new("migrations")).await
+///     }))
+///     .await?;
+/// let database = pool.lease().await?;
+/// let conn = PgConnection::connect(database.uri()).await?;
+/// ```
+///
+pub struct PgTestDatabasePool {
+    name: String,
+    size: usize,
+    databases: Arc<Mutex<PgPoolDatabases>>,
+    permits: Arc<Semaphore>,
+}
+
+///
+/// Names of the databases which are not leased
+///
+#[derive(Debug, Default)]
+struct PgPoolDatabases {
+    /// copies of the template
+    clean: Vec<String>,
+    /// used by a test
+    dirty: Vec<String>,
+}
+
+impl PgTestDatabasePool {
+    ///
+    /// Create a pool of `size` databases named `<name>_<n>`
+    ///
+    /// The template database `<name>_template` is passed to `prepare` unless it has been
+    /// prepared before, `prepare` must close its connections.
+    ///
+    /// Returns an error if the shared server could not be started or the databases could not
+    /// be created.
+    ///
+    pub async fn new<F, Fut>(name: &str, size: usize, prepare: F) -> PgResult<Self>
+    where
+        F: FnOnce(PgTestDatabase) -> Fut,
+        Fut: Future<Output = PgResult<()>>,
+    {
+        let pg = shared_server().await?;
+        let template = format!("{}_template", name);
+        let template_database = PgTestDatabase {
+            name: template.clone(),
+            uri: pg.full_db_uri(&template),
+        };
+        pg.create_template_database(&template, |_| prepare(template_database))
+            .await?;
+        let pool = PgTestDatabasePool {
+            name: name.to_string(),
+            size,
+            databases: Arc::new(Mutex::new(PgPoolDatabases::default())),
+            permits: Arc::new(Semaphore::new(size)),
+        };
+        for n in 0..size {
+            let name = format!("{}_{}", name, n);
+            pool.recycle(&name).await?;
+            pool.databases().clean.push(name);
+        }
+        Ok(pool)
+    }
+
+    ///
+    /// Number of databases
+    ///
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    ///
+    /// Lease a database, waiting until one is available
+    ///
+    /// The database is returned to the pool when the lease is dropped, connections to it must
+    /// be closed by then.
+    ///
+    pub async fn lease(&self) -> PgResult<PgTestDatabaseLease> {
+        let permit =
+            self.permits
+                .clone()
+                .acquire_owned()
+                .await
+                .map_err(|e| PgEmbedError::PgError {
+                    message: "failed to lease a test database".to_string(),
+                    source: Box::new(e),
+                })?;
+        let (name, dirty) = {
+            let mut databases = self.databases();
+            match databases.clean.pop() {
+                Some(name) => (name, false),
+                None => (
+                    databases
+                        .dirty
+                        .pop()
+                        .expect("a permit guarantees a free database"),
+                    true,
+                ),
+            }
+        };
+        if dirty {
+            if let Err(e) = self.recycle(&name).await {
+                self.databases().dirty.push(name);
+                return Err(e);
+            }
+        }
+        let pg = shared_server().await?;
+        Ok(PgTestDatabaseLease {
+            database: PgTestDatabase {
+                uri: pg.full_db_uri(&name),
+                name,
+            },
+            databases: self.databases.clone(),
+            _permit: permit,
+        })
+    }
+
+    ///
+    /// Drop the database `name` and copy it from the template again
+    ///
+    async fn recycle(&self, name: &str) -> PgResult<()> {
+        let pg = shared_server().await?;
+        // connections left open by the previous test are terminated
+        pg.drop_database_force(name).await?;
+        pg.create_database_from_template(name, &format!("{}_template", self.name))
+            .await
+    }
+
+    fn databases(&self) -> MutexGuard<'_, PgPoolDatabases> {
+        self.databases.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+///
+/// Database leased from a [PgTestDatabasePool], returned to the pool when dropped
+///
+pub struct PgTestDatabaseLease {
+    database: PgTestDatabase,
+    databases: Arc<Mutex<PgPoolDatabases>>,
+    /// released after the database was returned
+    _permit: OwnedSemaphorePermit,
+}
+
+impl Deref for PgTestDatabaseLease {
+    type Target = PgTestDatabase;
+
+    fn deref(&self) -> &PgTestDatabase {
+        &self.database
+    }
+}
+
+impl Drop for PgTestDatabaseLease {
+    fn drop(&mut self) {
+        let name = std::mem::take(&mut self.database.name);
+        self.databases
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .dirty
+            .push(name);
+    }
+}
+
+///
+/// Shared server, started on first use
+///
+async fn shared_server() -> PgResult<&'static PgEmbed> {
+    SHARED_INSTANCE.get_or_try_init(start_shared_instance).await
+}
+
+///
+/// Run a test of the `#[pg_embed::test]` attribute on a runtime of its own
+///
+/// Creates the database of the test, applies the migrations of `migration_dir` and removes the
+/// database if the test did not panic.
+///
+/// Panics if the database can not be created.
+///
+#[doc(hidden)]
+pub fn run_test<F, Fut>(migration_dir: Option<&str>, test: F) -> Fut::Output
+where
+    F: FnOnce(PgTestDatabase) -> Fut,
+    Fut: Future,
+{
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .expect("failed to build the test runtime");
+    runtime.block_on(async {
+        let database = shared_instance()
+            .await
+            .expect("failed to create the test database");
+        if let Some(migration_dir) = migration_dir {
+            database
+                .migrate(Path::new(migration_dir))
+                .await
+                .expect("failed to migrate the test database");
+        }
+        let output = test(database.clone()).await;
+        let name = database.name.clone();
+        if let Err(e) = database.remove().await {
+            log::warn!("Failed to remove the test database {}: {}", name, e);
+        }
+        output
+    })
+}
+
+///
+/// Start the shared server and create the template database
+///
+async fn start_shared_instance() -> PgResult<PgEmbed> {
+    let mut pg = PgEmbed::new_ephemeral(PgFetchSettings::default()).await?;
+    let server_tuning = std::mem::take(&mut pg.pg_settings.server_tuning);
+    pg.pg_settings.server_tuning = server_tuning.test_performance_preset();
+    pg.setup().await?;
+    pg.install_signal_handlers()?;
+    *EXIT_CLEANUP.lock().unwrap_or_else(|e| e.into_inner()) = Some(pg.signal_cleanup());
+    register_exit_cleanup();
+    pg.start_db().await?;
+    pg.create_template_database(TEMPLATE_DATABASE, |_| async { Ok(()) })
+        .await?;
+    Ok(pg)
+}
+
+///
+/// Run the cleanup of the shared server when the process exits
+///
+/// Statics are never dropped, `atexit` handlers run after the test harness returned.
+///
+#[cfg(unix)]
+fn register_exit_cleanup() {
+    extern "C" fn cleanup() {
+        let cleanup = EXIT_CLEANUP
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .take();
+        if let Some(cleanup) = cleanup {
+            cleanup.run();
+        }
+    }
+
+    if unsafe { libc::atexit(cleanup) } != 0 {
+        log::warn!("Failed to register the cleanup of the shared postgresql server");
+    }
+}
+
+///
+/// The job object of the orphan guard kills the shared server when the process exits
+///
+#[cfg(windows)]
+fn register_exit_cleanup() {}
@@ -1,3 +1,4 @@
+use std::collections::HashMap;
 use std::path::PathBuf;
 use std::time::Duration;
 
@@ -6,6 +7,7 @@ use env_logger::Env;
 use pg_embed::pg_enums::PgAuthMethod;
 use pg_embed::pg_errors::PgEmbedError;
 use pg_embed::pg_fetch::{PgFetchSettings, PG_V15};
+use pg_embed::pg_types::Timeouts;
 use pg_embed::postgres::{PgEmbed, PgSettings};
 
 pub async fn setup(
@@ -27,11 +29,29 @@ pub async fn setup(
         cache_dir: Some(cache_dir),
         port,
         user: "postgres".to_string(),
-        password: "password".to_string(),
+        password: "password".into(),
         auth_method: PgAuthMethod::MD5,
         persistent,
-        timeout: Some(Duration::from_secs(10)),
+        timeouts: Timeouts {
+            process: Some(Duration::from_secs(10)),
+            ..Default::default()
+        },
         migration_dir,
+        maintenance_db: None,
+        os_user: None,
+        server_config: HashMap::new(),
+        server_tuning: Default::default(),
+        listen_addresses: Default::default(),
+        initdb_options: Default::default(),
+        tls: None,
+        stale_pid_policy: Default::default(),
+        reinit_on_mismatch: false,
+        initdb_template: false,
+        bootstrap_sql: None,
+        roles: Vec::new(),
+        databases: Vec::new(),
+        wal_archiving: None,
+        ram_backed: false,
     };
     let fetch_settings = PgFetchSettings {
         version: PG_V15,
@@ -1,16 +1,25 @@
+use std::collections::HashMap;
+use std::panic::AssertUnwindSafe;
 use std::path::PathBuf;
 
 use futures::stream::StreamExt;
+use futures::FutureExt;
 use serial_test::serial;
 use tokio::sync::Mutex;
 
 use env_logger::Env;
 use pg_embed::pg_access::PgAccess;
+use pg_embed::pg_bench::{PgBenchDuration, PgBenchOptions};
+use pg_embed::pg_commands::PgBaseBackupOptions;
+use pg_embed::pg_config::PgWalArchiving;
 use pg_embed::pg_enums::{PgAuthMethod, PgServerStatus};
 use pg_embed::pg_errors::PgEmbedError;
 use pg_embed::pg_fetch::{PgFetchSettings, PG_V16};
+use pg_embed::pg_health::PgHealth;
+use pg_embed::pg_maintenance::PgVacuumOptions;
+use pg_embed::pg_types::Timeouts;
 use pg_embed::postgres::{PgEmbed, PgSettings};
-use std::time::Duration;
+use std::time::{Duration, SystemTime};
 
 #[path = "common.rs"]
 mod common;
@@ -23,18 +32,33 @@ async fn postgres_server_start_stop() -> Result<(), PgEmbedError> {
         let server_status = *pg.server_status.lock().await;
         assert_eq!(server_status, PgServerStatus::Initialized);
     }
+    let mut status = pg.subscribe_status();
 
     pg.start_db().await?;
+    assert!(status.has_changed().unwrap());
+    assert_eq!(PgServerStatus::Started, *status.borrow_and_update());
+    assert_eq!(PgServerStatus::Started, pg.status());
     {
         let server_status = *pg.server_status.lock().await;
         assert_eq!(server_status, PgServerStatus::Started);
     }
+    assert_eq!(PgHealth::Accepting, pg.is_healthy().await);
+    pg.pg_settings
+        .server_config
+        .insert("log_statement".to_string(), "all".to_string());
+    pg.reload_config().await?;
+    let server_process = pg.server_process()?.expect("server process");
+    assert_eq!(Some(server_process.pid()), pg.server_pid()?);
+    assert!(server_process.is_running());
 
     pg.stop_db().await?;
+    assert!(!server_process.is_running());
+    assert_eq!(None, pg.server_pid()?);
     {
         let server_status = *pg.server_status.lock().await;
         assert_eq!(server_status, PgServerStatus::Stopped);
     }
+    assert_eq!(PgHealth::NoResponse, pg.is_healthy().await);
 
     Ok(())
 }
@@ -42,15 +66,50 @@ async fn postgres_server_start_stop() -> Result<(), PgEmbedError> {
 #[tokio::test]
 #[serial]
 async fn postgres_server_drop() -> Result<(), PgEmbedError> {
-    let db_path = PathBuf::from(PathBuf::from("data_test").join("db"));
+    let db_path = PathBuf::from("data_test").join("db");
     {
         let mut pg = common::setup(5432, db_path.clone(), false, None).await?;
         pg.start_db().await?;
         let file_exists = PgAccess::pg_version_file_exists(&db_path).await?;
-        assert_eq!(true, file_exists);
+        assert!(file_exists);
     }
     let file_exists = PgAccess::pg_version_file_exists(&db_path).await?;
-    assert_eq!(false, file_exists);
+    assert!(!file_exists);
+    Ok(())
+}
+
+#[tokio::test]
+#[serial]
+async fn postgres_server_guard() -> Result<(), PgEmbedError> {
+    let db_path = PathBuf::from("data_test").join("db");
+    let mut pg = common::setup(5432, db_path.clone(), false, None).await?;
+    let mut server_process = None;
+    let result = AssertUnwindSafe(async {
+        let guard = pg.start_db_guarded().await.unwrap();
+        server_process = guard.server_process().unwrap();
+        panic!("failing test");
+    })
+    .catch_unwind()
+    .await;
+    assert!(result.is_err());
+    assert!(!server_process.expect("server process").is_running());
+    assert_eq!(PgServerStatus::Uninitialized, pg.status());
+    let file_exists = PgAccess::pg_version_file_exists(&db_path).await?;
+    assert!(!file_exists);
+    pg.shutdown().await
+}
+
+#[tokio::test]
+#[serial]
+async fn postgres_server_shutdown() -> Result<(), PgEmbedError> {
+    let db_path = PathBuf::from("data_test").join("db");
+    let mut pg = common::setup(5432, db_path.clone(), false, None).await?;
+    pg.start_db().await?;
+    let server_process = pg.server_process()?.expect("server process");
+    pg.shutdown().await?;
+    assert!(!server_process.is_running());
+    let file_exists = PgAccess::pg_version_file_exists(&db_path).await?;
+    assert!(!file_exists);
     Ok(())
 }
 
@@ -109,15 +168,15 @@ async fn postgres_server_persistent_true() -> Result<(), PgEmbedError> {
         database_dir.clone_from(&pg.pg_access.database_dir);
         pw_file_path.clone_from(&pg.pg_access.pw_file_path);
         let file_exists = PgAccess::pg_version_file_exists(&db_path).await?;
-        assert_eq!(true, file_exists);
+        assert!(file_exists);
     }
     let file_exists = PgAccess::pg_version_file_exists(&db_path).await?;
-    assert_eq!(true, file_exists);
+    assert!(file_exists);
 
     PgAccess::clean_up(database_dir, pw_file_path).await?;
 
     let file_exists = PgAccess::pg_version_file_exists(&db_path).await?;
-    assert_eq!(false, file_exists);
+    assert!(!file_exists);
 
     Ok(())
 }
@@ -129,10 +188,30 @@ async fn postgres_server_persistent_false() -> Result<(), PgEmbedError> {
     {
         let _pg = common::setup(5432, db_path.clone(), false, None).await?;
         let file_exists = PgAccess::pg_version_file_exists(&db_path).await?;
-        assert_eq!(true, file_exists);
+        assert!(file_exists);
     }
     let file_exists = PgAccess::pg_version_file_exists(&db_path).await?;
-    assert_eq!(false, file_exists);
+    assert!(!file_exists);
+
+    Ok(())
+}
+
+#[tokio::test]
+#[serial]
+async fn postgres_server_stale_postmaster() -> Result<(), PgEmbedError> {
+    let db_path = PathBuf::from("data_test").join("db");
+    let mut pg = common::setup(5432, db_path.clone(), true, None).await?;
+    pg.start_db().await?;
+    let stale_process = pg.server_process()?.expect("server process");
+    // a killed process neither stops the server nor removes the database files
+    pg.shutting_down = true;
+    drop(pg);
+
+    let mut pg = common::setup(5432, db_path, false, None).await?;
+    pg.start_db().await?;
+    assert!(!stale_process.is_running());
+    assert_ne!(Some(stale_process.pid()), pg.server_pid()?);
+    pg.stop_db().await?;
 
     Ok(())
 }
@@ -149,11 +228,29 @@ async fn postgres_server_timeout() -> Result<(), PgEmbedError> {
         cache_dir: None,
         port: 5432,
         user: "postgres".to_string(),
-        password: "password".to_string(),
+        password: "password".into(),
         auth_method: PgAuthMethod::MD5,
         persistent: false,
-        timeout: Some(Duration::from_secs(10)),
+        timeouts: Timeouts {
+            process: Some(Duration::from_secs(10)),
+            ..Default::default()
+        },
         migration_dir: None,
+        maintenance_db: None,
+        os_user: None,
+        server_config: HashMap::new(),
+        server_tuning: Default::default(),
+        listen_addresses: Default::default(),
+        initdb_options: Default::default(),
+        tls: None,
+        stale_pid_policy: Default::default(),
+        reinit_on_mismatch: false,
+        initdb_template: false,
+        bootstrap_sql: None,
+        roles: Vec::new(),
+        databases: Vec::new(),
+        wal_archiving: None,
+        ram_backed: false,
     };
     let fetch_settings = PgFetchSettings {
         version: PG_V16,
@@ -161,7 +258,7 @@ async fn postgres_server_timeout() -> Result<(), PgEmbedError> {
     };
     let mut pg = PgEmbed::new(pg_settings, fetch_settings).await?;
     let _ = pg.setup().await;
-    pg.pg_settings.timeout = Some(Duration::from_millis(10));
+    pg.pg_settings.timeouts.process = Some(Duration::from_millis(10));
     let res = pg.start_db().await.err().map(|e| e.to_string());
     assert_eq!(
         Some("timed out due to error: deadline has elapsed".to_string()),
@@ -170,3 +267,327 @@ async fn postgres_server_timeout() -> Result<(), PgEmbedError> {
 
     Ok(())
 }
+
+#[tokio::test]
+#[serial]
+async fn postgres_server_ephemeral() -> Result<(), PgEmbedError> {
+    let database_dir;
+    {
+        let fetch_settings = PgFetchSettings {
+            version: PG_V16,
+            ..Default::default()
+        };
+        let mut pg = PgEmbed::new_ephemeral(fetch_settings).await?;
+        database_dir = pg.pg_settings.database_dir.clone();
+        assert!(database_dir.starts_with(std::env::temp_dir()));
+        assert!(!pg.pg_settings.persistent);
+        pg.setup().await?;
+        pg.start_db().await?;
+        assert_eq!(PgServerStatus::Started, *pg.server_status.lock().await);
+    }
+    assert!(!database_dir.parent().unwrap().exists());
+    Ok(())
+}
+
+#[tokio::test]
+#[serial]
+async fn postgres_server_trust_auth() -> Result<(), PgEmbedError> {
+    let pg_settings = PgSettings::builder()
+        .database_dir(PathBuf::from("data_test").join("db"))
+        .cache_dir(PathBuf::from("data_test").join("cache"))
+        .auth_method(PgAuthMethod::Trust)
+        .build()?;
+    let port = pg_settings.port;
+    let fetch_settings = PgFetchSettings {
+        version: PG_V16,
+        ..Default::default()
+    };
+    let mut pg = PgEmbed::new(pg_settings, fetch_settings).await?;
+    assert_eq!(format!("postgres://postgres@localhost:{}", port), pg.db_uri);
+    pg.setup().await?;
+    assert!(!pg.pg_access.pw_file_path.exists());
+    pg.start_db().await?;
+    pg.stop_db().await?;
+    Ok(())
+}
+
+#[tokio::test]
+#[serial]
+async fn postgres_server_initdb_template() -> Result<(), PgEmbedError> {
+    let fetch_settings = PgFetchSettings {
+        version: PG_V16,
+        ..Default::default()
+    };
+    for name in ["db_template_1", "db_template_2"].iter() {
+        let pg_settings = PgSettings::builder()
+            .database_dir(PathBuf::from("data_test").join(name))
+            .cache_dir(PathBuf::from("data_test").join("cache"))
+            .password("password")
+            .initdb_template(true)
+            .build()?;
+        let mut pg = PgEmbed::new(pg_settings, fetch_settings.clone()).await?;
+        pg.setup().await?;
+        assert!(pg.pg_access.cache_dir.join("templates").exists());
+        pg.start_db().await?;
+        assert!(!pg.database_exists("test").await?);
+        pg.shutdown().await?;
+    }
+    Ok(())
+}
+
+#[tokio::test]
+#[serial]
+async fn postgres_server_run_sql() -> Result<(), PgEmbedError> {
+    let mut pg = common::setup(5432, PathBuf::from("data_test").join("db"), false, None).await?;
+    pg.start_db().await?;
+    pg.run_sql(
+        "postgres",
+        "CREATE TABLE users (id INTEGER); INSERT INTO users VALUES (1);",
+    )
+    .await?;
+    let script = PathBuf::from("data_test").join("setup.sql");
+    std::fs::write(
+        &script,
+        "INSERT INTO users VALUES (2);\nSELECT missing FROM users;\n",
+    )
+    .unwrap();
+    let result = pg.run_sql_file("postgres", &script).await;
+    assert!(matches!(result, Err(PgEmbedError::PgProcessFailure { .. })));
+    std::fs::remove_file(&script).unwrap();
+    pg.stop_db().await?;
+    Ok(())
+}
+
+#[tokio::test]
+#[serial]
+async fn postgres_server_run_tool() -> Result<(), PgEmbedError> {
+    let mut pg = common::setup(5432, PathBuf::from("data_test").join("db"), false, None).await?;
+    pg.start_db().await?;
+    let output = pg
+        .run_tool("pg_controldata", std::iter::empty::<&str>())
+        .await?;
+    assert!(String::from_utf8_lossy(&output.stdout).contains("in production"));
+    let result = pg.run_tool("missing_tool", ["--version"]).await;
+    assert!(matches!(result, Err(PgEmbedError::ToolNotFound(_))));
+    pg.stop_db().await?;
+    Ok(())
+}
+
+#[tokio::test]
+#[serial]
+async fn postgres_server_pgbench() -> Result<(), PgEmbedError> {
+    let mut pg = common::setup(5432, PathBuf::from("data_test").join("db"), false, None).await?;
+    pg.start_db().await?;
+    let options = PgBenchOptions {
+        clients: 2,
+        duration: PgBenchDuration::Transactions(50),
+        ..Default::default()
+    };
+    let result = pg.pgbench(&options).await?;
+    assert_eq!(100, result.transactions);
+    assert_eq!(0, result.failed_transactions);
+    assert!(result.tps > 0.0);
+    assert!(result.latency_average.is_some());
+
+    // the initialized tables are reused
+    let options = PgBenchOptions {
+        initialize: false,
+        duration: PgBenchDuration::Time(Duration::from_secs(1)),
+        ..Default::default()
+    };
+    assert!(pg.pgbench(&options).await?.transactions > 0);
+    pg.stop_db().await?;
+    Ok(())
+}
+
+#[tokio::test]
+#[serial]
+async fn postgres_server_maintenance() -> Result<(), PgEmbedError> {
+    let mut pg = common::setup(5432, PathBuf::from("data_test").join("db"), false, None).await?;
+    pg.start_db().await?;
+    pg.run_sql(
+        "postgres",
+        "CREATE TABLE maintained AS SELECT generate_series(1, 1000) AS id; \
+         CREATE INDEX maintained_id ON maintained (id); \
+         DELETE FROM maintained WHERE id % 2 = 0",
+    )
+    .await?;
+    let options = PgVacuumOptions {
+        full: true,
+        analyze: true,
+        tables: vec!["maintained".to_string()],
+        ..Default::default()
+    };
+    pg.vacuum("postgres", &options).await?;
+    pg.vacuum("postgres", &PgVacuumOptions::default()).await?;
+    pg.analyze("postgres").await?;
+    pg.reindex("postgres").await?;
+    // unknown tables fail the vacuum
+    let options = PgVacuumOptions {
+        tables: vec!["missing".to_string()],
+        ..Default::default()
+    };
+    assert!(pg.vacuum("postgres", &options).await.is_err());
+    pg.stop_db().await?;
+    Ok(())
+}
+
+#[tokio::test]
+#[serial]
+async fn postgres_server_base_backup() -> Result<(), PgEmbedError> {
+    let mut pg = common::setup(5432, PathBuf::from("data_test").join("db"), false, None).await?;
+    pg.start_db().await?;
+    pg.run_sql("postgres", "CREATE TABLE backed_up (id INTEGER)")
+        .await?;
+    let backup_dir = PathBuf::from("data_test").join("db_backup");
+    pg.base_backup(&backup_dir, &PgBaseBackupOptions::default())
+        .await?;
+    // the target directory must be empty
+    assert!(pg
+        .base_backup(&backup_dir, &PgBaseBackupOptions::default())
+        .await
+        .is_err());
+    pg.stop_db().await?;
+
+    // the plain format backup is started as it is
+    let mut restored = common::setup(5433, backup_dir, false, None).await?;
+    restored.start_db().await?;
+    restored
+        .run_sql("postgres", "SELECT * FROM backed_up")
+        .await?;
+    restored.stop_db().await?;
+    Ok(())
+}
+
+#[tokio::test]
+#[serial]
+async fn postgres_server_replica() -> Result<(), PgEmbedError> {
+    let mut pg = common::setup(5432, PathBuf::from("data_test").join("db"), false, None).await?;
+    pg.start_db().await?;
+    pg.run_sql("postgres", "CREATE TABLE before_backup (id INTEGER)")
+        .await?;
+    let replica_settings = PgSettings::builder()
+        .database_dir(PathBuf::from("data_test").join("db_replica"))
+        .cache_dir(PathBuf::from("data_test").join("cache"))
+        .port(5433)
+        .build()?;
+    let mut replica = pg.spawn_replica(replica_settings).await?;
+    replica
+        .run_sql("postgres", "SELECT * FROM before_backup")
+        .await?;
+    // standbys are read only
+    assert!(replica
+        .run_sql("postgres", "CREATE TABLE t (id INTEGER)")
+        .await
+        .is_err());
+
+    pg.run_sql("postgres", "CREATE TABLE streamed (id INTEGER)")
+        .await?;
+    let mut attempts = 0;
+    while replica
+        .run_sql("postgres", "SELECT * FROM streamed")
+        .await
+        .is_err()
+    {
+        attempts += 1;
+        assert!(attempts < 50, "the table was not replicated");
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    replica.stop_db().await?;
+    pg.stop_db().await?;
+    Ok(())
+}
+
+#[tokio::test]
+#[serial]
+async fn postgres_server_switchover() -> Result<(), PgEmbedError> {
+    let mut pg = common::setup(5432, PathBuf::from("data_test").join("db"), false, None).await?;
+    pg.start_db().await?;
+    let replica_settings = PgSettings::builder()
+        .database_dir(PathBuf::from("data_test").join("db_replica"))
+        .cache_dir(PathBuf::from("data_test").join("cache"))
+        .port(5433)
+        .build()?;
+    let mut replica = pg.spawn_replica(replica_settings).await?;
+    assert!(replica.is_standby().await?);
+    assert!(!pg.is_standby().await?);
+    assert!(matches!(
+        replica.switchover(&mut pg).await,
+        Err(PgEmbedError::PgNotStandby(5432))
+    ));
+
+    pg.switchover(&mut replica).await?;
+    assert!(!replica.is_standby().await?);
+    assert!(pg.is_standby().await?);
+    replica
+        .run_sql("postgres", "CREATE TABLE after_switchover (id INTEGER)")
+        .await?;
+    let mut attempts = 0;
+    while pg
+        .run_sql("postgres", "SELECT * FROM after_switchover")
+        .await
+        .is_err()
+    {
+        attempts += 1;
+        assert!(attempts < 50, "the table was not replicated");
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+
+    // and back
+    replica.switchover(&mut pg).await?;
+    assert!(!pg.is_standby().await?);
+    pg.stop_db().await?;
+    replica.stop_db().await?;
+    Ok(())
+}
+
+#[tokio::test]
+#[serial]
+async fn postgres_server_point_in_time_recovery() -> Result<(), PgEmbedError> {
+    let pg_settings = PgSettings::builder()
+        .database_dir(PathBuf::from("data_test").join("db"))
+        .cache_dir(PathBuf::from("data_test").join("cache"))
+        .password("password")
+        .wal_archiving(PgWalArchiving::default())
+        .build()?;
+    let fetch_settings = PgFetchSettings {
+        version: PG_V16,
+        ..Default::default()
+    };
+    let mut pg = PgEmbed::new(pg_settings, fetch_settings).await?;
+    pg.setup().await?;
+    pg.start_db().await?;
+    let backup_dir = PathBuf::from("data_test").join("db_backup");
+    pg.base_backup(&backup_dir, &PgBaseBackupOptions::default())
+        .await?;
+    pg.run_sql("postgres", "CREATE TABLE before_target (id INTEGER)")
+        .await?;
+    tokio::time::sleep(Duration::from_secs(1)).await;
+    let target_time = SystemTime::now();
+    tokio::time::sleep(Duration::from_secs(1)).await;
+    pg.run_sql("postgres", "CREATE TABLE after_target (id INTEGER)")
+        .await?;
+
+    let restored_settings = PgSettings::builder()
+        .database_dir(PathBuf::from("data_test").join("db_restored"))
+        .cache_dir(PathBuf::from("data_test").join("cache"))
+        .port(5433)
+        .build()?;
+    let mut restored = pg
+        .restore_to(&backup_dir, target_time, restored_settings)
+        .await?;
+    restored
+        .run_sql("postgres", "SELECT * FROM before_target")
+        .await?;
+    assert!(restored
+        .run_sql("postgres", "SELECT * FROM after_target")
+        .await
+        .is_err());
+    // the recovered server accepts writes
+    restored
+        .run_sql("postgres", "CREATE TABLE recovered (id INTEGER)")
+        .await?;
+    restored.stop_db().await?;
+    pg.stop_db().await?;
+    Ok(())
+}
@@ -0,0 +1,72 @@
+use sqlx_tokio::{Connection, PgConnection};
+
+use pg_embed::pg_errors::PgEmbedError;
+use pg_embed::test_support;
+
+#[tokio::test]
+async fn shared_instance_isolates_tests() -> Result<(), PgEmbedError> {
+    let database = test_support::shared_instance().await?;
+    let mut conn = PgConnection::connect(database.uri()).await?;
+    sqlx_tokio::query("CREATE TABLE isolated (id INTEGER)")
+        .execute(&mut conn)
+        .await?;
+    conn.close().await?;
+    Ok(())
+}
+
+#[tokio::test]
+async fn shared_instance_databases_are_distinct() -> Result<(), PgEmbedError> {
+    let first = test_support::shared_instance().await?;
+    let second = test_support::shared_instance().await?;
+    assert_ne!(first.name(), second.name());
+    let mut conn = PgConnection::connect(second.uri()).await?;
+    // the table of the other test is not visible
+    sqlx_tokio::query("CREATE TABLE isolated (id INTEGER)")
+        .execute(&mut conn)
+        .await?;
+    conn.close().await?;
+    Ok(())
+}
+
+#[cfg(feature = "macros")]
+#[pg_embed::test(migrations = "migration_test")]
+async fn test_attribute_applies_migrations(
+    db: test_support::PgTestDatabase,
+) -> Result<(), PgEmbedError> {
+    let mut conn = PgConnection::connect(db.uri()).await?;
+    sqlx_tokio::query("SELECT * FROM testing")
+        .execute(&mut conn)
+        .await?;
+    conn.close().await?;
+    Ok(())
+}
+
+#[tokio::test]
+async fn database_pool_recycles_databases() -> Result<(), PgEmbedError> {
+    let pool = test_support::PgTestDatabasePool::new("pool", 1, |template| async move {
+        template
+            .migrate(std::path::Path::new("migration_test"))
+            .await
+    })
+    .await?;
+    assert_eq!(1, pool.size());
+    let lease = pool.lease().await?;
+    let name = lease.name().to_string();
+    let mut conn = PgConnection::connect(lease.uri()).await?;
+    sqlx_tokio::query("INSERT INTO testing (description, done) VALUES ('leased', true)")
+        .execute(&mut conn)
+        .await?;
+    conn.close().await?;
+    drop(lease);
+
+    let lease = pool.lease().await?;
+    assert_eq!(name, lease.name());
+    let mut conn = PgConnection::connect(lease.uri()).await?;
+    // the rows of the previous lease are gone
+    let rows = sqlx_tokio::query("SELECT * FROM testing")
+        .fetch_all(&mut conn)
+        .await?;
+    assert!(rows.is_empty());
+    conn.close().await?;
+    Ok(())
+}